@@ -15,10 +15,16 @@
  *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use axum::{extract::Extension, response::Json, routing::get, Router};
+use axum::{
+    extract::Extension,
+    response::Json,
+    routing::{get, post},
+    Router,
+};
 use basic_axum_rate_limit::{
-    rate_limit_middleware, security_context_middleware, ActionChecker, OnBlocked, RateLimitConfig,
-    RateLimiter, RequestScreener, ScreeningConfig, SecurityContext,
+    action_limit_middleware, rate_limit_middleware, security_context_middleware, ActionChecker,
+    ActionLimit, OnBlocked, RateLimitConfig, RateLimiter, RequestScreener, ScreeningConfig,
+    SecurityContext,
 };
 use std::time::Duration;
 
@@ -67,12 +73,22 @@ async fn main() {
         .with_user_agent_patterns(vec!["zgrab".to_string(), "nuclei".to_string()]);
 
     let screener =
-        RequestScreener::new(&screening_config).expect("Failed to compile screening patterns");
+        RequestScreener::new(&screening_config).expect("Invalid screening configuration");
 
-    let rate_limiter = RateLimiter::new(config, SimpleCallbacks).with_screener(screener);
+    let rate_limiter = RateLimiter::new(config, SimpleCallbacks)
+        .with_screener(screener)
+        .with_action_checker(SimpleCallbacks);
 
     let app = Router::new()
         .route("/", get(handler))
+        // At most one password reset per IP every 10 minutes
+        .route(
+            "/password-reset",
+            post(handler).route_layer(axum::middleware::from_fn_with_state(
+                ActionLimit::new("password-reset", Duration::from_secs(10 * 60)),
+                action_limit_middleware,
+            )),
+        )
         .layer(axum::middleware::from_fn_with_state(
             rate_limiter,
             rate_limit_middleware,
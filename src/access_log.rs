@@ -0,0 +1,484 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::types::{RateLimitDecision, RateLimitOutcome, SecurityContext};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Instant;
+
+/// Lines queued for the writer thread before new ones are dropped.
+pub const QUEUE_CAPACITY: usize = 8192;
+
+/// One line of the access log.
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessLogEntry {
+    pub timestamp: DateTime<Utc>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub latency_ms: f64,
+    pub tokens_before: Option<f64>,
+    pub tokens_after: Option<f64>,
+    /// `None` when the request didn't pass through `rate_limit_middleware`
+    pub outcome: Option<RateLimitOutcome>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub screening_reason: Option<String>,
+    /// IDs of the matched screening rules
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub screening_rules: Vec<String>,
+    pub dry_run: bool,
+}
+
+/// Size-based rotation for `AccessLogger::to_file`.
+#[derive(Debug, Clone)]
+pub struct RotationPolicy {
+    /// Rotate once the current file would grow past this many bytes
+    pub max_bytes: u64,
+    /// Number of rotated files (`access.log.1`, `access.log.2`, ...) to keep
+    pub max_files: usize,
+}
+
+impl Default for RotationPolicy {
+    fn default() -> Self {
+        Self {
+            max_bytes: 100 * 1024 * 1024,
+            max_files: 5,
+        }
+    }
+}
+
+impl RotationPolicy {
+    pub fn new(max_bytes: u64, max_files: usize) -> Self {
+        Self {
+            max_bytes,
+            max_files,
+        }
+    }
+}
+
+trait LogSink: Send {
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+struct WriterSink<W>(W);
+
+impl<W: Write + Send> LogSink for WriterSink<W> {
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        self.0.write_all(line)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    policy: RotationPolicy,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, policy: RotationPolicy) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            file,
+            written,
+            policy,
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        if self.policy.max_files > 0 {
+            for index in (1..self.policy.max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl LogSink for RotatingFile {
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.written > 0 && self.written + line.len() as u64 > self.policy.max_bytes {
+            self.rotate()?;
+        }
+        self.file.write_all(line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+enum Message {
+    Line(Vec<u8>),
+    /// Reply once every line queued before it has been written
+    Flush(mpsc::Sender<()>),
+}
+
+/// Owns the sink and writes queued lines, flushing whenever the queue runs
+/// dry. Exits once every `AccessLogger` clone has been dropped.
+fn run_writer(mut sink: Box<dyn LogSink>, receiver: mpsc::Receiver<Message>) {
+    while let Ok(message) = receiver.recv() {
+        let mut next = Some(message);
+        while let Some(message) = next {
+            match message {
+                Message::Line(line) => {
+                    if let Err(e) = sink.write_line(&line) {
+                        tracing::warn!("Failed to write access log entry: {}", e);
+                    }
+                }
+                Message::Flush(done) => {
+                    if let Err(e) = sink.flush() {
+                        tracing::warn!("Failed to flush access log: {}", e);
+                    }
+                    let _ = done.send(());
+                }
+            }
+            next = receiver.try_recv().ok();
+        }
+        if let Err(e) = sink.flush() {
+            tracing::warn!("Failed to flush access log: {}", e);
+        }
+    }
+}
+
+/// Writes one JSON line per request. Use it with `access_log_middleware`.
+///
+/// Lines are written by a dedicated thread, so requests never wait for disk
+/// I/O. If the writer falls more than `QUEUE_CAPACITY` lines behind,
+/// new lines are dropped and counted by `dropped`.
+#[derive(Clone)]
+pub struct AccessLogger {
+    sender: mpsc::SyncSender<Message>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl AccessLogger {
+    /// Log to any `Write` sink. The sink is flushed whenever the writer has
+    /// caught up.
+    pub fn to_writer<W: Write + Send + 'static>(writer: W) -> Self {
+        Self::spawn(Box::new(WriterSink(writer))).expect("failed to spawn access log writer")
+    }
+
+    /// Append to the file at `path`, rotating it according to `policy`.
+    pub fn to_file(path: impl AsRef<Path>, policy: RotationPolicy) -> io::Result<Self> {
+        let file = RotatingFile::open(path.as_ref().to_path_buf(), policy)?;
+        Self::spawn(Box::new(file))
+    }
+
+    fn spawn(sink: Box<dyn LogSink>) -> io::Result<Self> {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        std::thread::Builder::new()
+            .name("access-log".to_string())
+            .spawn(move || run_writer(sink, receiver))?;
+        Ok(Self {
+            sender,
+            dropped: Arc::new(AtomicU64::new(0)),
+        })
+    }
+
+    /// Queue `entry` for writing. Never blocks.
+    pub fn log(&self, entry: &AccessLogEntry) {
+        let mut line = match serde_json::to_vec(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                tracing::warn!("Failed to serialize access log entry: {}", e);
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        if self.sender.try_send(Message::Line(line)).is_err()
+            && self.dropped.fetch_add(1, Ordering::Relaxed) == 0
+        {
+            tracing::warn!("Access log writer is behind; dropping entries");
+        }
+    }
+
+    /// Number of entries dropped because the writer fell behind.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Block until every entry logged so far has been written and the sink
+    /// flushed, e.g. before shutdown. Call it from a blocking context.
+    pub fn flush(&self) {
+        let (done, wait) = mpsc::channel();
+        if self.sender.send(Message::Flush(done)).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+}
+
+/// Log every request that passes through. Place it outside
+/// `rate_limit_middleware` so the `RateLimitDecision` is available, including
+/// for rejected requests.
+pub async fn access_log_middleware(
+    State(logger): State<AccessLogger>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let start = Instant::now();
+    let method = request.method().to_string();
+    let path = request.uri().path().to_string();
+    let context = request.extensions().get::<SecurityContext>().cloned();
+
+    let response = next.run(request).await;
+
+    let decision = response.extensions().get::<RateLimitDecision>();
+    let context = decision.map(|d| &d.context).or(context.as_ref());
+    let entry = AccessLogEntry {
+        timestamp: Utc::now(),
+        ip: context.map(|c| c.ip_address.clone()),
+        user_agent: context.map(|c| c.user_agent.clone()),
+        method,
+        path,
+        status: response.status().as_u16(),
+        latency_ms: start.elapsed().as_secs_f64() * 1000.0,
+        tokens_before: decision.map(|d| d.tokens_before),
+        tokens_after: decision.map(|d| d.tokens_after),
+        outcome: decision.map(|d| d.outcome),
+        screening_reason: decision.and_then(|d| d.screening_reason.clone()),
+        screening_rules: decision
+            .map(|d| d.screening_rules.clone())
+            .unwrap_or_default(),
+        dry_run: decision.is_some_and(|d| d.dry_run),
+    };
+    logger.log(&entry);
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::security_context_middleware,
+        limiter::RateLimiter,
+        middleware::rate_limit_middleware,
+        screener::{RequestScreener, ScreeningConfig},
+        types::NoOpOnBlocked,
+        RateLimitConfig,
+    };
+    use axum::{
+        middleware::{from_fn, from_fn_with_state},
+        routing::get,
+        Router,
+    };
+    use axum_test::TestServer;
+    use std::net::SocketAddr;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SharedBuffer {
+        fn lines(&self) -> Vec<serde_json::Value> {
+            String::from_utf8(self.0.lock().unwrap().clone())
+                .unwrap()
+                .lines()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_logs_one_line_per_request() {
+        let buffer = SharedBuffer::default();
+        let config = RateLimitConfig::new(2, Duration::from_secs(60))
+            .with_grace_period(0)
+            .with_error_penalty(0.5);
+        let screener =
+            RequestScreener::new(&ScreeningConfig::new().with_path_pattern(r"\.php$")).unwrap();
+        let limiter = RateLimiter::new(config, NoOpOnBlocked).with_screener(screener);
+        let logger = AccessLogger::to_writer(buffer.clone());
+
+        let app = Router::new()
+            .route("/", get(|| async { "OK" }))
+            .layer(from_fn_with_state(limiter, rate_limit_middleware))
+            .layer(from_fn_with_state(logger.clone(), access_log_middleware))
+            .layer(from_fn(security_context_middleware))
+            .layer(axum::extract::connect_info::MockConnectInfo(
+                SocketAddr::from(([127, 0, 0, 1], 8080)),
+            ));
+        let server = TestServer::new(app);
+
+        for (ip, path) in [
+            ("10.1.0.1", "/"),
+            ("10.1.0.1", "/missing"),
+            ("10.1.0.1", "/"),
+            ("10.1.0.2", "/index.php"),
+        ] {
+            server
+                .get(path)
+                .add_header("X-Forwarded-For", ip)
+                .add_header("User-Agent", "test-agent")
+                .await;
+        }
+
+        logger.flush();
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 4);
+
+        assert_eq!(lines[0]["ip"], "10.1.0.1");
+        assert_eq!(lines[0]["user_agent"], "test-agent");
+        assert_eq!(lines[0]["method"], "GET");
+        assert_eq!(lines[0]["status"], 200);
+        assert_eq!(lines[0]["outcome"], "allowed");
+        assert_eq!(lines[0]["tokens_before"], 1.0);
+        assert!(lines[0]["latency_ms"].is_number());
+
+        assert_eq!(lines[1]["status"], 404);
+        assert_eq!(lines[1]["outcome"], "penalized");
+        assert_eq!(lines[1]["tokens_before"], 0.0);
+        assert_eq!(lines[1]["tokens_after"], -0.5);
+
+        assert_eq!(lines[2]["status"], 429);
+        assert_eq!(lines[2]["outcome"], "blocked");
+
+        assert_eq!(lines[3]["status"], 418);
+        assert_eq!(lines[3]["outcome"], "screened");
+        assert_eq!(
+            lines[3]["screening_reason"],
+            r"malicious path pattern: \.php$"
+        );
+        assert_eq!(lines[3]["screening_rules"][0], "path_patterns[0]");
+    }
+
+    #[test]
+    fn test_file_rotation_keeps_max_files() {
+        let dir = std::env::temp_dir().join(format!("access-log-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("access.log");
+
+        let logger = AccessLogger::to_file(&path, RotationPolicy::new(300, 2)).unwrap();
+        let entry = entry();
+        for _ in 0..20 {
+            logger.log(&entry);
+        }
+        logger.flush();
+
+        assert!(std::fs::metadata(&path).unwrap().len() <= 300);
+        assert!(dir.join("access.log.1").exists());
+        assert!(dir.join("access.log.2").exists());
+        assert!(!dir.join("access.log.3").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Blocks every write while the test holds the lock.
+    struct GatedWriter(SharedBuffer, Arc<Mutex<()>>);
+
+    impl Write for GatedWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _gate = self.1.lock().unwrap();
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_full_queue_drops_entries() {
+        let buffer = SharedBuffer::default();
+        let gate = Arc::new(Mutex::new(()));
+        let logger = AccessLogger::to_writer(GatedWriter(buffer.clone(), gate.clone()));
+
+        let held = gate.lock().unwrap();
+        for _ in 0..QUEUE_CAPACITY + 10 {
+            logger.log(&entry());
+        }
+        // The writer may have taken one line off the queue before blocking
+        assert!((9..=10).contains(&logger.dropped()), "{}", logger.dropped());
+        drop(held);
+
+        logger.flush();
+        assert_eq!(
+            buffer.lines().len() as u64,
+            (QUEUE_CAPACITY + 10) as u64 - logger.dropped()
+        );
+    }
+
+    fn entry() -> AccessLogEntry {
+        AccessLogEntry {
+            timestamp: Utc::now(),
+            ip: Some("10.1.0.3".to_string()),
+            user_agent: None,
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            status: 200,
+            latency_ms: 1.0,
+            tokens_before: Some(1.0),
+            tokens_after: Some(1.0),
+            outcome: Some(RateLimitOutcome::Allowed),
+            screening_reason: None,
+            screening_rules: Vec::new(),
+            dry_run: false,
+        }
+    }
+}
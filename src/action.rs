@@ -0,0 +1,572 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Per-action cooldown limits backed by the configured [`ActionChecker`].
+//!
+//! The token bucket limits how often an IP can hit the API at all. Action
+//! limits answer a different question: "has this IP done *this* recently?",
+//! e.g. one password-reset email per IP per 10 minutes.
+
+use crate::types::ActionChecker;
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    http::{request::Parts, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use dashmap::{mapref::entry::Entry, DashMap};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+use tokio::task::JoinHandle;
+
+/// A cooldown for a named action.
+#[derive(Debug, Clone)]
+pub struct ActionLimit {
+    pub action: String,
+    pub within: Duration,
+}
+
+impl ActionLimit {
+    pub fn new(action: impl Into<String>, within: Duration) -> Self {
+        Self {
+            action: action.into(),
+            within,
+        }
+    }
+}
+
+/// Rejection returned when an action was performed within its cooldown.
+/// Responds with 429 Too Many Requests naming the action.
+#[derive(Debug, Clone)]
+pub struct ActionLimited {
+    pub action: String,
+}
+
+impl std::fmt::Display for ActionLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Too many requests for action: {}", self.action)
+    }
+}
+
+impl IntoResponse for ActionLimited {
+    fn into_response(self) -> Response {
+        (StatusCode::TOO_MANY_REQUESTS, self.to_string()).into_response()
+    }
+}
+
+/// Handle for checking action cooldowns for the current client.
+///
+/// Injected into request extensions by `rate_limit_middleware` when the
+/// limiter has an `ActionChecker` (see `RateLimiter::with_action_checker`).
+/// Extract it in a handler to check actions whose name depends on the request:
+///
+/// ```rust,ignore
+/// async fn send_reset_email(guard: ActionGuard, Json(body): Json<ResetRequest>) -> Response {
+///     if let Err(limited) = guard.check("password-reset", Duration::from_secs(600)).await {
+///         return limited.into_response();
+///     }
+///     // ...
+/// }
+/// ```
+///
+/// Checker errors are logged and the action is allowed, so a database outage
+/// doesn't lock users out.
+#[derive(Clone)]
+pub struct ActionGuard {
+    checker: Arc<dyn ActionChecker>,
+    ip: String,
+    dry_run: bool,
+    #[cfg(feature = "metrics")]
+    metrics: crate::metrics::MetricsSink,
+}
+
+impl ActionGuard {
+    pub(crate) fn new(checker: Arc<dyn ActionChecker>, ip: String, dry_run: bool) -> Self {
+        Self {
+            checker,
+            ip,
+            dry_run,
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) fn with_metrics_sink(mut self, metrics: crate::metrics::MetricsSink) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// The IP address actions are checked against.
+    pub fn ip(&self) -> &str {
+        &self.ip
+    }
+
+    /// Returns `Err(ActionLimited)` if `action` was performed by this IP within `within`.
+    pub async fn check(&self, action: &str, within: Duration) -> Result<(), ActionLimited> {
+        let performed = match self
+            .checker
+            .check_recent_action(&self.ip, action, within)
+            .await
+        {
+            Ok(performed) => performed,
+            Err(e) => {
+                tracing::error!(
+                    "Action check failed for {} (action: {}), allowing: {}",
+                    self.ip,
+                    action,
+                    e
+                );
+                return Ok(());
+            }
+        };
+
+        if !performed {
+            return Ok(());
+        }
+
+        if self.dry_run {
+            tracing::warn!(
+                "Dry run: action would have been limited: {} (action: {})",
+                self.ip,
+                action
+            );
+            #[cfg(feature = "metrics")]
+            self.metrics.record_dry_run("action_limited");
+            return Ok(());
+        }
+
+        Err(self.limited(action))
+    }
+
+    pub async fn check_limit(&self, limit: &ActionLimit) -> Result<(), ActionLimited> {
+        self.check(&limit.action, limit.within).await
+    }
+
+    /// Like `check`, but reserves `action` through `ActionChecker::try_acquire`
+    /// so concurrent requests cannot all pass the check. Call `record` if the
+    /// action succeeds and `release` if it doesn't.
+    pub async fn acquire(&self, action: &str, within: Duration) -> Result<(), ActionLimited> {
+        if self.dry_run {
+            return self.check(action, within).await;
+        }
+
+        match self.checker.try_acquire(&self.ip, action, within).await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(self.limited(action)),
+            Err(e) => {
+                tracing::error!(
+                    "Action check failed for {} (action: {}), allowing: {}",
+                    self.ip,
+                    action,
+                    e
+                );
+                Ok(())
+            }
+        }
+    }
+
+    /// Undo an `acquire` whose action did not happen. Errors are logged, not
+    /// returned.
+    pub async fn release(&self, action: &str) {
+        if self.dry_run {
+            return;
+        }
+        if let Err(e) = self.checker.release(&self.ip, action).await {
+            tracing::error!(
+                "Failed to release action for {} (action: {}): {}",
+                self.ip,
+                action,
+                e
+            );
+        }
+    }
+
+    fn limited(&self, action: &str) -> ActionLimited {
+        tracing::info!("Action limited: {} (action: {})", self.ip, action);
+        #[cfg(feature = "metrics")]
+        self.metrics.record_action_limited(action);
+
+        ActionLimited {
+            action: action.to_string(),
+        }
+    }
+
+    /// Record that this IP performed `action`. Errors are logged, not returned.
+    pub async fn record(&self, action: &str) {
+        if self.dry_run {
+            return;
+        }
+        if let Err(e) = self.checker.record_action(&self.ip, action).await {
+            tracing::error!(
+                "Failed to record action for {} (action: {}): {}",
+                self.ip,
+                action,
+                e
+            );
+        }
+    }
+}
+
+impl<S: Send + Sync> FromRequestParts<S> for ActionGuard {
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        parts.extensions.get::<ActionGuard>().cloned().ok_or_else(|| {
+            tracing::error!("ActionGuard not found in request extensions. rate_limit_middleware should run before this handler and the RateLimiter needs an ActionChecker.");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        })
+    }
+}
+
+/// Per-route action limit middleware.
+///
+/// Must run inside `rate_limit_middleware`, so add it with `route_layer` on the
+/// routes it protects:
+///
+/// ```rust,ignore
+/// let app = Router::new()
+///     .route(
+///         "/password-reset",
+///         post(send_reset_email).route_layer(axum::middleware::from_fn_with_state(
+///             ActionLimit::new("password-reset", Duration::from_secs(600)),
+///             action_limit_middleware,
+///         )),
+///     )
+///     .layer(axum::middleware::from_fn_with_state(rate_limiter, rate_limit_middleware))
+///     .layer(axum::middleware::from_fn(security_context_middleware));
+/// ```
+pub async fn action_limit_middleware(
+    State(limit): State<ActionLimit>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let guard = match request.extensions().get::<ActionGuard>() {
+        Some(guard) => guard.clone(),
+        None => {
+            tracing::error!("ActionGuard not found in request extensions. rate_limit_middleware should run before action_limit_middleware and the RateLimiter needs an ActionChecker.");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if let Err(limited) = guard.acquire(&limit.action, limit.within).await {
+        return limited.into_response();
+    }
+
+    let response = next.run(request).await;
+
+    if response.status().is_success() {
+        guard.record(&limit.action).await;
+    } else {
+        guard.release(&limit.action).await;
+    }
+
+    response
+}
+
+/// An [`ActionChecker`] that keeps the last time each IP performed each action
+/// in memory, for deployments without a database.
+///
+/// Entries older than `retention` are expired by [`cleanup`](Self::cleanup)
+/// (see [`spawn_cleanup`](Self::spawn_cleanup)) and whenever the store is full;
+/// if it is still full, the oldest entries are evicted. Both work from an
+/// insertion-ordered queue, so a flood of new IPs costs amortized O(1) per
+/// record. `retention` should be at least the longest cooldown you check.
+/// Clones share the same store.
+///
+/// ```rust,ignore
+/// let checker = InMemoryActionChecker::new(Duration::from_secs(60 * 60));
+/// let rate_limiter = RateLimiter::new(config, callbacks).with_action_checker(checker.clone());
+/// ```
+#[derive(Clone)]
+pub struct InMemoryActionChecker {
+    actions: Arc<DashMap<ActionKey, Instant>>,
+    /// Records oldest first. A record whose time no longer matches `actions`
+    /// was superseded or removed and is skipped.
+    order: Arc<Mutex<VecDeque<(ActionKey, Instant)>>>,
+    retention: Duration,
+    max_entries: usize,
+}
+
+type ActionKey = (String, String);
+
+impl InMemoryActionChecker {
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            actions: Arc::new(DashMap::new()),
+            order: Arc::new(Mutex::new(VecDeque::new())),
+            retention,
+            max_entries: 100_000,
+        }
+    }
+
+    /// Maximum number of (IP, action) pairs kept (default: 100,000).
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries.max(1);
+        self
+    }
+
+    /// Record that `ip` performed `action` now.
+    pub fn record_action(&self, ip: &str, action: &str) {
+        let key = (ip.to_string(), action.to_string());
+        self.make_room(&key);
+        let now = Instant::now();
+        self.actions.insert(key.clone(), now);
+        self.push_order(key, now);
+    }
+
+    /// Record that `ip` performed `action` now, unless it already did within
+    /// `within`. The check and the record are atomic, so of several concurrent
+    /// calls only one returns `true`.
+    pub fn try_acquire(&self, ip: &str, action: &str, within: Duration) -> bool {
+        let key = (ip.to_string(), action.to_string());
+        self.make_room(&key);
+        let now = Instant::now();
+        match self.actions.entry(key.clone()) {
+            Entry::Occupied(mut entry) => {
+                if entry.get().elapsed() < within.min(self.retention) {
+                    return false;
+                }
+                entry.insert(now);
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(now);
+            }
+        }
+        // The entry guard is dropped above; never lock `order` while holding it
+        self.push_order(key, now);
+        true
+    }
+
+    /// Forget that `ip` performed `action`, e.g. to undo a `try_acquire` for an
+    /// action that failed.
+    pub fn release(&self, ip: &str, action: &str) {
+        self.actions.remove(&(ip.to_string(), action.to_string()));
+    }
+
+    /// Whether `ip` performed `action` within `within`.
+    pub fn performed_within(&self, ip: &str, action: &str, within: Duration) -> bool {
+        self.actions
+            .get(&(ip.to_string(), action.to_string()))
+            .is_some_and(|performed_at| performed_at.elapsed() < within.min(self.retention))
+    }
+
+    /// Remove entries older than the retention period.
+    pub fn cleanup(&self) {
+        let before_count = self.actions.len();
+        let mut order = lock(&self.order);
+        while order
+            .front()
+            .is_some_and(|(_, at)| at.elapsed() >= self.retention)
+        {
+            if let Some((key, at)) = order.pop_front() {
+                self.actions
+                    .remove_if(&key, |_, performed_at| *performed_at == at);
+            }
+        }
+        drop(order);
+        let after_count = self.actions.len();
+
+        if before_count > after_count {
+            tracing::debug!(
+                "Expired {} recorded actions ({} -> {} entries)",
+                before_count - after_count,
+                before_count,
+                after_count
+            );
+        }
+    }
+
+    /// Spawn a task that calls [`cleanup`](Self::cleanup) every `interval`.
+    /// Abort the returned handle to stop it.
+    pub fn spawn_cleanup(&self, interval: Duration) -> JoinHandle<()> {
+        let checker = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                interval.tick().await;
+                checker.cleanup();
+            }
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.actions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty()
+    }
+
+    /// Evict from the front of `order` until there is room for `key`: expired
+    /// entries first, then the oldest.
+    fn make_room(&self, key: &ActionKey) {
+        if self.actions.len() < self.max_entries || self.actions.contains_key(key) {
+            return;
+        }
+
+        let mut order = lock(&self.order);
+        while let Some((key, at)) = order.pop_front() {
+            self.actions
+                .remove_if(&key, |_, performed_at| *performed_at == at);
+            let expired_next = order
+                .front()
+                .is_some_and(|(_, at)| at.elapsed() >= self.retention);
+            if self.actions.len() < self.max_entries && !expired_next {
+                break;
+            }
+        }
+    }
+
+    fn push_order(&self, key: ActionKey, at: Instant) {
+        let mut order = lock(&self.order);
+        order.push_back((key, at));
+
+        // Re-recorded keys leave stale records behind. Dropping them once the
+        // queue is twice the store keeps it bounded at amortized O(1) cost.
+        if order.len() > self.max_entries.saturating_mul(2) {
+            order.retain(|(key, at)| {
+                self.actions
+                    .get(key)
+                    .is_some_and(|performed_at| *performed_at == *at)
+            });
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[async_trait::async_trait]
+impl ActionChecker for InMemoryActionChecker {
+    async fn check_recent_action(
+        &self,
+        ip: &str,
+        action: &str,
+        within: Duration,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.performed_within(ip, action, within))
+    }
+
+    async fn record_action(
+        &self,
+        ip: &str,
+        action: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        InMemoryActionChecker::record_action(self, ip, action);
+        Ok(())
+    }
+
+    async fn try_acquire(
+        &self,
+        ip: &str,
+        action: &str,
+        within: Duration,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(InMemoryActionChecker::try_acquire(self, ip, action, within))
+    }
+
+    async fn release(
+        &self,
+        ip: &str,
+        action: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        InMemoryActionChecker::release(self, ip, action);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_records_and_checks_actions() {
+        let checker = InMemoryActionChecker::new(Duration::from_secs(60));
+        assert!(!checker.performed_within("1.2.3.4", "password-reset", Duration::from_secs(60)));
+
+        checker.record_action("1.2.3.4", "password-reset");
+
+        assert!(checker.performed_within("1.2.3.4", "password-reset", Duration::from_secs(60)));
+        assert!(!checker.performed_within("1.2.3.4", "signup", Duration::from_secs(60)));
+        assert!(!checker.performed_within("5.6.7.8", "password-reset", Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_actions_expire() {
+        let checker = InMemoryActionChecker::new(Duration::from_millis(20));
+        checker.record_action("1.2.3.4", "password-reset");
+
+        std::thread::sleep(Duration::from_millis(30));
+
+        assert!(!checker.performed_within("1.2.3.4", "password-reset", Duration::from_secs(60)));
+        checker.cleanup();
+        assert!(checker.is_empty());
+    }
+
+    #[test]
+    fn test_try_acquire_reserves_once() {
+        let checker = InMemoryActionChecker::new(Duration::from_secs(60));
+        let within = Duration::from_secs(60);
+
+        assert!(checker.try_acquire("1.2.3.4", "password-reset", within));
+        assert!(!checker.try_acquire("1.2.3.4", "password-reset", within));
+        assert!(checker.try_acquire("5.6.7.8", "password-reset", within));
+
+        checker.release("1.2.3.4", "password-reset");
+        assert!(checker.try_acquire("1.2.3.4", "password-reset", within));
+    }
+
+    #[test]
+    fn test_store_is_bounded() {
+        let checker = InMemoryActionChecker::new(Duration::from_secs(60)).with_max_entries(2);
+        checker.record_action("1.1.1.1", "a");
+        std::thread::sleep(Duration::from_millis(2));
+        checker.record_action("2.2.2.2", "a");
+        std::thread::sleep(Duration::from_millis(2));
+        checker.record_action("3.3.3.3", "a");
+
+        assert_eq!(checker.len(), 2);
+        assert!(
+            !checker.performed_within("1.1.1.1", "a", Duration::from_secs(60)),
+            "oldest entry should be evicted"
+        );
+        assert!(checker.performed_within("3.3.3.3", "a", Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_unique_key_flood_stays_bounded() {
+        let checker = InMemoryActionChecker::new(Duration::from_secs(60)).with_max_entries(100);
+        for i in 0..10_000 {
+            checker.record_action(&format!("10.0.{}.{}", i / 256, i % 256), "signup");
+            checker.record_action("1.1.1.1", "signup");
+        }
+
+        assert!(checker.len() <= 100);
+        assert!(lock(&checker.order).len() <= 200);
+        assert!(checker.performed_within("1.1.1.1", "signup", Duration::from_secs(60)));
+        assert!(checker.performed_within("10.0.39.15", "signup", Duration::from_secs(60)));
+        assert!(!checker.performed_within("10.0.0.0", "signup", Duration::from_secs(60)));
+    }
+}
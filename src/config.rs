@@ -17,14 +17,100 @@
 
 use std::time::Duration;
 
+/// Error returned when loading or validating configuration.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// The input could not be parsed at all.
+    Parse { message: String },
+    /// A field (or environment variable) has an invalid value.
+    InvalidField { field: String, message: String },
+}
+
+impl ConfigError {
+    /// Qualify the field name with the section it was loaded from.
+    pub(crate) fn in_section(self, section: &str) -> Self {
+        match self {
+            Self::InvalidField { field, message } => Self::InvalidField {
+                field: format!("{}.{}", section, field),
+                message,
+            },
+            other => other,
+        }
+    }
+
+    pub(crate) fn invalid(field: impl Into<String>, message: impl Into<String>) -> Self {
+        Self::InvalidField {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Parse { message } => write!(f, "Failed to parse configuration: {}", message),
+            Self::InvalidField { field, message } => {
+                write!(f, "Invalid value for {}: {}", field, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[cfg(feature = "serde")]
+impl From<toml::de::Error> for ConfigError {
+    fn from(e: toml::de::Error) -> Self {
+        Self::Parse {
+            message: e.to_string(),
+        }
+    }
+}
+
+/// Read an environment variable and parse it, naming the variable in errors.
+pub(crate) fn parse_env<T>(
+    lookup: &impl Fn(&str) -> Option<String>,
+    name: &str,
+    parse: impl Fn(&str) -> Result<T, String>,
+) -> Result<Option<T>, ConfigError> {
+    lookup(name)
+        .map(|value| parse(value.trim()).map_err(|message| ConfigError::invalid(name, message)))
+        .transpose()
+}
+
+pub(crate) fn parse_env_bool(value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        _ => Err(format!("expected a boolean, got '{}'", value)),
+    }
+}
+
+pub(crate) fn parse_env_number<T: std::str::FromStr>(value: &str) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    value.parse().map_err(|e: T::Err| e.to_string())
+}
+
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
 pub struct RateLimitConfig {
     pub rate_limit_per_minute: u32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::duration::serde_format"))]
     pub block_duration: Duration,
     pub grace_period_seconds: u64,
     pub cache_refund_ratio: f64,
     pub auth_refund_ratio: f64,
     pub error_penalty_tokens: f64,
+    /// Evaluate limits without enforcing them. Requests that would be rate
+    /// limited are logged and metered as "would have" and passed through.
+    pub dry_run: bool,
 }
 
 impl Default for RateLimitConfig {
@@ -36,6 +122,7 @@ impl Default for RateLimitConfig {
             cache_refund_ratio: 0.5,
             auth_refund_ratio: 0.0,
             error_penalty_tokens: 2.0,
+            dry_run: false,
         }
     }
 }
@@ -69,6 +156,99 @@ impl RateLimitConfig {
         self
     }
 
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Check that all values are in range. Builder methods clamp their inputs,
+    /// but values loaded from files or the environment are rejected instead.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.rate_limit_per_minute == 0 {
+            return Err(ConfigError::invalid(
+                "rate_limit_per_minute",
+                "must be greater than 0",
+            ));
+        }
+        crate::duration::validate_block_duration("block_duration", self.block_duration)?;
+        for (field, ratio) in [
+            ("cache_refund_ratio", self.cache_refund_ratio),
+            ("auth_refund_ratio", self.auth_refund_ratio),
+        ] {
+            if !(0.0..=1.0).contains(&ratio) {
+                return Err(ConfigError::invalid(
+                    field,
+                    format!("must be between 0.0 and 1.0, got {}", ratio),
+                ));
+            }
+        }
+        if !self.error_penalty_tokens.is_finite() || self.error_penalty_tokens < 0.0 {
+            return Err(ConfigError::invalid(
+                "error_penalty_tokens",
+                format!("must be 0.0 or greater, got {}", self.error_penalty_tokens),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Parse and validate a TOML document containing the config fields at the
+    /// top level. Missing fields use their defaults; durations accept values
+    /// like `"15m"` or a number of seconds.
+    #[cfg(feature = "serde")]
+    pub fn from_toml_str(input: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(input)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Load from environment variables named `{prefix}RATE_LIMIT_PER_MINUTE`,
+    /// `{prefix}BLOCK_DURATION`, `{prefix}GRACE_PERIOD_SECONDS`,
+    /// `{prefix}CACHE_REFUND_RATIO`, `{prefix}AUTH_REFUND_RATIO`,
+    /// `{prefix}ERROR_PENALTY_TOKENS` and `{prefix}DRY_RUN`. Unset variables
+    /// use their defaults.
+    pub fn from_env(prefix: &str) -> Result<Self, ConfigError> {
+        Self::from_env_with(prefix, |name| std::env::var(name).ok())
+    }
+
+    pub(crate) fn from_env_with(
+        prefix: &str,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let var = |name: &str| format!("{}{}", prefix, name);
+        let mut config = Self::default();
+
+        if let Some(v) = parse_env(&lookup, &var("RATE_LIMIT_PER_MINUTE"), parse_env_number)? {
+            config.rate_limit_per_minute = v;
+        }
+        if let Some(v) = parse_env(&lookup, &var("BLOCK_DURATION"), crate::duration::parse)? {
+            config.block_duration = v;
+        }
+        if let Some(v) = parse_env(&lookup, &var("GRACE_PERIOD_SECONDS"), parse_env_number)? {
+            config.grace_period_seconds = v;
+        }
+        if let Some(v) = parse_env(&lookup, &var("CACHE_REFUND_RATIO"), parse_env_number)? {
+            config.cache_refund_ratio = v;
+        }
+        if let Some(v) = parse_env(&lookup, &var("AUTH_REFUND_RATIO"), parse_env_number)? {
+            config.auth_refund_ratio = v;
+        }
+        if let Some(v) = parse_env(&lookup, &var("ERROR_PENALTY_TOKENS"), parse_env_number)? {
+            config.error_penalty_tokens = v;
+        }
+        if let Some(v) = parse_env(&lookup, &var("DRY_RUN"), parse_env_bool)? {
+            config.dry_run = v;
+        }
+
+        // Report range errors against the variable the value came from
+        config.validate().map_err(|e| match e {
+            ConfigError::InvalidField { field, message } => {
+                ConfigError::invalid(var(&field.to_ascii_uppercase()), message)
+            }
+            other => other,
+        })?;
+        Ok(config)
+    }
+
     pub fn max_tokens(&self) -> f64 {
         self.rate_limit_per_minute as f64
     }
@@ -23,6 +23,7 @@ use axum::{
 };
 use std::net::{IpAddr, SocketAddr};
 
+use crate::config::{parse_env, ConfigError};
 use crate::types::SecurityContext;
 
 /// Error returned when client IP extraction fails.
@@ -81,7 +82,16 @@ impl IntoResponse for IpExtractionError {
 /// Different proxy configurations require different IP extraction strategies:
 /// - Behind trusted proxies: use `ForwardedHeader` with appropriate `proxy_depth`
 /// - Direct connections: use `SocketAddr`
+///
+/// With the `serde` feature, the strategy is written as a table tagged by
+/// `strategy`, e.g. `{ strategy = "forwarded_header", header_name = "X-Real-IP", proxy_depth = 1 }`
+/// or `{ strategy = "socket_addr" }`.
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(tag = "strategy", rename_all = "snake_case", deny_unknown_fields)
+)]
 pub enum IpExtractionStrategy {
     /// Extract client IP from a forwarded header with proxy depth validation.
     ///
@@ -152,6 +162,11 @@ impl IpExtractionStrategy {
 
 /// Configuration for the security context middleware.
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
 pub struct SecurityContextConfig {
     /// Strategy for extracting the client IP address.
     pub ip_extraction: IpExtractionStrategy,
@@ -168,6 +183,79 @@ impl SecurityContextConfig {
         self.ip_extraction = strategy;
         self
     }
+
+    /// Check that the forwarded header name is valid and the proxy depth is at least 1.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let IpExtractionStrategy::ForwardedHeader {
+            header_name,
+            proxy_depth,
+        } = &self.ip_extraction
+        {
+            if axum::http::HeaderName::from_bytes(header_name.as_bytes()).is_err() {
+                return Err(ConfigError::invalid(
+                    "ip_extraction.header_name",
+                    format!("'{}' is not a valid header name", header_name),
+                ));
+            }
+            if *proxy_depth == 0 {
+                return Err(ConfigError::invalid(
+                    "ip_extraction.proxy_depth",
+                    "must be greater than 0",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse and validate a TOML document containing the config fields at the
+    /// top level.
+    #[cfg(feature = "serde")]
+    pub fn from_toml_str(input: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(input)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Load from `{prefix}IP_EXTRACTION` and `{prefix}PROXY_DEPTH`.
+    ///
+    /// `IP_EXTRACTION` is either `socket_addr` or the name of a forwarded
+    /// header; `PROXY_DEPTH` defaults to 1.
+    pub fn from_env(prefix: &str) -> Result<Self, ConfigError> {
+        Self::from_env_with(prefix, |name| std::env::var(name).ok())
+    }
+
+    pub(crate) fn from_env_with(
+        prefix: &str,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let source_var = format!("{}IP_EXTRACTION", prefix);
+        let depth_var = format!("{}PROXY_DEPTH", prefix);
+
+        let proxy_depth = parse_env(&lookup, &depth_var, |value| {
+            value.parse::<usize>().map_err(|e| e.to_string())
+        })?
+        .unwrap_or(1);
+
+        let ip_extraction = match lookup(&source_var).as_deref().map(str::trim) {
+            None => IpExtractionStrategy::default(),
+            Some("socket_addr") => IpExtractionStrategy::SocketAddr,
+            Some(header_name) => IpExtractionStrategy::custom_header(header_name, proxy_depth),
+        };
+
+        let config = Self { ip_extraction };
+        config.validate().map_err(|e| match e {
+            ConfigError::InvalidField { field, message } => {
+                let var = if field.ends_with("proxy_depth") {
+                    depth_var
+                } else {
+                    source_var
+                };
+                ConfigError::invalid(var, message)
+            }
+            other => other,
+        })?;
+        Ok(config)
+    }
 }
 
 /// Security context middleware with configurable IP extraction.
@@ -250,7 +338,7 @@ fn process_security_context(
     Ok(())
 }
 
-fn extract_client_ip(
+pub(crate) fn extract_client_ip(
     strategy: &IpExtractionStrategy,
     headers: &HeaderMap,
     socket_ip: IpAddr,
@@ -317,7 +405,7 @@ fn parse_forwarded_header(
         })
 }
 
-fn sanitize_user_agent(user_agent: &str) -> String {
+pub(crate) fn sanitize_user_agent(user_agent: &str) -> String {
     const MAX_LENGTH: usize = 500;
 
     let sanitized: String = user_agent
@@ -0,0 +1,440 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#[cfg(feature = "metrics")]
+use crate::metrics::MetricsSink;
+use crate::types::{BlockEvent, OnBlocked, UnblockEvent};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use tokio::sync::{mpsc, Semaphore};
+
+/// What to do with an `OnBlocked` event when the dispatch queue can't take it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Drop the new event when the queue is full.
+    #[default]
+    DropNewest,
+    /// Replace an event of the same kind for the same key that is still
+    /// waiting in the queue, so a burst for one key costs one callback.
+    /// New keys are dropped when the queue is full.
+    Coalesce,
+}
+
+/// Limits on how `OnBlocked` callbacks are run.
+///
+/// Events are queued and handed to a single worker task, which runs at most
+/// `max_concurrency` callbacks at a time and abandons any callback that takes
+/// longer than `callback_timeout`.
+#[derive(Debug, Clone)]
+pub struct DispatchConfig {
+    /// Maximum number of events waiting to be delivered
+    pub queue_capacity: usize,
+    /// Maximum number of callbacks running at once
+    pub max_concurrency: usize,
+    /// How long a single callback may run before it is abandoned
+    pub callback_timeout: Duration,
+    pub overflow_policy: OverflowPolicy,
+}
+
+impl Default for DispatchConfig {
+    fn default() -> Self {
+        Self {
+            queue_capacity: 1024,
+            max_concurrency: 16,
+            callback_timeout: Duration::from_secs(10),
+            overflow_policy: OverflowPolicy::DropNewest,
+        }
+    }
+}
+
+impl DispatchConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = capacity.max(1);
+        self
+    }
+
+    pub fn with_max_concurrency(mut self, max: usize) -> Self {
+        self.max_concurrency = max.max(1);
+        self
+    }
+
+    pub fn with_callback_timeout(mut self, timeout: Duration) -> Self {
+        self.callback_timeout = timeout;
+        self
+    }
+
+    pub fn with_overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = policy;
+        self
+    }
+}
+
+/// Counters for `OnBlocked` event delivery since the limiter was created.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DispatchStats {
+    /// Callbacks that ran to completion
+    pub delivered: u64,
+    /// Events dropped because the queue was full
+    pub dropped: u64,
+    /// Events replaced by a newer event for the same key
+    pub coalesced: u64,
+    /// Callbacks abandoned after `callback_timeout`
+    pub timed_out: u64,
+}
+
+pub(crate) enum LimiterNotification {
+    Blocked(BlockEvent),
+    Unblocked(UnblockEvent),
+    Expired(UnblockEvent),
+}
+
+impl LimiterNotification {
+    fn coalesce_key(&self) -> (u8, String) {
+        match self {
+            LimiterNotification::Blocked(event) => (0, event.key.clone()),
+            LimiterNotification::Unblocked(event) => (1, event.key.clone()),
+            LimiterNotification::Expired(event) => (2, event.key.clone()),
+        }
+    }
+
+    async fn deliver<B: OnBlocked>(self, on_blocked: &B) {
+        match self {
+            LimiterNotification::Blocked(event) => on_blocked.on_block_event(&event).await,
+            LimiterNotification::Unblocked(event) => on_blocked.on_unblocked(&event).await,
+            LimiterNotification::Expired(event) => on_blocked.on_expired(&event).await,
+        }
+    }
+}
+
+enum Queued {
+    Event(LimiterNotification),
+    /// The event is held in `Shared::pending` so it can be replaced in place
+    Pending((u8, String)),
+}
+
+#[derive(Default)]
+struct Shared {
+    pending: Mutex<HashMap<(u8, String), LimiterNotification>>,
+    delivered: AtomicU64,
+    dropped: AtomicU64,
+    coalesced: AtomicU64,
+    timed_out: AtomicU64,
+    no_runtime_warned: AtomicBool,
+    #[cfg(feature = "metrics")]
+    metrics: MetricsSink,
+}
+
+/// Bounded queue in front of the `OnBlocked` callbacks. The worker task is
+/// started on the first event, so a limiter can be built outside a runtime.
+pub(crate) struct Dispatcher<B: OnBlocked> {
+    config: DispatchConfig,
+    on_blocked: Arc<B>,
+    shared: Arc<Shared>,
+    sender: OnceLock<mpsc::Sender<Queued>>,
+}
+
+impl<B: OnBlocked + 'static> Dispatcher<B> {
+    pub(crate) fn new(config: DispatchConfig, on_blocked: Arc<B>) -> Self {
+        Self {
+            config,
+            on_blocked,
+            shared: Arc::new(Shared::default()),
+            sender: OnceLock::new(),
+        }
+    }
+
+    /// Record drops and timeouts to `metrics`. Only called while building
+    /// the limiter, before any event has been counted.
+    #[cfg(feature = "metrics")]
+    pub(crate) fn with_metrics_sink(mut self, metrics: MetricsSink) -> Self {
+        self.shared = Arc::new(Shared {
+            metrics,
+            ..Default::default()
+        });
+        self
+    }
+
+    pub(crate) fn on_blocked(&self) -> Arc<B> {
+        self.on_blocked.clone()
+    }
+
+    pub(crate) fn stats(&self) -> DispatchStats {
+        DispatchStats {
+            delivered: self.shared.delivered.load(Ordering::Relaxed),
+            dropped: self.shared.dropped.load(Ordering::Relaxed),
+            coalesced: self.shared.coalesced.load(Ordering::Relaxed),
+            timed_out: self.shared.timed_out.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn dispatch(&self, notification: LimiterNotification) {
+        let Some(sender) = self.sender() else {
+            if !self.shared.no_runtime_warned.swap(true, Ordering::Relaxed) {
+                tracing::warn!(
+                    "No tokio runtime; OnBlocked events are dropped until the limiter is used inside one"
+                );
+            }
+            self.count_dropped("no_runtime");
+            return;
+        };
+
+        let queued = match self.config.overflow_policy {
+            OverflowPolicy::DropNewest => Queued::Event(notification),
+            OverflowPolicy::Coalesce => {
+                let key = notification.coalesce_key();
+                let mut pending = lock(&self.shared.pending);
+                if let Some(waiting) = pending.get_mut(&key) {
+                    *waiting = notification;
+                    self.shared.coalesced.fetch_add(1, Ordering::Relaxed);
+                    #[cfg(feature = "metrics")]
+                    self.shared.metrics.record_callback_dropped("coalesced");
+                    return;
+                }
+                // Reserve the slot while holding the lock so the worker can't
+                // take the key before the event is in place
+                match sender.try_reserve() {
+                    Ok(permit) => {
+                        pending.insert(key.clone(), notification);
+                        permit.send(Queued::Pending(key));
+                    }
+                    Err(_) => self.record_dropped(),
+                }
+                return;
+            }
+        };
+
+        if sender.try_send(queued).is_err() {
+            self.record_dropped();
+        }
+    }
+
+    fn record_dropped(&self) {
+        tracing::warn!("OnBlocked queue full; event dropped");
+        self.count_dropped("queue_full");
+    }
+
+    fn count_dropped(&self, _reason: &str) {
+        self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        self.shared.metrics.record_callback_dropped(_reason);
+    }
+
+    fn sender(&self) -> Option<&mpsc::Sender<Queued>> {
+        if let Some(sender) = self.sender.get() {
+            return Some(sender);
+        }
+        let handle = tokio::runtime::Handle::try_current().ok()?;
+
+        Some(self.sender.get_or_init(|| {
+            let (sender, receiver) = mpsc::channel(self.config.queue_capacity);
+            handle.spawn(run_worker(
+                receiver,
+                self.on_blocked.clone(),
+                self.shared.clone(),
+                self.config.clone(),
+            ));
+            sender
+        }))
+    }
+}
+
+/// Delivers queued events until every sender (and so every limiter clone)
+/// has been dropped.
+async fn run_worker<B: OnBlocked + 'static>(
+    mut receiver: mpsc::Receiver<Queued>,
+    on_blocked: Arc<B>,
+    shared: Arc<Shared>,
+    config: DispatchConfig,
+) {
+    let semaphore = Arc::new(Semaphore::new(config.max_concurrency));
+
+    while let Some(queued) = receiver.recv().await {
+        let permit = semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("dispatch semaphore is never closed");
+
+        let notification = match queued {
+            Queued::Event(notification) => notification,
+            Queued::Pending(key) => match lock(&shared.pending).remove(&key) {
+                Some(notification) => notification,
+                None => continue,
+            },
+        };
+
+        let on_blocked = on_blocked.clone();
+        let shared = shared.clone();
+        let timeout = config.callback_timeout;
+        tokio::spawn(async move {
+            match tokio::time::timeout(timeout, notification.deliver(on_blocked.as_ref())).await {
+                Ok(()) => {
+                    shared.delivered.fetch_add(1, Ordering::Relaxed);
+                }
+                Err(_) => {
+                    shared.timed_out.fetch_add(1, Ordering::Relaxed);
+                    tracing::warn!("OnBlocked callback timed out after {:?}", timeout);
+                    #[cfg(feature = "metrics")]
+                    shared.metrics.record_callback_dropped("timeout");
+                }
+            }
+            drop(permit);
+        });
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{BlockReason, SecurityContext};
+    use std::sync::atomic::AtomicUsize;
+
+    #[derive(Default)]
+    struct SlowOnBlocked {
+        delay: Duration,
+        running: AtomicUsize,
+        max_running: AtomicUsize,
+        strikes_seen: Mutex<Vec<u32>>,
+    }
+
+    #[async_trait::async_trait]
+    impl OnBlocked for SlowOnBlocked {
+        async fn on_block_event(&self, event: &BlockEvent) {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_running.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(self.delay).await;
+            self.strikes_seen.lock().unwrap().push(event.strikes);
+            self.running.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    fn blocked(key: &str, strikes: u32) -> LimiterNotification {
+        LimiterNotification::Blocked(BlockEvent {
+            key: key.to_string(),
+            path: "/".to_string(),
+            context: SecurityContext::new(key.to_string(), String::new()),
+            reason: BlockReason::TokenExhaustion,
+            matched_pattern: None,
+            blocked_until: chrono::Utc::now(),
+            tokens: 0.0,
+            strikes,
+        })
+    }
+
+    fn dispatcher(config: DispatchConfig, delay: Duration) -> Dispatcher<SlowOnBlocked> {
+        let on_blocked = SlowOnBlocked {
+            delay,
+            ..Default::default()
+        };
+        Dispatcher::new(config, Arc::new(on_blocked))
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_is_limited() {
+        let config = DispatchConfig::new().with_max_concurrency(2);
+        let dispatcher = dispatcher(config, Duration::from_millis(20));
+
+        for i in 0..8 {
+            dispatcher.dispatch(blocked(&format!("10.0.0.{}", i), 1));
+        }
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(dispatcher.stats().delivered, 8);
+        assert_eq!(
+            dispatcher.on_blocked().max_running.load(Ordering::SeqCst),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_drops_newest() {
+        let config = DispatchConfig::new().with_queue_capacity(2);
+        let dispatcher = dispatcher(config, Duration::ZERO);
+
+        // The worker can't run until this task yields, so the queue fills up
+        for i in 0..5 {
+            dispatcher.dispatch(blocked(&format!("10.0.0.{}", i), 1));
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let stats = dispatcher.stats();
+        assert_eq!(stats.delivered, 2);
+        assert_eq!(stats.dropped, 3);
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_keeps_latest_event_per_key() {
+        let config = DispatchConfig::new().with_overflow_policy(OverflowPolicy::Coalesce);
+        let dispatcher = dispatcher(config, Duration::ZERO);
+
+        for strikes in 1..=5 {
+            dispatcher.dispatch(blocked("10.0.0.1", strikes));
+        }
+        dispatcher.dispatch(blocked("10.0.0.2", 1));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let stats = dispatcher.stats();
+        assert_eq!(stats.delivered, 2);
+        assert_eq!(stats.coalesced, 4);
+        assert_eq!(stats.dropped, 0);
+        assert_eq!(
+            *dispatcher.on_blocked().strikes_seen.lock().unwrap(),
+            vec![5, 1]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_slow_callback_times_out() {
+        let config = DispatchConfig::new().with_callback_timeout(Duration::from_millis(20));
+        let dispatcher = dispatcher(config, Duration::from_secs(5));
+
+        dispatcher.dispatch(blocked("10.0.0.1", 1));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let stats = dispatcher.stats();
+        assert_eq!(stats.timed_out, 1);
+        assert_eq!(stats.delivered, 0);
+    }
+
+    #[test]
+    fn test_dispatch_without_runtime_counts_drops() {
+        let dispatcher = dispatcher(DispatchConfig::default(), Duration::ZERO);
+
+        dispatcher.dispatch(blocked("10.0.0.1", 1));
+        dispatcher.dispatch(blocked("10.0.0.2", 1));
+        assert_eq!(
+            dispatcher.stats(),
+            DispatchStats {
+                dropped: 2,
+                ..Default::default()
+            }
+        );
+        assert!(dispatcher.shared.no_runtime_warned.load(Ordering::Relaxed));
+    }
+}
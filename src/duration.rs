@@ -0,0 +1,209 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Human-friendly durations for configuration files and environment variables.
+//!
+//! Accepts a bare number of seconds (`900`) or one or more `<number><unit>`
+//! components (`15m`, `1h30m`, `500ms`), where the unit is `ms`, `s`, `m`, `h`
+//! or `d`.
+
+use crate::config::ConfigError;
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+/// Longest accepted block duration, 100 years. Anything longer is a mistake,
+/// and the bound keeps every block expiry within what chrono can represent.
+pub(crate) const MAX_BLOCK_DURATION: Duration = Duration::from_secs(100 * 365 * 86_400);
+
+/// Rejects a zero block duration or one longer than `MAX_BLOCK_DURATION`.
+pub(crate) fn validate_block_duration(field: &str, duration: Duration) -> Result<(), ConfigError> {
+    if duration.is_zero() {
+        return Err(ConfigError::invalid(field, "must be greater than 0"));
+    }
+    if duration > MAX_BLOCK_DURATION {
+        return Err(ConfigError::invalid(
+            field,
+            format!(
+                "must be at most {}, got {}",
+                format(&MAX_BLOCK_DURATION),
+                format(&duration)
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// `duration` as a chrono duration, saturating instead of failing.
+pub(crate) fn to_chrono(duration: Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).unwrap_or(chrono::Duration::MAX)
+}
+
+/// `now + duration`, saturating at the latest time chrono can represent.
+pub(crate) fn expiry(now: DateTime<Utc>, duration: Duration) -> DateTime<Utc> {
+    now.checked_add_signed(to_chrono(duration))
+        .unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+pub(crate) fn parse(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("empty duration".to_string());
+    }
+
+    if let Ok(seconds) = input.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+
+    let mut total = Duration::ZERO;
+    let mut rest = input;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(format!("invalid duration '{}': expected a number", input));
+        }
+        let value: u64 = rest[..digits]
+            .parse()
+            .map_err(|_| format!("invalid duration '{}': number too large", input))?;
+        rest = &rest[digits..];
+
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let too_large = || format!("invalid duration '{}': number too large", input);
+        let seconds = |multiplier: u64| {
+            value
+                .checked_mul(multiplier)
+                .map(Duration::from_secs)
+                .ok_or_else(too_large)
+        };
+        let component = match &rest[..unit_len] {
+            "ms" => Duration::from_millis(value),
+            "s" => Duration::from_secs(value),
+            "m" => seconds(60)?,
+            "h" => seconds(60 * 60)?,
+            "d" => seconds(60 * 60 * 24)?,
+            unit => {
+                return Err(format!(
+                    "invalid duration '{}': unknown unit '{}' (expected ms, s, m, h or d)",
+                    input, unit
+                ))
+            }
+        };
+        total = total.checked_add(component).ok_or_else(too_large)?;
+        rest = &rest[unit_len..];
+    }
+
+    Ok(total)
+}
+
+pub(crate) fn format(duration: &Duration) -> String {
+    let millis = duration.as_millis();
+    if !millis.is_multiple_of(1000) {
+        return format!("{}ms", millis);
+    }
+
+    let seconds = duration.as_secs();
+    match seconds {
+        0 => "0s".to_string(),
+        s if s.is_multiple_of(86_400) => format!("{}d", s / 86_400),
+        s if s.is_multiple_of(3_600) => format!("{}h", s / 3_600),
+        s if s.is_multiple_of(60) => format!("{}m", s / 60),
+        s => format!("{}s", s),
+    }
+}
+
+/// `#[serde(with = "crate::duration::serde_format")]` adapter.
+#[cfg(feature = "serde")]
+pub(crate) mod serde_format {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Seconds(u64),
+        Text(String),
+    }
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&super::format(duration))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        match Raw::deserialize(deserializer)? {
+            Raw::Seconds(seconds) => Ok(Duration::from_secs(seconds)),
+            Raw::Text(text) => super::parse(&text).map_err(de::Error::custom),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_units() {
+        assert_eq!(parse("900").unwrap(), Duration::from_secs(900));
+        assert_eq!(parse("15m").unwrap(), Duration::from_secs(900));
+        assert_eq!(parse("1h30m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse("2d").unwrap(), Duration::from_secs(172_800));
+    }
+
+    #[test]
+    fn test_parse_rejects_garbage() {
+        assert!(parse("").is_err());
+        assert!(parse("15 minutes").is_err());
+        assert!(parse("m15").is_err());
+        assert!(parse("10y").is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_overflow() {
+        for text in [
+            "999999999999999999d",
+            "18446744073709551615m",
+            "18446744073709551615s1s",
+        ] {
+            let err = parse(text).unwrap_err();
+            assert!(err.contains("number too large"), "{}: {}", text, err);
+        }
+        assert!(parse("18446744073709551615s").is_ok());
+    }
+
+    #[test]
+    fn test_block_duration_bounds() {
+        assert!(validate_block_duration("d", Duration::from_secs(60)).is_ok());
+        assert!(validate_block_duration("d", MAX_BLOCK_DURATION).is_ok());
+        assert!(validate_block_duration("d", Duration::ZERO).is_err());
+        let huge = parse("100000000d").unwrap();
+        assert!(validate_block_duration("d", huge).is_err());
+
+        // Expiries saturate rather than overflow
+        assert_eq!(expiry(Utc::now(), Duration::MAX), DateTime::<Utc>::MAX_UTC);
+        assert_eq!(to_chrono(Duration::MAX), chrono::Duration::MAX);
+    }
+
+    #[test]
+    fn test_format_round_trips() {
+        for text in ["15m", "1h", "90s", "250ms", "1d", "0s"] {
+            assert_eq!(format(&parse(text).unwrap()), text);
+        }
+    }
+}
@@ -0,0 +1,258 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+#[cfg(feature = "metrics")]
+use crate::metrics::MetricsSink;
+use crate::rules::RuleAction;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+/// A decision made by `rate_limit_middleware`, published to every
+/// `RateLimiter::subscribe` receiver.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LimiterEvent {
+    /// The request passed rate limiting and screening.
+    Allowed {
+        key: String,
+        path: String,
+        /// Token balance after the request was charged
+        tokens: f64,
+    },
+    /// The key was rejected by the rate limit. `newly_blocked` is set for the
+    /// request that started the block.
+    Blocked {
+        key: String,
+        path: String,
+        newly_blocked: bool,
+        dry_run: bool,
+    },
+    /// The request matched a screening pattern.
+    Screened {
+        key: String,
+        path: String,
+        user_agent: String,
+        /// Description of the matched pattern, as in `ScreeningReason`'s `Display`
+        reason: String,
+        /// IDs of every matched rule
+        rule_ids: Vec<String>,
+        /// The action taken, or that would have been taken in dry run
+        action: RuleAction,
+        dry_run: bool,
+    },
+    /// The request was for a honeypot decoy path. `newly_blocked` is unset
+    /// when the key was already banned by the honeypot.
+    Honeypot {
+        key: String,
+        path: String,
+        user_agent: String,
+        newly_blocked: bool,
+        dry_run: bool,
+    },
+    /// Tokens were returned for an authenticated or `304 Not Modified` response.
+    Refunded {
+        key: String,
+        path: String,
+        amount: f64,
+        tokens: f64,
+    },
+    /// Extra tokens were charged for an error response.
+    Penalized {
+        key: String,
+        path: String,
+        status: u16,
+        amount: f64,
+        tokens: f64,
+    },
+}
+
+impl LimiterEvent {
+    /// The rate limit key (client IP) the event is about.
+    pub fn key(&self) -> &str {
+        match self {
+            LimiterEvent::Allowed { key, .. }
+            | LimiterEvent::Blocked { key, .. }
+            | LimiterEvent::Screened { key, .. }
+            | LimiterEvent::Honeypot { key, .. }
+            | LimiterEvent::Refunded { key, .. }
+            | LimiterEvent::Penalized { key, .. } => key,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            LimiterEvent::Allowed { path, .. }
+            | LimiterEvent::Blocked { path, .. }
+            | LimiterEvent::Screened { path, .. }
+            | LimiterEvent::Honeypot { path, .. }
+            | LimiterEvent::Refunded { path, .. }
+            | LimiterEvent::Penalized { path, .. } => path,
+        }
+    }
+}
+
+/// Sending half of the event stream, shared by every clone of a limiter.
+pub(crate) struct EventStream {
+    sender: broadcast::Sender<LimiterEvent>,
+    lagged: Arc<AtomicU64>,
+    #[cfg(feature = "metrics")]
+    metrics: MetricsSink,
+}
+
+impl EventStream {
+    pub(crate) fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        Self {
+            sender,
+            lagged: Arc::new(AtomicU64::new(0)),
+            #[cfg(feature = "metrics")]
+            metrics: MetricsSink::default(),
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) fn with_metrics_sink(mut self, metrics: MetricsSink) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Publish the event built by `event`, which is only called when someone
+    /// is subscribed.
+    pub(crate) fn emit(&self, event: impl FnOnce() -> LimiterEvent) {
+        if self.sender.receiver_count() > 0 {
+            // Only fails when every receiver was dropped since the check
+            let _ = self.sender.send(event());
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> EventReceiver {
+        EventReceiver {
+            receiver: self.sender.subscribe(),
+            lagged: 0,
+            total_lagged: self.lagged.clone(),
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics.clone(),
+        }
+    }
+
+    pub(crate) fn lagged(&self) -> u64 {
+        self.lagged.load(Ordering::Relaxed)
+    }
+}
+
+/// Receiving half of the event stream returned by `RateLimiter::subscribe`.
+///
+/// The stream is bounded. A consumer that falls more than the channel
+/// capacity behind skips the oldest events; the number skipped is reported by
+/// `lagged`, summed across receivers by `RateLimiter::events_lagged`, and
+/// counted in `rate_limit_events_lagged_total` with the `metrics` feature.
+pub struct EventReceiver {
+    receiver: broadcast::Receiver<LimiterEvent>,
+    lagged: u64,
+    total_lagged: Arc<AtomicU64>,
+    #[cfg(feature = "metrics")]
+    metrics: MetricsSink,
+}
+
+impl EventReceiver {
+    /// Wait for the next event. Returns `None` once every clone of the
+    /// limiter has been dropped.
+    pub async fn recv(&mut self) -> Option<LimiterEvent> {
+        loop {
+            match self.receiver.recv().await {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(skipped)) => self.record_lag(skipped),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Return the next event if one is ready, without waiting.
+    pub fn try_recv(&mut self) -> Option<LimiterEvent> {
+        use broadcast::error::TryRecvError;
+
+        loop {
+            match self.receiver.try_recv() {
+                Ok(event) => return Some(event),
+                Err(TryRecvError::Lagged(skipped)) => self.record_lag(skipped),
+                Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => return None,
+            }
+        }
+    }
+
+    /// Number of events this receiver skipped because it fell behind.
+    pub fn lagged(&self) -> u64 {
+        self.lagged
+    }
+
+    fn record_lag(&mut self, skipped: u64) {
+        self.lagged += skipped;
+        self.total_lagged.fetch_add(skipped, Ordering::Relaxed);
+        tracing::warn!("Event subscriber fell behind; skipped {} events", skipped);
+        #[cfg(feature = "metrics")]
+        self.metrics.record_events_lagged(skipped);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(n: usize) -> LimiterEvent {
+        LimiterEvent::Allowed {
+            key: "10.0.0.1".to_string(),
+            path: format!("/{}", n),
+            tokens: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_emit_without_subscribers_skips_event() {
+        let stream = EventStream::new(4);
+        stream.emit(|| panic!("event built with no subscribers"));
+    }
+
+    #[test]
+    fn test_slow_receiver_counts_lag() {
+        let stream = EventStream::new(2);
+        let mut slow = stream.subscribe();
+        let mut fast = stream.subscribe();
+
+        for n in 0..5 {
+            stream.emit(|| allowed(n));
+            assert_eq!(fast.try_recv(), Some(allowed(n)));
+        }
+
+        assert_eq!(slow.try_recv(), Some(allowed(3)));
+        assert_eq!(slow.try_recv(), Some(allowed(4)));
+        assert_eq!(slow.try_recv(), None);
+        assert_eq!(slow.lagged(), 3);
+        assert_eq!(fast.lagged(), 0);
+        assert_eq!(stream.lagged(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_recv_ends_when_stream_dropped() {
+        let stream = EventStream::new(4);
+        let mut receiver = stream.subscribe();
+        stream.emit(|| allowed(0));
+        drop(stream);
+
+        assert_eq!(receiver.recv().await, Some(allowed(0)));
+        assert_eq!(receiver.recv().await, None);
+    }
+}
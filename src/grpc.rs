@@ -0,0 +1,444 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Rate limiting for tonic gRPC services.
+//!
+//! [`GrpcRateLimitLayer`] applies the same per-IP token bucket and screening
+//! as `rate_limit_middleware`, but reports rejections as gRPC statuses:
+//! `RESOURCE_EXHAUSTED` (with `RetryInfo` details) for rate limited clients and
+//! `PERMISSION_DENIED` for screened requests.
+
+use crate::{
+    context::{extract_client_ip, sanitize_user_agent, IpExtractionStrategy},
+    limiter::RateLimiter,
+    rules::RuleAction,
+    screener::ScreeningRequest,
+    types::{BlockReason, OnBlocked, SecurityContext},
+};
+use axum::http;
+use chrono::Utc;
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tonic::{transport::server::TcpConnectInfo, Code, Status};
+use tonic_types::{ErrorDetails, StatusExt};
+use tower::{Layer, Service};
+
+/// Tower layer that rate limits tonic gRPC requests.
+///
+/// The client IP is taken from the TCP remote address (`IpExtractionStrategy::SocketAddr`)
+/// or from a metadata key (`IpExtractionStrategy::ForwardedHeader`). gRPC metadata is
+/// carried in HTTP/2 headers, so the same proxy depth validation applies.
+///
+/// # Example
+/// ```rust,ignore
+/// use basic_axum_rate_limit::{GrpcRateLimitLayer, IpExtractionStrategy};
+///
+/// Server::builder()
+///     .layer(GrpcRateLimitLayer::new(rate_limiter, IpExtractionStrategy::SocketAddr))
+///     .add_service(GreeterServer::new(greeter))
+///     .serve(addr)
+///     .await?;
+/// ```
+pub struct GrpcRateLimitLayer<B: OnBlocked> {
+    limiter: RateLimiter<B>,
+    ip_extraction: IpExtractionStrategy,
+}
+
+impl<B: OnBlocked + 'static> GrpcRateLimitLayer<B> {
+    pub fn new(limiter: RateLimiter<B>, ip_extraction: IpExtractionStrategy) -> Self {
+        Self {
+            limiter,
+            ip_extraction,
+        }
+    }
+}
+
+impl<B: OnBlocked> Clone for GrpcRateLimitLayer<B> {
+    fn clone(&self) -> Self {
+        Self {
+            limiter: self.limiter.clone(),
+            ip_extraction: self.ip_extraction.clone(),
+        }
+    }
+}
+
+impl<S, B: OnBlocked> Layer<S> for GrpcRateLimitLayer<B> {
+    type Service = GrpcRateLimitService<S, B>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcRateLimitService {
+            inner,
+            limiter: self.limiter.clone(),
+            ip_extraction: self.ip_extraction.clone(),
+        }
+    }
+}
+
+/// Service produced by [`GrpcRateLimitLayer`].
+pub struct GrpcRateLimitService<S, B: OnBlocked> {
+    inner: S,
+    limiter: RateLimiter<B>,
+    ip_extraction: IpExtractionStrategy,
+}
+
+impl<S: Clone, B: OnBlocked> Clone for GrpcRateLimitService<S, B> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            limiter: self.limiter.clone(),
+            ip_extraction: self.ip_extraction.clone(),
+        }
+    }
+}
+
+impl<S, B, ReqBody, ResBody> Service<http::Request<ReqBody>> for GrpcRateLimitService<S, B>
+where
+    S: Service<http::Request<ReqBody>, Response = http::Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    B: OnBlocked + 'static,
+    ReqBody: Send + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: http::Request<ReqBody>) -> Self::Future {
+        // Take the service that was driven to readiness and leave a fresh clone behind
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let limiter = self.limiter.clone();
+        let ip_extraction = self.ip_extraction.clone();
+
+        Box::pin(async move {
+            let security_context = match grpc_security_context(&ip_extraction, &request) {
+                Ok(ctx) => ctx,
+                Err(status) => return Ok(status.into_http()),
+            };
+
+            let path = request.uri().path().to_string();
+            let rate_limit_key = security_context.ip_address.clone();
+
+            let (is_allowed, newly_blocked, _tokens) = limiter
+                .check_rate_limit(&rate_limit_key, &security_context, &path)
+                .await;
+
+            let (config, screener) = limiter.settings();
+            let dry_run = config.dry_run;
+
+            if !is_allowed && dry_run {
+                tracing::warn!(
+                    "Dry run: gRPC call would have been rate limited: {} (gRPC method: {})",
+                    security_context.ip_address,
+                    &path
+                );
+                limiter.telemetry().record_dry_run(if newly_blocked {
+                    "block"
+                } else {
+                    "rate_limited"
+                });
+            } else if !is_allowed {
+                if newly_blocked {
+                    tracing::warn!(
+                        "IP blocked for rate limiting: {} (gRPC method: {})",
+                        security_context.ip_address,
+                        &path
+                    );
+                    limiter.telemetry().record_block(&rate_limit_key);
+                } else {
+                    tracing::debug!(
+                        "Blocked IP attempted gRPC call: {}",
+                        security_context.ip_address
+                    );
+                }
+
+                return Ok(resource_exhausted(&limiter, &rate_limit_key).into_http());
+            }
+
+            if let Some(screener) = screener {
+                if let Some(result) = screener.check_request(
+                    &ScreeningRequest::from_request(&request, &security_context.user_agent)
+                        .with_key(&rate_limit_key),
+                ) {
+                    if !result.action.rejects() {
+                        tracing::warn!(
+                            "Screening rule {} matched gRPC call (log only): {} from {} (user-agent: {}, reason: {})",
+                            result.rule_id,
+                            path,
+                            security_context.ip_address,
+                            security_context.user_agent,
+                            result.reason
+                        );
+                    } else if dry_run || screener.is_dry_run() {
+                        tracing::warn!(
+                            "Dry run: gRPC call would have been screened by rule {} ({}): {} from {} (user-agent: {}, reason: {})",
+                            result.rule_id,
+                            result.action,
+                            path,
+                            security_context.ip_address,
+                            security_context.user_agent,
+                            result.reason
+                        );
+                        if !screener.is_dry_run() && result.action == RuleAction::BlockIp {
+                            limiter.block_with_reason(
+                                &rate_limit_key,
+                                &security_context,
+                                &path,
+                                BlockReason::Screening,
+                                Some(result.reason.pattern()),
+                            );
+                        }
+                        limiter.telemetry().record_dry_run("screening");
+                    } else {
+                        tracing::warn!(
+                            "Malicious gRPC call screened by rule {} ({}): {} from {} (user-agent: {}, reason: {})",
+                            result.rule_id,
+                            result.action,
+                            path,
+                            security_context.ip_address,
+                            security_context.user_agent,
+                            result.reason
+                        );
+
+                        match result.action {
+                            RuleAction::BlockIp => limiter.block_with_reason(
+                                &rate_limit_key,
+                                &security_context,
+                                &path,
+                                BlockReason::Screening,
+                                Some(result.reason.pattern()),
+                            ),
+                            RuleAction::Tarpit { delay } => tokio::time::sleep(delay).await,
+                            RuleAction::RejectOnly | RuleAction::LogOnly | RuleAction::Score(_) => {
+                            }
+                        }
+
+                        limiter
+                            .telemetry()
+                            .record_screening_block(&rate_limit_key, &result.rule_id);
+
+                        return Ok(Status::permission_denied("request rejected").into_http());
+                    }
+                }
+            }
+
+            request.extensions_mut().insert(security_context);
+            inner.call(request).await
+        })
+    }
+}
+
+/// Builds a `RESOURCE_EXHAUSTED` status carrying a `RetryInfo` detail with the
+/// remaining block time for `key`.
+fn resource_exhausted<B: OnBlocked + 'static>(limiter: &RateLimiter<B>, key: &str) -> Status {
+    let retry_delay = limiter.blocked_until(key).and_then(|blocked_until| {
+        blocked_until
+            .signed_duration_since(Utc::now())
+            .to_std()
+            .ok()
+    });
+
+    Status::with_error_details(
+        Code::ResourceExhausted,
+        "rate limit exceeded",
+        ErrorDetails::with_retry_info(retry_delay),
+    )
+}
+
+fn grpc_security_context<ReqBody>(
+    strategy: &IpExtractionStrategy,
+    request: &http::Request<ReqBody>,
+) -> Result<SecurityContext, Status> {
+    let socket_ip = request
+        .extensions()
+        .get::<TcpConnectInfo>()
+        .and_then(|info| info.remote_addr())
+        .map(|addr| addr.ip());
+
+    let ip_address = match (strategy, socket_ip) {
+        (IpExtractionStrategy::SocketAddr, None) => {
+            tracing::error!("Remote address not found in gRPC request extensions. The server must be built with tonic's TCP incoming stream.");
+            return Err(Status::internal("client address unavailable"));
+        }
+        (_, socket_ip) => {
+            let socket_ip = socket_ip.unwrap_or(std::net::Ipv4Addr::UNSPECIFIED.into());
+            extract_client_ip(strategy, request.headers(), socket_ip).map_err(|e| {
+                tracing::warn!("IP extraction failed: {}", e);
+                Status::invalid_argument(e.to_string())
+            })?
+        }
+    };
+
+    let user_agent = request
+        .headers()
+        .get("user-agent")
+        .and_then(|h| h.to_str().ok())
+        .map(sanitize_user_agent)
+        .unwrap_or_default();
+
+    Ok(SecurityContext::new(ip_address.to_string(), user_agent))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        config::RateLimitConfig,
+        screener::{RequestScreener, ScreeningConfig},
+        types::NoOpOnBlocked,
+    };
+    use std::{convert::Infallible, net::SocketAddr, time::Duration};
+    use tower::{service_fn, ServiceExt};
+
+    type TestService = tower::util::BoxCloneService<
+        http::Request<()>,
+        http::Response<tonic::body::Body>,
+        Infallible,
+    >;
+
+    fn grpc_service(
+        limiter: RateLimiter<NoOpOnBlocked>,
+        strategy: IpExtractionStrategy,
+    ) -> TestService {
+        let inner = service_fn(|request: http::Request<()>| async move {
+            assert!(request.extensions().get::<SecurityContext>().is_some());
+            Ok::<_, Infallible>(http::Response::new(tonic::body::Body::default()))
+        });
+        tower::util::BoxCloneService::new(GrpcRateLimitLayer::new(limiter, strategy).layer(inner))
+    }
+
+    fn grpc_request(path: &str, ip: &str) -> http::Request<()> {
+        http::Request::builder()
+            .uri(path)
+            .header("x-forwarded-for", ip)
+            .body(())
+            .unwrap()
+    }
+
+    fn status_code(response: &http::Response<tonic::body::Body>) -> Code {
+        Status::from_header_map(response.headers())
+            .map(|status| status.code())
+            .unwrap_or(Code::Ok)
+    }
+
+    #[tokio::test]
+    async fn test_resource_exhausted_with_retry_info() {
+        let config = RateLimitConfig::new(2, Duration::from_secs(60)).with_grace_period(0);
+        let service = grpc_service(
+            RateLimiter::new(config, NoOpOnBlocked),
+            IpExtractionStrategy::x_forwarded_for(1),
+        );
+
+        for _ in 0..2 {
+            let response = service
+                .clone()
+                .oneshot(grpc_request("/greeter.Greeter/SayHello", "10.1.0.1"))
+                .await
+                .unwrap();
+            assert_eq!(status_code(&response), Code::Ok);
+        }
+
+        let response = service
+            .clone()
+            .oneshot(grpc_request("/greeter.Greeter/SayHello", "10.1.0.1"))
+            .await
+            .unwrap();
+        let status = Status::from_header_map(response.headers()).unwrap();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+
+        let retry_info = status.get_details_retry_info().unwrap();
+        let retry_delay = retry_info.retry_delay.unwrap();
+        assert!(retry_delay > Duration::from_secs(50));
+        assert!(retry_delay <= Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_screened_call_is_permission_denied() {
+        let config = RateLimitConfig::new(50, Duration::from_secs(60)).with_grace_period(0);
+        let screener =
+            RequestScreener::new(&ScreeningConfig::new().with_path_pattern(r"^/grpc\.reflection"))
+                .unwrap();
+        let service = grpc_service(
+            RateLimiter::new(config, NoOpOnBlocked).with_screener(screener),
+            IpExtractionStrategy::x_forwarded_for(1),
+        );
+
+        let response = service
+            .clone()
+            .oneshot(grpc_request(
+                "/grpc.reflection.v1.ServerReflection/ServerReflectionInfo",
+                "10.1.0.2",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(status_code(&response), Code::PermissionDenied);
+
+        // Screening blocks the IP, so further calls are rate limited
+        let response = service
+            .oneshot(grpc_request("/greeter.Greeter/SayHello", "10.1.0.2"))
+            .await
+            .unwrap();
+        assert_eq!(status_code(&response), Code::ResourceExhausted);
+    }
+
+    #[tokio::test]
+    async fn test_remote_addr_strategy() {
+        let config = RateLimitConfig::new(1, Duration::from_secs(60)).with_grace_period(0);
+        let service = grpc_service(
+            RateLimiter::new(config, NoOpOnBlocked),
+            IpExtractionStrategy::SocketAddr,
+        );
+
+        let remote_addr: SocketAddr = "10.1.0.3:50051".parse().unwrap();
+        let mut request = grpc_request("/greeter.Greeter/SayHello", "10.1.0.99");
+        request.extensions_mut().insert(TcpConnectInfo {
+            local_addr: None,
+            remote_addr: Some(remote_addr),
+        });
+        let response = service.clone().oneshot(request).await.unwrap();
+        assert_eq!(status_code(&response), Code::Ok);
+
+        // Without connect info the client address cannot be determined
+        let response = service
+            .oneshot(grpc_request("/greeter.Greeter/SayHello", "10.1.0.3"))
+            .await
+            .unwrap();
+        assert_eq!(status_code(&response), Code::Internal);
+    }
+
+    #[tokio::test]
+    async fn test_missing_metadata_is_invalid_argument() {
+        let config = RateLimitConfig::new(5, Duration::from_secs(60));
+        let service = grpc_service(
+            RateLimiter::new(config, NoOpOnBlocked),
+            IpExtractionStrategy::custom_header("x-client-ip", 1),
+        );
+
+        let response = service
+            .oneshot(grpc_request("/greeter.Greeter/SayHello", "10.1.0.4"))
+            .await
+            .unwrap();
+        assert_eq!(status_code(&response), Code::InvalidArgument);
+    }
+}
@@ -0,0 +1,444 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Memory-bounded tracking of the keys consuming the most capacity.
+//!
+//! Each interval keeps a space-saving summary per signal: a fixed number of
+//! counters, where a new key evicts the smallest counter and inherits its
+//! count. Counts are therefore upper bounds, overestimating by at most the
+//! reported `error`, and every key with a true count above
+//! `total / capacity` is guaranteed to be present.
+//!
+//! Large trackers split their counters across shards by key hash so that
+//! recording only locks the shard owning the key. A key is then guaranteed
+//! to be present once it exceeds its shard's total divided by the shard's
+//! capacity, which is close to `total / capacity` when traffic spreads
+//! across many keys.
+
+use crate::config::ConfigError;
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::hash::BuildHasher;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The signal heavy hitters are ranked by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum HeavyHitterKind {
+    /// Every request seen by `rate_limit_middleware`, including rejected ones
+    Requests,
+    /// Error penalties applied
+    Penalties,
+    /// Screening matches
+    ScreeningHits,
+}
+
+/// A key's estimated count over the tracked interval.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Offender {
+    pub key: String,
+    /// Estimated count; never lower than the true count
+    pub count: u64,
+    /// Maximum overestimate included in `count`
+    pub error: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct HeavyHitterConfig {
+    /// Counters kept per signal per interval; bounds memory and accuracy
+    pub capacity: usize,
+    /// Length of one interval
+    pub interval: Duration,
+    /// Number of intervals reported together, e.g. 5 one-minute intervals
+    /// for a sliding five-minute view
+    pub intervals: usize,
+}
+
+impl Default for HeavyHitterConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1000,
+            interval: Duration::from_secs(60),
+            intervals: 5,
+        }
+    }
+}
+
+impl HeavyHitterConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_intervals(mut self, intervals: usize) -> Self {
+        self.intervals = intervals.max(1);
+        self
+    }
+
+    /// Length of the whole tracked window, `interval * intervals`, saturating
+    /// at `Duration::MAX`.
+    pub fn window(&self) -> Duration {
+        u32::try_from(self.intervals)
+            .ok()
+            .and_then(|intervals| self.interval.checked_mul(intervals))
+            .unwrap_or(Duration::MAX)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.capacity == 0 {
+            return Err(ConfigError::invalid("capacity", "must be greater than 0"));
+        }
+        if self.interval.is_zero() {
+            return Err(ConfigError::invalid("interval", "must be greater than 0"));
+        }
+        if self.intervals == 0 {
+            return Err(ConfigError::invalid("intervals", "must be greater than 0"));
+        }
+        Ok(())
+    }
+}
+
+/// Space-saving summary over at most `capacity` keys.
+pub(crate) struct SpaceSaving {
+    capacity: usize,
+    counters: HashMap<Arc<str>, (u64, u64)>,
+    /// (count, key), ordered so the smallest counter is first. Shares each
+    /// key's allocation with `counters`.
+    order: BTreeSet<(u64, Arc<str>)>,
+}
+
+impl SpaceSaving {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            counters: HashMap::new(),
+            order: BTreeSet::new(),
+        }
+    }
+
+    pub(crate) fn observe(&mut self, key: &str, weight: u64) {
+        if let Some((stored, &(count, _))) = self.counters.get_key_value(key) {
+            let stored = Arc::clone(stored);
+            self.order.remove(&(count, Arc::clone(&stored)));
+            self.order.insert((count + weight, stored));
+            if let Some((count, _)) = self.counters.get_mut(key) {
+                *count += weight;
+            }
+            return;
+        }
+
+        let (count, error) = if self.counters.len() < self.capacity {
+            (weight, 0)
+        } else {
+            let (min, evicted) = self.order.pop_first().expect("capacity is never zero");
+            self.counters.remove(&evicted);
+            (min + weight, min)
+        };
+        let key: Arc<str> = Arc::from(key);
+        self.counters.insert(Arc::clone(&key), (count, error));
+        self.order.insert((count, key));
+    }
+
+    /// Lower bound on how often `key` was observed (count minus error), or 0
+    /// if it is not tracked.
+    #[cfg(any(test, feature = "metrics"))]
+    pub(crate) fn guaranteed(&self, key: &str) -> u64 {
+        self.counters
+            .get(key)
+            .map_or(0, |(count, error)| count - error)
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (&Arc<str>, &(u64, u64))> {
+        self.counters.iter()
+    }
+}
+
+struct Interval {
+    started: Instant,
+    summaries: [SpaceSaving; 3],
+}
+
+impl Interval {
+    fn new(started: Instant, capacity: usize) -> Self {
+        Self {
+            started,
+            summaries: [
+                SpaceSaving::new(capacity),
+                SpaceSaving::new(capacity),
+                SpaceSaving::new(capacity),
+            ],
+        }
+    }
+}
+
+fn index(kind: HeavyHitterKind) -> usize {
+    match kind {
+        HeavyHitterKind::Requests => 0,
+        HeavyHitterKind::Penalties => 1,
+        HeavyHitterKind::ScreeningHits => 2,
+    }
+}
+
+/// Trackers only shard once each shard keeps at least this many counters,
+/// so small trackers stay as accurate as a single summary
+const MIN_SHARD_CAPACITY: usize = 64;
+const MAX_SHARDS: usize = 16;
+
+type Shard = Mutex<VecDeque<Interval>>;
+
+/// Tracks the heaviest keys per `HeavyHitterKind` over a sliding window of
+/// intervals. Enable with `RateLimiter::with_heavy_hitters`.
+pub struct HeavyHitterTracker {
+    config: HeavyHitterConfig,
+    /// Each key always hashes to the same shard, so shards hold disjoint keys
+    shards: Vec<Shard>,
+    shard_capacity: usize,
+    hasher: RandomState,
+}
+
+impl HeavyHitterTracker {
+    /// Fails if `config` does not pass `HeavyHitterConfig::validate`.
+    pub fn new(config: HeavyHitterConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+        let shards = (config.capacity / MIN_SHARD_CAPACITY).clamp(1, MAX_SHARDS);
+        Ok(Self {
+            shard_capacity: config.capacity.div_ceil(shards),
+            shards: (0..shards).map(|_| Mutex::new(VecDeque::new())).collect(),
+            hasher: RandomState::new(),
+            config,
+        })
+    }
+
+    pub fn config(&self) -> &HeavyHitterConfig {
+        &self.config
+    }
+
+    pub fn record(&self, kind: HeavyHitterKind, key: &str) {
+        self.record_at(kind, key, Instant::now());
+    }
+
+    /// The `n` keys with the highest estimated counts over the tracked window,
+    /// largest first.
+    pub fn top(&self, kind: HeavyHitterKind, n: usize) -> Vec<Offender> {
+        self.top_at(kind, n, Instant::now())
+    }
+
+    fn record_at(&self, kind: HeavyHitterKind, key: &str, now: Instant) {
+        let mut intervals = lock(self.shard(key));
+        let starts_new = intervals
+            .back()
+            .is_none_or(|current| now.duration_since(current.started) >= self.config.interval);
+        if starts_new {
+            intervals.push_back(Interval::new(now, self.shard_capacity));
+            while intervals.len() > self.config.intervals {
+                intervals.pop_front();
+            }
+        }
+
+        let current = intervals.back_mut().expect("an interval was just ensured");
+        current.summaries[index(kind)].observe(key, 1);
+    }
+
+    fn top_at(&self, kind: HeavyHitterKind, n: usize, now: Instant) -> Vec<Offender> {
+        let window = self.config.window();
+
+        // Shards hold disjoint keys, so the overall top `n` is among the top
+        // `n` of each shard, and only one shard is locked at a time
+        let mut offenders = Vec::new();
+        for shard in &self.shards {
+            let intervals = lock(shard);
+            let mut merged: HashMap<&str, (u64, u64)> = HashMap::new();
+            for interval in intervals
+                .iter()
+                .filter(|interval| now.duration_since(interval.started) < window)
+            {
+                for (key, (count, error)) in interval.summaries[index(kind)].entries() {
+                    let entry = merged.entry(key).or_default();
+                    entry.0 += count;
+                    entry.1 += error;
+                }
+            }
+
+            let mut shard_top: Vec<Offender> = merged
+                .into_iter()
+                .map(|(key, (count, error))| Offender {
+                    key: key.to_string(),
+                    count,
+                    error,
+                })
+                .collect();
+            rank(&mut shard_top, n);
+            offenders.append(&mut shard_top);
+        }
+        rank(&mut offenders, n);
+        offenders
+    }
+
+    fn shard(&self, key: &str) -> &Shard {
+        let hash = self.hasher.hash_one(key);
+        &self.shards[(hash % self.shards.len() as u64) as usize]
+    }
+}
+
+/// Sort largest first, ties by key, and keep the first `n`.
+fn rank(offenders: &mut Vec<Offender>, n: usize) {
+    offenders.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+    offenders.truncate(n);
+}
+
+fn lock(shard: &Shard) -> std::sync::MutexGuard<'_, VecDeque<Interval>> {
+    shard
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_space_saving_keeps_heavy_keys_under_churn() {
+        let mut summary = SpaceSaving::new(10);
+        for i in 0..1000 {
+            summary.observe("heavy", 1);
+            summary.observe(&format!("scan-{}", i), 1);
+        }
+
+        assert!(summary.counters.len() <= 10);
+        let (count, error) = summary.counters["heavy"];
+        assert!(count >= 1000, "count is an upper bound");
+        assert!(count - error <= 1000, "count minus error is a lower bound");
+        assert!(summary.guaranteed("heavy") > summary.guaranteed("scan-999"));
+    }
+
+    #[test]
+    fn test_top_ranks_by_kind() {
+        let tracker = HeavyHitterTracker::new(HeavyHitterConfig::new().with_capacity(50)).unwrap();
+        for _ in 0..5 {
+            tracker.record(HeavyHitterKind::Requests, "10.0.0.1");
+        }
+        for _ in 0..3 {
+            tracker.record(HeavyHitterKind::Requests, "10.0.0.2");
+        }
+        tracker.record(HeavyHitterKind::Penalties, "10.0.0.2");
+
+        let top = tracker.top(HeavyHitterKind::Requests, 1);
+        assert_eq!(
+            top,
+            vec![Offender {
+                key: "10.0.0.1".to_string(),
+                count: 5,
+                error: 0,
+            }]
+        );
+        let penalties = tracker.top(HeavyHitterKind::Penalties, 10);
+        assert_eq!(penalties.len(), 1);
+        assert_eq!(penalties[0].key, "10.0.0.2");
+        assert!(tracker.top(HeavyHitterKind::ScreeningHits, 10).is_empty());
+    }
+
+    #[test]
+    fn test_old_intervals_slide_out() {
+        let tracker = HeavyHitterTracker::new(
+            HeavyHitterConfig::new()
+                .with_interval(Duration::from_secs(60))
+                .with_intervals(2),
+        )
+        .unwrap();
+        let start = Instant::now();
+        let minute = Duration::from_secs(60);
+
+        tracker.record_at(HeavyHitterKind::Requests, "old", start);
+        tracker.record_at(HeavyHitterKind::Requests, "new", start + minute);
+        tracker.record_at(HeavyHitterKind::Requests, "new", start + minute * 2);
+
+        let top = tracker.top_at(HeavyHitterKind::Requests, 10, start + minute * 2);
+        assert_eq!(top.len(), 1);
+        assert_eq!(top[0].key, "new");
+        assert_eq!(top[0].count, 2);
+
+        // Nothing recorded recently: everything has aged out of the window
+        assert!(tracker
+            .top_at(HeavyHitterKind::Requests, 10, start + minute * 10)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_sharded_tracker_ranks_across_shards() {
+        let tracker =
+            HeavyHitterTracker::new(HeavyHitterConfig::new().with_capacity(1000)).unwrap();
+        assert!(tracker.shards.len() > 1);
+
+        for i in 0..200u64 {
+            for _ in 0..=i % 7 {
+                tracker.record(HeavyHitterKind::Requests, &format!("10.0.0.{}", i));
+            }
+        }
+
+        let top = tracker.top(HeavyHitterKind::Requests, 5);
+        assert_eq!(top.len(), 5);
+        assert!(top.iter().all(|offender| offender.count == 7));
+        assert!(top.windows(2).all(|pair| pair[0].key < pair[1].key));
+    }
+
+    #[test]
+    fn test_config_validation() {
+        assert!(HeavyHitterConfig::new().validate().is_ok());
+        let zero_interval = HeavyHitterConfig {
+            interval: Duration::ZERO,
+            ..HeavyHitterConfig::new()
+        };
+        assert!(HeavyHitterTracker::new(zero_interval).is_err());
+        let no_intervals = HeavyHitterConfig {
+            intervals: 0,
+            ..HeavyHitterConfig::new()
+        };
+        assert!(no_intervals.validate().is_err());
+        let no_capacity = HeavyHitterConfig {
+            capacity: 0,
+            ..HeavyHitterConfig::new()
+        };
+        assert!(no_capacity.validate().is_err());
+    }
+
+    #[test]
+    fn test_window_saturates() {
+        let config = HeavyHitterConfig::new()
+            .with_interval(Duration::MAX)
+            .with_intervals(usize::MAX);
+        assert_eq!(config.window(), Duration::MAX);
+        assert_eq!(
+            HeavyHitterConfig::new().window(),
+            Duration::from_secs(5 * 60)
+        );
+    }
+}
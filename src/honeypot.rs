@@ -0,0 +1,278 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Decoy paths that no legitimate client requests. A key that touches one is
+//! banned for `HoneypotConfig::block_duration` with `BlockReason::Honeypot`.
+
+use crate::config::ConfigError;
+use crate::normalize::PathNormalization;
+use std::collections::HashSet;
+use std::time::Duration;
+
+const DEFAULT_BLOCK_DURATION: Duration = Duration::from_secs(24 * 3600);
+
+/// What a request for a decoy path receives.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum HoneypotResponse {
+    /// 418, as for screened requests
+    #[default]
+    Reject,
+    /// 200 with `body` as HTML, so the scanner cannot tell it was caught
+    FakeOk {
+        #[cfg_attr(feature = "serde", serde(default))]
+        body: String,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct HoneypotConfig {
+    /// Decoy paths, compared exactly with the normalized request path
+    pub paths: Vec<String>,
+    /// How long a key that requests a decoy is blocked (default 24 hours)
+    #[cfg_attr(feature = "serde", serde(with = "crate::duration::serde_format"))]
+    pub block_duration: Duration,
+    pub response: HoneypotResponse,
+    /// How decoys and request paths are normalized before they are compared.
+    /// Use the screener's `ScreeningConfig::path_normalization` so both see
+    /// the same path
+    pub path_normalization: PathNormalization,
+}
+
+impl Default for HoneypotConfig {
+    fn default() -> Self {
+        Self {
+            paths: Vec::new(),
+            block_duration: DEFAULT_BLOCK_DURATION,
+            response: HoneypotResponse::default(),
+            path_normalization: PathNormalization::default(),
+        }
+    }
+}
+
+impl HoneypotConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a decoy path, e.g. `/admin.php`. Call once per path.
+    pub fn with_path(mut self, path: &str) -> Self {
+        self.paths.push(path.to_string());
+        self
+    }
+
+    pub fn with_block_duration(mut self, duration: Duration) -> Self {
+        self.block_duration = duration;
+        self
+    }
+
+    pub fn with_response(mut self, response: HoneypotResponse) -> Self {
+        self.response = response;
+        self
+    }
+
+    /// Replace the default path normalization, e.g. with the screener's.
+    pub fn with_path_normalization(mut self, normalization: PathNormalization) -> Self {
+        self.path_normalization = normalization;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for (i, path) in self.paths.iter().enumerate() {
+            if !path.starts_with('/') {
+                return Err(ConfigError::invalid(
+                    format!("paths[{}]", i),
+                    format!("decoy path '{}' must start with '/'", path),
+                ));
+            }
+        }
+        crate::duration::validate_block_duration("block_duration", self.block_duration)
+    }
+
+    /// Parse and validate a TOML document containing the config fields at the
+    /// top level.
+    #[cfg(feature = "serde")]
+    pub fn from_toml_str(input: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(input)?;
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// A validated `HoneypotConfig` with its decoys normalized, ready to attach
+/// with `RateLimiter::with_honeypot`.
+#[derive(Debug, Clone)]
+pub struct Honeypot {
+    config: HoneypotConfig,
+    decoys: HashSet<String>,
+}
+
+impl Honeypot {
+    pub fn new(config: &HoneypotConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+        let decoys = config
+            .paths
+            .iter()
+            .map(|path| config.path_normalization.normalize(path).into_owned())
+            .collect();
+        Ok(Self {
+            config: config.clone(),
+            decoys,
+        })
+    }
+
+    pub fn config(&self) -> &HoneypotConfig {
+        &self.config
+    }
+
+    /// Whether `path` is a decoy. Variants of a decoy that normalize to the
+    /// same path, such as encoded or dot-segment forms, count as the decoy.
+    pub fn is_decoy(&self, path: &str) -> bool {
+        if self.decoys.is_empty() {
+            return false;
+        }
+        let path = self.config.path_normalization.normalize(path);
+        self.decoys.contains(path.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoys_match_normalized_paths() {
+        let honeypot = Honeypot::new(
+            &HoneypotConfig::new()
+                .with_path("/admin.php")
+                .with_path("/.well-known/trap-7f3a"),
+        )
+        .unwrap();
+
+        assert!(honeypot.is_decoy("/admin.php"));
+        assert!(honeypot.is_decoy("//admin%2ephp"));
+        assert!(honeypot.is_decoy("/static/../admin.php"));
+        assert!(honeypot.is_decoy("/.well-known/trap-7f3a"));
+        assert!(!honeypot.is_decoy("/admin.php/"));
+        assert!(!honeypot.is_decoy("/admin"));
+        assert!(!honeypot.is_decoy("/Admin.php"));
+        assert!(!Honeypot::new(&HoneypotConfig::new())
+            .unwrap()
+            .is_decoy("/admin.php"));
+    }
+
+    #[test]
+    fn test_decoys_use_configured_normalization() {
+        // Decoys are normalized too, so a decoy written with a repeated slash
+        // or in mixed case still matches
+        let honeypot = Honeypot::new(
+            &HoneypotConfig::new()
+                .with_path("/a//b")
+                .with_path("/Admin.PHP")
+                .with_path_normalization(PathNormalization::new().with_case_fold(true)),
+        )
+        .unwrap();
+        assert!(honeypot.is_decoy("/a/b"));
+        assert!(honeypot.is_decoy("/a//b"));
+        assert!(honeypot.is_decoy("/admin.php"));
+        assert!(honeypot.is_decoy("/ADMIN.php"));
+
+        let raw = Honeypot::new(
+            &HoneypotConfig::new()
+                .with_path("/admin.php")
+                .with_path_normalization(PathNormalization::none()),
+        )
+        .unwrap();
+        assert!(raw.is_decoy("/admin.php"));
+        assert!(!raw.is_decoy("//admin.php"));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(HoneypotConfig::new().with_path("/a").validate().is_ok());
+        assert!(HoneypotConfig::new().with_path("a").validate().is_err());
+        assert!(HoneypotConfig::new()
+            .with_block_duration(Duration::ZERO)
+            .validate()
+            .is_err());
+        assert!(Honeypot::new(&HoneypotConfig::new().with_path("a")).is_err());
+        assert!(HoneypotConfig::new()
+            .with_block_duration(Duration::from_secs(200 * 365 * 86_400))
+            .validate()
+            .is_err());
+        assert!(Honeypot::new(&HoneypotConfig::new().with_block_duration(Duration::MAX)).is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_from_toml_str() {
+        let config = HoneypotConfig::from_toml_str(
+            r#"
+            paths = ["/admin.php", "/wp-login.php"]
+            block_duration = "7d"
+            response = { fake_ok = { body = "<html></html>" } }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.paths, vec!["/admin.php", "/wp-login.php"]);
+        assert_eq!(config.block_duration, Duration::from_secs(7 * 86_400));
+        assert_eq!(
+            config.response,
+            HoneypotResponse::FakeOk {
+                body: "<html></html>".to_string()
+            }
+        );
+
+        let config = HoneypotConfig::from_toml_str(
+            r#"
+            paths = ["/admin.php"]
+            block_duration = "48h"
+            response = { fake_ok = {} }
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.block_duration, Duration::from_secs(48 * 3600));
+        assert_eq!(
+            config.response,
+            HoneypotResponse::FakeOk {
+                body: String::new()
+            }
+        );
+        assert_eq!(
+            HoneypotConfig::from_toml_str("paths = [\"/x\"]")
+                .unwrap()
+                .response,
+            HoneypotResponse::Reject
+        );
+        assert!(HoneypotConfig::from_toml_str(
+            r#"
+            paths = ["/x"]
+            block_duration = "100000000d"
+            "#
+        )
+        .is_err());
+    }
+}
@@ -15,33 +15,91 @@
  *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+#[cfg(feature = "access-log")]
+pub mod access_log;
+pub mod action;
 pub mod config;
 pub mod context;
+pub mod dispatch;
+pub(crate) mod duration;
+pub mod events;
+pub mod heavy_hitters;
+pub mod honeypot;
 pub mod limiter;
 pub mod middleware;
+pub mod normalize;
+pub mod protocol;
+pub mod reload;
+pub mod rule_file;
+pub mod rule_packs;
+pub mod rule_stats;
+pub mod rules;
 pub mod screener;
+pub(crate) mod telemetry;
 pub mod types;
 
+#[cfg(feature = "grpc")]
+pub mod grpc;
+
 #[cfg(feature = "metrics")]
 pub mod metrics;
+#[cfg(feature = "metrics-facade")]
+pub mod metrics_facade;
+#[cfg(feature = "otel")]
+pub mod otel;
 
-#[cfg(feature = "metrics")]
+#[cfg(any(feature = "metrics", feature = "serde"))]
 pub mod routes;
 
-pub use config::RateLimitConfig;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+#[cfg(feature = "access-log")]
+pub use access_log::{access_log_middleware, AccessLogEntry, AccessLogger, RotationPolicy};
+pub use action::{
+    action_limit_middleware, ActionGuard, ActionLimit, ActionLimited, InMemoryActionChecker,
+};
+pub use config::{ConfigError, RateLimitConfig};
 pub use context::{
     security_context_middleware, security_context_middleware_with_config, IpExtractionError,
     IpExtractionStrategy, SecurityContextConfig,
 };
+pub use dispatch::{DispatchConfig, DispatchStats, OverflowPolicy};
+pub use events::{EventReceiver, LimiterEvent};
+#[cfg(feature = "grpc")]
+pub use grpc::{GrpcRateLimitLayer, GrpcRateLimitService};
+pub use heavy_hitters::{HeavyHitterConfig, HeavyHitterKind, HeavyHitterTracker, Offender};
+pub use honeypot::{Honeypot, HoneypotConfig, HoneypotResponse};
 pub use limiter::RateLimiter;
 pub use middleware::rate_limit_middleware;
-pub use screener::{RequestScreener, ScreeningConfig, ScreeningReason, ScreeningResult};
+pub use normalize::PathNormalization;
+pub use protocol::{ProtocolCheck, ProtocolChecks};
+pub use reload::{ConfigWatcher, ReloadError, ReloadedConfig};
+pub use rule_file::{load_rules, RuleFileError};
+pub use rule_packs::RulePack;
+pub use rule_stats::RuleStats;
+pub use rules::{
+    RuleAction, RuleChange, RuleTarget, ScoringConfig, ScreeningException, ScreeningRule,
+    ANOMALY_SCORE_RULE_ID,
+};
+pub use screener::{
+    RequestScreener, ScreeningConfig, ScreeningReason, ScreeningRequest, ScreeningResult,
+};
 pub use types::{
-    ActionChecker, AuthRefundCallback, NoOpActionChecker, NoOpOnBlocked, OnBlocked, SecurityContext,
+    ActionChecker, AuthRefundCallback, BlockEvent, BlockReason, NoOpActionChecker, NoOpOnBlocked,
+    OnBlocked, RateLimitDecision, RateLimitOutcome, SecurityContext, UnblockEvent,
 };
+#[cfg(feature = "websocket")]
+pub use websocket::RateLimitedWebSocket;
 
 #[cfg(feature = "metrics")]
-pub use routes::metrics_handler;
+pub use metrics::{IpLabelPolicy, MetricsConfig, PrometheusMetrics};
+#[cfg(feature = "otel")]
+pub use otel::OtelMetrics;
+#[cfg(feature = "metrics")]
+pub use routes::{metrics_handler, registry_metrics_handler};
+#[cfg(feature = "serde")]
+pub use routes::{rule_stats_handler, top_offenders_handler, TopOffenders, TopOffendersQuery};
 
 #[cfg(test)]
 mod tests;
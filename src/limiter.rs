@@ -15,37 +15,292 @@
  *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
  */
 
-use crate::config::RateLimitConfig;
+use crate::config::{ConfigError, RateLimitConfig};
+use crate::dispatch::{DispatchConfig, DispatchStats, Dispatcher, LimiterNotification};
+use crate::duration;
+use crate::events::{EventReceiver, EventStream, LimiterEvent};
+use crate::heavy_hitters::{HeavyHitterConfig, HeavyHitterKind, HeavyHitterTracker, Offender};
+use crate::honeypot::Honeypot;
+#[cfg(feature = "metrics")]
+use crate::metrics::{MetricsSink, PrometheusMetrics};
 use crate::screener::RequestScreener;
-use crate::types::{OnBlocked, RateLimitEntry, SecurityContext};
-use chrono::Utc;
+use crate::telemetry::Telemetry;
+use crate::types::{
+    ActionChecker, BlockEvent, BlockReason, OnBlocked, RateLimitEntry, SecurityContext,
+    UnblockEvent,
+};
+use chrono::{DateTime, Utc};
 use dashmap::DashMap;
-use std::sync::Arc;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
 
+const DEFAULT_EVENT_CAPACITY: usize = 1024;
+
+/// Configuration and screener are held behind one shared lock so that
+/// `update_config`, `replace_screener` and `apply_config` take effect for
+/// every clone of the limiter, including those already handed to in-flight
+/// requests.
 pub struct RateLimiter<B: OnBlocked> {
     rate_limit_cache: Arc<DashMap<String, RateLimitEntry>>,
-    config: RateLimitConfig,
-    on_blocked: Arc<B>,
-    screener: Option<Arc<RequestScreener>>,
+    settings: Arc<RwLock<Settings>>,
+    dispatcher: Arc<Dispatcher<B>>,
+    events: Arc<EventStream>,
+    action_checker: Option<Arc<dyn ActionChecker>>,
+    heavy_hitters: Option<Arc<HeavyHitterTracker>>,
+    honeypot: Option<Arc<Honeypot>>,
+    telemetry: Telemetry,
 }
 
 impl<B: OnBlocked + 'static> RateLimiter<B> {
     pub fn new(config: RateLimitConfig, on_blocked: B) -> Self {
+        let dispatcher = Dispatcher::new(DispatchConfig::default(), Arc::new(on_blocked));
+        let events = EventStream::new(DEFAULT_EVENT_CAPACITY);
+        let telemetry = Telemetry::default();
+        #[cfg(feature = "metrics")]
+        let (dispatcher, events) = (
+            dispatcher.with_metrics_sink(telemetry.metrics().clone()),
+            events.with_metrics_sink(telemetry.metrics().clone()),
+        );
+
         Self {
             rate_limit_cache: Arc::new(DashMap::new()),
-            config,
-            on_blocked: Arc::new(on_blocked),
-            screener: None,
+            settings: Arc::new(RwLock::new(Settings {
+                config: Arc::new(config),
+                screener: None,
+            })),
+            dispatcher: Arc::new(dispatcher),
+            events: Arc::new(events),
+            action_checker: None,
+            heavy_hitters: None,
+            honeypot: None,
+            telemetry,
+        }
+    }
+
+    /// Record metrics on `metrics`, registered on your own registry, instead
+    /// of the global default registry. Can only be set once per limiter.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(self, metrics: PrometheusMetrics) -> Self {
+        if !self.telemetry.attach_metrics(metrics) {
+            tracing::warn!("Metrics already attached to this limiter; ignoring");
+        }
+        self
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) fn metrics(&self) -> &MetricsSink {
+        self.telemetry.metrics()
+    }
+
+    /// Create the OpenTelemetry instruments from `meter` instead of the
+    /// global meter provider. Can only be set once per limiter.
+    #[cfg(feature = "otel")]
+    pub fn with_otel_meter(self, meter: &opentelemetry::metrics::Meter) -> Self {
+        if !self.telemetry.attach_otel(meter) {
+            tracing::warn!("OpenTelemetry instruments already created for this limiter; ignoring");
         }
+        self
+    }
+
+    pub(crate) fn telemetry(&self) -> &Telemetry {
+        &self.telemetry
+    }
+
+    /// Replace the default limits on how `OnBlocked` callbacks are queued and
+    /// run. Call this while building the limiter, before it handles requests.
+    pub fn with_dispatch_config(mut self, config: DispatchConfig) -> Self {
+        let dispatcher = Dispatcher::new(config, self.dispatcher.on_blocked());
+        #[cfg(feature = "metrics")]
+        let dispatcher = dispatcher.with_metrics_sink(self.metrics().clone());
+        self.dispatcher = Arc::new(dispatcher);
+        self
+    }
+
+    /// Delivery counters for `OnBlocked` events.
+    pub fn dispatch_stats(&self) -> DispatchStats {
+        self.dispatcher.stats()
     }
 
-    pub fn with_screener(mut self, screener: RequestScreener) -> Self {
-        self.screener = Some(Arc::new(screener));
+    /// Set how many events the `subscribe` stream buffers before slow
+    /// receivers start skipping them (default 1024). Call this before
+    /// subscribing.
+    pub fn with_event_capacity(mut self, capacity: usize) -> Self {
+        let events = EventStream::new(capacity);
+        #[cfg(feature = "metrics")]
+        let events = events.with_metrics_sink(self.metrics().clone());
+        self.events = Arc::new(events);
         self
     }
 
-    pub fn screener(&self) -> Option<&RequestScreener> {
-        self.screener.as_deref()
+    /// Subscribe to the stream of decisions made by `rate_limit_middleware`.
+    /// Events are only built while at least one receiver is alive.
+    pub fn subscribe(&self) -> EventReceiver {
+        self.events.subscribe()
+    }
+
+    /// Total number of events skipped by receivers that fell behind.
+    pub fn events_lagged(&self) -> u64 {
+        self.events.lagged()
+    }
+
+    pub(crate) fn emit(&self, event: impl FnOnce() -> LimiterEvent) {
+        self.events.emit(event);
+    }
+
+    /// Track the keys with the most requests, penalties and screening hits
+    /// in bounded memory. Query them with `top_offenders`. Fails if `config`
+    /// does not pass `HeavyHitterConfig::validate`.
+    pub fn with_heavy_hitters(mut self, config: HeavyHitterConfig) -> Result<Self, ConfigError> {
+        self.heavy_hitters = Some(Arc::new(HeavyHitterTracker::new(config)?));
+        Ok(self)
+    }
+
+    pub fn heavy_hitters(&self) -> Option<&Arc<HeavyHitterTracker>> {
+        self.heavy_hitters.as_ref()
+    }
+
+    /// The `n` heaviest keys by `kind` over the tracked window. Empty unless
+    /// `with_heavy_hitters` was called.
+    pub fn top_offenders(&self, kind: HeavyHitterKind, n: usize) -> Vec<Offender> {
+        self.heavy_hitters
+            .as_ref()
+            .map(|tracker| tracker.top(kind, n))
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn record_heavy_hitter(&self, kind: HeavyHitterKind, key: &str) {
+        if let Some(tracker) = &self.heavy_hitters {
+            tracker.record(kind, key);
+        }
+    }
+
+    /// Enable per-action cooldowns. `rate_limit_middleware` then injects an
+    /// `ActionGuard` into request extensions for handlers and
+    /// `action_limit_middleware` to use.
+    pub fn with_action_checker<C: ActionChecker + 'static>(mut self, checker: C) -> Self {
+        self.action_checker = Some(Arc::new(checker));
+        self
+    }
+
+    pub fn action_checker(&self) -> Option<Arc<dyn ActionChecker>> {
+        self.action_checker.clone()
+    }
+
+    /// Ban any key that requests one of `honeypot`'s decoy paths for its
+    /// `block_duration`, reported as `BlockReason::Honeypot`.
+    pub fn with_honeypot(mut self, honeypot: Honeypot) -> Self {
+        self.honeypot = Some(Arc::new(honeypot));
+        self
+    }
+
+    pub fn honeypot(&self) -> Option<&Arc<Honeypot>> {
+        self.honeypot.as_ref()
+    }
+
+    pub fn with_screener(self, screener: RequestScreener) -> Self {
+        self.replace_screener(screener);
+        self
+    }
+
+    /// Returns a snapshot of the current screener.
+    pub fn screener(&self) -> Option<Arc<RequestScreener>> {
+        read_lock(&self.settings).screener.clone()
+    }
+
+    /// Snapshot the configuration and screener together, so a request never
+    /// pairs a reloaded configuration with the previous screener.
+    pub(crate) fn settings(&self) -> (Arc<RateLimitConfig>, Option<Arc<RequestScreener>>) {
+        let settings = read_lock(&self.settings);
+        (settings.config.clone(), settings.screener.clone())
+    }
+
+    /// Atomically swap the configuration. Bucket state is kept; balances above
+    /// a lowered `rate_limit_per_minute` are capped on the next refill.
+    ///
+    /// Turning `dry_run` off drops the shadow blocks recorded in dry-run
+    /// mode, so they are never enforced without having been reported.
+    pub fn update_config(&self, config: RateLimitConfig) {
+        tracing::info!(
+            "Rate limit configuration updated ({} requests/minute, block duration {:?})",
+            config.rate_limit_per_minute,
+            config.block_duration
+        );
+        let dry_run = config.dry_run;
+        let previous = std::mem::replace(&mut write_lock(&self.settings).config, Arc::new(config));
+        self.dry_run_changed(previous.dry_run, dry_run);
+    }
+
+    /// Atomically swap the configuration and the screener together, as
+    /// `ConfigWatcher` does, so no request sees one without the other. `None`
+    /// removes the screener. Runtime rules and rule hit stats carry over as
+    /// with `replace_screener`, and turning `dry_run` off is handled as in
+    /// `update_config`.
+    pub fn apply_config(&self, config: RateLimitConfig, screener: Option<RequestScreener>) {
+        tracing::info!(
+            "Rate limit configuration and screener updated ({} requests/minute, block duration {:?}, screening {})",
+            config.rate_limit_per_minute,
+            config.block_duration,
+            if screener.is_some() { "enabled" } else { "disabled" }
+        );
+        let dry_run = config.dry_run;
+        let previous = {
+            let mut settings = write_lock(&self.settings);
+            let screener = screener.map(|mut screener| {
+                if let Some(previous) = &settings.screener {
+                    screener.inherit_from(previous);
+                }
+                Arc::new(screener)
+            });
+            settings.screener = screener;
+            std::mem::replace(&mut settings.config, Arc::new(config))
+        };
+        self.dry_run_changed(previous.dry_run, dry_run);
+    }
+
+    /// Requests decided under the previous snapshot may still record shadow
+    /// blocks after this runs; enforcement ignores those too.
+    fn dry_run_changed(&self, was_dry_run: bool, dry_run: bool) {
+        match (was_dry_run, dry_run) {
+            (true, false) => {
+                tracing::info!("Dry run disabled; rate limits are now enforced");
+                self.clear_dry_run_blocks();
+            }
+            (false, true) => tracing::info!("Dry run enabled; rate limits are no longer enforced"),
+            _ => {}
+        }
+    }
+
+    /// Drop every shadow block recorded in dry-run mode.
+    fn clear_dry_run_blocks(&self) {
+        let mut cleared = 0;
+        for mut entry in self.rate_limit_cache.iter_mut() {
+            if entry.dry_run_block {
+                clear_dry_run_block(&mut entry);
+                cleared += 1;
+            }
+        }
+        if cleared > 0 {
+            tracing::info!(
+                "Dry run disabled; dropped {} shadow blocks without enforcing them",
+                cleared
+            );
+        }
+    }
+
+    /// Atomically swap the request screener used by the middleware. Rules
+    /// added to the current screener at runtime and rule hit stats carry
+    /// over.
+    pub fn replace_screener(&self, mut screener: RequestScreener) {
+        let mut settings = write_lock(&self.settings);
+        if let Some(previous) = settings.screener.as_ref() {
+            screener.inherit_from(previous);
+        }
+        settings.screener = Some(Arc::new(screener));
+    }
+
+    /// Remove the request screener, disabling screening.
+    pub fn clear_screener(&self) {
+        write_lock(&self.settings).screener = None;
     }
 
     pub async fn check_rate_limit(
@@ -53,25 +308,58 @@ impl<B: OnBlocked + 'static> RateLimiter<B> {
         key: &str,
         context: &SecurityContext,
         path: &str,
+    ) -> (bool, bool, f64) {
+        self.check_rate_limit_with_cost(key, context, path, 1.0)
+            .await
+    }
+
+    /// Same as `check_rate_limit`, but charges `cost` tokens instead of one.
+    pub async fn check_rate_limit_with_cost(
+        &self,
+        key: &str,
+        context: &SecurityContext,
+        path: &str,
+        cost: f64,
     ) -> (bool, bool, f64) {
         let now = Utc::now();
+        let config = self.config();
 
         if let Some(entry) = self.rate_limit_cache.get(key) {
             if let Some(blocked_until) = entry.blocked_until {
-                if now < blocked_until {
+                if now < blocked_until && (config.dry_run || !entry.dry_run_block) {
                     return (false, false, 0.0);
                 }
             }
         }
 
-        let max_tokens = self.config.max_tokens();
+        let max_tokens = config.max_tokens();
         let mut entry = self
             .rate_limit_cache
             .entry(key.to_string())
             .or_insert_with(|| RateLimitEntry::new(max_tokens));
 
+        if let Some(blocked_until) = entry.blocked_until {
+            if entry.dry_run_block && !config.dry_run {
+                // A shadow block left over from dry-run mode is never enforced
+                clear_dry_run_block(&mut entry);
+            } else if now >= blocked_until {
+                entry.blocked_until = None;
+                if !std::mem::take(&mut entry.dry_run_block) {
+                    self.notify(
+                        config.dry_run,
+                        LimiterNotification::Expired(UnblockEvent {
+                            key: key.to_string(),
+                            reason: entry.block_reason.clone(),
+                            blocked_until,
+                            strikes: entry.strikes,
+                        }),
+                    );
+                }
+            }
+        }
+
         let entry_age = now.signed_duration_since(entry.created_at);
-        if entry_age.num_seconds() < self.config.grace_period_seconds as i64 {
+        if entry_age.num_seconds() < config.grace_period_seconds as i64 {
             return (true, false, max_tokens);
         }
 
@@ -79,35 +367,43 @@ impl<B: OnBlocked + 'static> RateLimiter<B> {
             .signed_duration_since(entry.last_refill)
             .num_seconds()
             .max(0) as f64;
-        let refill_rate = self.config.refill_rate_per_second();
+        let refill_rate = config.refill_rate_per_second();
         entry.tokens = (entry.tokens + elapsed * refill_rate).min(max_tokens);
         entry.last_refill = now;
 
-        if entry.tokens >= 1.0 {
-            entry.tokens -= 1.0;
+        if entry.tokens >= cost {
+            entry.tokens -= cost;
             let remaining_tokens = entry.tokens;
             (true, false, remaining_tokens)
         } else if entry.blocked_until.is_none() {
-            let block_duration_chrono = chrono::Duration::from_std(self.config.block_duration)
-                .unwrap_or(chrono::Duration::minutes(15));
-            entry.blocked_until = Some(now + block_duration_chrono);
+            if config.dry_run {
+                // Shadow mode: keep the bucket state but don't report a real block
+                tracing::warn!(
+                    "Dry run: IP would have exceeded rate limit: {} (path: {}, tokens: {:.2})",
+                    context.ip_address,
+                    path,
+                    entry.tokens
+                );
+            } else {
+                tracing::warn!(
+                    "IP exceeded rate limit: {} (path: {}, tokens: {:.2})",
+                    context.ip_address,
+                    path,
+                    entry.tokens
+                );
+            }
 
-            tracing::warn!(
-                "IP exceeded rate limit: {} (path: {}, tokens: {:.2})",
-                context.ip_address,
+            let event = self.apply_block(
+                &mut entry,
+                config.block_duration,
+                config.dry_run,
+                key,
+                context,
                 path,
-                entry.tokens
+                BlockReason::TokenExhaustion,
+                None,
             );
-
-            // Call on_blocked directly - spawn a task to avoid blocking the rate limit check
-            let on_blocked = self.on_blocked.clone();
-            let ip = context.ip_address.clone();
-            let path = path.to_string();
-            let context = context.clone();
-
-            tokio::spawn(async move {
-                on_blocked.on_blocked(&ip, &path, &context).await;
-            });
+            self.notify(config.dry_run, LimiterNotification::Blocked(event));
 
             (false, true, 0.0)
         } else {
@@ -115,19 +411,97 @@ impl<B: OnBlocked + 'static> RateLimiter<B> {
         }
     }
 
+    /// Sets `blocked_until` and counts a strike, returning the event to report.
+    /// A longer block already in place is kept rather than shortened.
+    ///
+    /// In dry-run mode the block is only shadow state (see
+    /// `RateLimitEntry::dry_run_block`) and an enforced block is left as is.
+    #[allow(clippy::too_many_arguments)]
+    fn apply_block(
+        &self,
+        entry: &mut RateLimitEntry,
+        block_duration: Duration,
+        dry_run: bool,
+        key: &str,
+        context: &SecurityContext,
+        path: &str,
+        reason: BlockReason,
+        matched_pattern: Option<&str>,
+    ) -> BlockEvent {
+        let now = Utc::now();
+        let mut blocked_until = duration::expiry(now, block_duration);
+        let enforced = entry.blocked_until.filter(|_| !entry.dry_run_block);
+        let tokens = entry.tokens;
+
+        if dry_run && enforced.is_some_and(|until| now < until) {
+            // Nothing to shadow; the key is already blocked for real
+        } else {
+            let existing = if dry_run {
+                entry.blocked_until
+            } else {
+                enforced
+            };
+            if let Some(existing) = existing {
+                blocked_until = blocked_until.max(existing);
+            }
+            entry.blocked_until = Some(blocked_until);
+            entry.block_reason = Some(reason.clone());
+            entry.dry_run_block = dry_run;
+            if !dry_run {
+                entry.strikes += 1;
+            }
+        }
+
+        BlockEvent {
+            key: key.to_string(),
+            path: path.to_string(),
+            context: context.clone(),
+            reason,
+            matched_pattern: matched_pattern.map(str::to_string),
+            blocked_until,
+            tokens,
+            strikes: entry.strikes,
+        }
+    }
+
+    /// Queue the `OnBlocked` callback for `notification` so it doesn't delay
+    /// the rate limit check. Skipped when `dry_run`, the flag of the config
+    /// snapshot the caller decided with.
+    fn notify(&self, dry_run: bool, notification: LimiterNotification) {
+        if dry_run {
+            return;
+        }
+
+        self.dispatcher.dispatch(notification);
+    }
+
     pub fn cleanup_cache(&self) {
         let now = Utc::now();
-        let cache_retention = chrono::Duration::from_std(self.config.block_duration)
-            .unwrap_or(chrono::Duration::minutes(15))
-            * 2;
+        let config = self.config();
+        let cache_retention = duration::to_chrono(config.block_duration)
+            .checked_mul(2)
+            .unwrap_or(chrono::Duration::MAX);
 
         let before_count = self.rate_limit_cache.len();
+        let mut expired = Vec::new();
 
-        self.rate_limit_cache.retain(|_, entry| {
+        self.rate_limit_cache.retain(|key, entry| {
+            if entry.dry_run_block && !config.dry_run {
+                clear_dry_run_block(entry);
+            }
             if let Some(blocked_until) = entry.blocked_until {
                 if now < blocked_until {
                     return true;
                 }
+                entry.blocked_until = None;
+                if !std::mem::take(&mut entry.dry_run_block) {
+                    expired.push(UnblockEvent {
+                        key: key.clone(),
+                        reason: entry.block_reason.clone(),
+                        blocked_until,
+                        strikes: entry.strikes,
+                    });
+                }
             }
 
             let inactive_duration = now.signed_duration_since(entry.last_refill);
@@ -136,6 +510,15 @@ impl<B: OnBlocked + 'static> RateLimiter<B> {
 
         let after_count = self.rate_limit_cache.len();
 
+        if let Some(screener) = self.screener() {
+            screener.cleanup_scores();
+            screener.remove_expired_rules();
+        }
+
+        for event in expired {
+            self.notify(config.dry_run, LimiterNotification::Expired(event));
+        }
+
         if before_count > after_count {
             tracing::info!(
                 "Cleaned up {} old rate limit cache entries ({} -> {} entries)",
@@ -148,11 +531,12 @@ impl<B: OnBlocked + 'static> RateLimiter<B> {
 
     pub fn refund_tokens(&self, key: &str, amount: f64) {
         if let Some(mut entry) = self.rate_limit_cache.get_mut(key) {
-            let max_tokens = self.config.max_tokens();
+            let max_tokens = self.config().max_tokens();
             entry.tokens = (entry.tokens + amount).min(max_tokens);
 
             tracing::debug!(
-                "Refunded {:.2} tokens to {} (new balance: {:.2})",
+                "{}Refunded {:.2} tokens to {} (new balance: {:.2})",
+                self.dry_run_prefix(),
                 amount,
                 key,
                 entry.tokens
@@ -165,7 +549,8 @@ impl<B: OnBlocked + 'static> RateLimiter<B> {
             entry.tokens -= amount;
 
             tracing::debug!(
-                "Consumed additional {:.2} tokens from {} (new balance: {:.2})",
+                "{}Consumed additional {:.2} tokens from {} (new balance: {:.2})",
+                self.dry_run_prefix(),
                 amount,
                 key,
                 entry.tokens
@@ -173,25 +558,196 @@ impl<B: OnBlocked + 'static> RateLimiter<B> {
         }
     }
 
-    pub fn config(&self) -> &RateLimitConfig {
-        &self.config
+    /// Current token balance for `key`, if it is in the cache.
+    pub fn tokens(&self, key: &str) -> Option<f64> {
+        self.rate_limit_cache.get(key).map(|entry| entry.tokens)
+    }
+
+    /// Returns a snapshot of the current configuration.
+    pub fn config(&self) -> Arc<RateLimitConfig> {
+        read_lock(&self.settings).config.clone()
+    }
+
+    /// Whether limits are evaluated without being enforced.
+    pub fn is_dry_run(&self) -> bool {
+        self.config().dry_run
+    }
+
+    fn dry_run_prefix(&self) -> &'static str {
+        if self.is_dry_run() {
+            "Dry run: "
+        } else {
+            ""
+        }
     }
 
     /// Immediately block an IP address, draining all tokens and setting blocked_until.
     /// Caller should ensure the IP is not already blocked before calling this.
+    /// `OnBlocked::on_block_event` is called with `BlockReason::Manual`, an
+    /// empty path and a `SecurityContext` holding only the key; use
+    /// `block_immediately_with_context` to report the request instead.
     pub fn block_immediately(&self, key: &str) {
-        let now = Utc::now();
-        let max_tokens = self.config.max_tokens();
+        let context = SecurityContext::new(key.to_string(), String::new());
+        self.block_immediately_with_context(key, &context, "");
+    }
+
+    /// Immediately block `key` like `block_immediately`, reporting the request
+    /// that caused it to `OnBlocked::on_block_event` with `BlockReason::Manual`.
+    pub fn block_immediately_with_context(&self, key: &str, context: &SecurityContext, path: &str) {
+        self.block_with_reason(key, context, path, BlockReason::Manual, None);
+    }
 
+    /// Immediately block `key` like `block_immediately_with_context`, reporting
+    /// `reason` instead of `BlockReason::Manual`.
+    pub fn block_with_reason(
+        &self,
+        key: &str,
+        context: &SecurityContext,
+        path: &str,
+        reason: BlockReason,
+        matched_pattern: Option<&str>,
+    ) {
+        let config = self.config();
+        self.block_for(
+            &config,
+            key,
+            context,
+            path,
+            config.block_duration,
+            reason,
+            matched_pattern,
+        );
+    }
+
+    /// Ban `key` for the honeypot's block duration after it requested the
+    /// decoy `path`. Returns `false`, without reporting a new block, if no
+    /// honeypot is configured or the key is already banned by it.
+    pub fn trip_honeypot(&self, key: &str, context: &SecurityContext, path: &str) -> bool {
+        let Some(honeypot) = &self.honeypot else {
+            return false;
+        };
+        let config = self.config();
+        let max_tokens = config.max_tokens();
+
+        // Check and block under one entry guard so concurrent decoy hits
+        // report a single new block
         let mut entry = self
             .rate_limit_cache
             .entry(key.to_string())
             .or_insert_with(|| RateLimitEntry::new(max_tokens));
+        let already_banned = entry.block_reason == Some(BlockReason::Honeypot)
+            && entry.blocked_until.is_some_and(|until| Utc::now() < until)
+            && (config.dry_run || !entry.dry_run_block);
+        if already_banned {
+            return false;
+        }
 
         entry.tokens = 0.0;
-        let block_duration_chrono = chrono::Duration::from_std(self.config.block_duration)
-            .unwrap_or(chrono::Duration::minutes(15));
-        entry.blocked_until = Some(now + block_duration_chrono);
+        let event = self.apply_block(
+            &mut entry,
+            honeypot.config().block_duration,
+            config.dry_run,
+            key,
+            context,
+            path,
+            BlockReason::Honeypot,
+            Some(path),
+        );
+        drop(entry);
+
+        self.notify(config.dry_run, LimiterNotification::Blocked(event));
+        true
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn block_for(
+        &self,
+        config: &RateLimitConfig,
+        key: &str,
+        context: &SecurityContext,
+        path: &str,
+        block_duration: Duration,
+        reason: BlockReason,
+        matched_pattern: Option<&str>,
+    ) {
+        let max_tokens = config.max_tokens();
+
+        let mut entry = self
+            .rate_limit_cache
+            .entry(key.to_string())
+            .or_insert_with(|| RateLimitEntry::new(max_tokens));
+
+        entry.tokens = 0.0;
+        let event = self.apply_block(
+            &mut entry,
+            block_duration,
+            config.dry_run,
+            key,
+            context,
+            path,
+            reason,
+            matched_pattern,
+        );
+        drop(entry);
+
+        self.notify(config.dry_run, LimiterNotification::Blocked(event));
+    }
+
+    /// Lift an active block on `key` and refill its bucket. Returns `false` if
+    /// the key was not blocked. `OnBlocked::on_unblocked` is called on success.
+    pub fn unblock(&self, key: &str) -> bool {
+        let now = Utc::now();
+        let config = self.config();
+
+        let Some(mut entry) = self.rate_limit_cache.get_mut(key) else {
+            return false;
+        };
+        if entry.dry_run_block && !config.dry_run {
+            clear_dry_run_block(&mut entry);
+            return false;
+        }
+        let Some(blocked_until) = entry.blocked_until.filter(|until| now < *until) else {
+            return false;
+        };
+
+        let dry_run_block = std::mem::take(&mut entry.dry_run_block);
+        entry.blocked_until = None;
+        entry.tokens = config.max_tokens();
+        entry.last_refill = now;
+        let event = UnblockEvent {
+            key: key.to_string(),
+            reason: entry.block_reason.clone(),
+            blocked_until,
+            strikes: entry.strikes,
+        };
+        drop(entry);
+
+        tracing::info!("Unblocked {}", key);
+        self.notify(
+            config.dry_run || dry_run_block,
+            LimiterNotification::Unblocked(event),
+        );
+        true
+    }
+
+    /// Number of times `key` has been blocked while it stayed in the cache.
+    pub fn strikes(&self, key: &str) -> u32 {
+        self.rate_limit_cache
+            .get(key)
+            .map(|entry| entry.strikes)
+            .unwrap_or(0)
+    }
+
+    /// Returns the time until which `key` is blocked, or `None` if it is not
+    /// currently blocked.
+    pub fn blocked_until(&self, key: &str) -> Option<DateTime<Utc>> {
+        let now = Utc::now();
+        let dry_run = self.is_dry_run();
+        self.rate_limit_cache
+            .get(key)
+            .filter(|entry| dry_run || !entry.dry_run_block)
+            .and_then(|entry| entry.blocked_until)
+            .filter(|blocked_until| now < *blocked_until)
     }
 
     pub fn get_cache_stats(&self) -> (usize, usize) {
@@ -212,11 +768,14 @@ impl<B: OnBlocked + 'static> RateLimiter<B> {
         (total_size, blocked_count)
     }
 
-    #[cfg(feature = "metrics")]
+    #[cfg(any(feature = "metrics", feature = "metrics-facade"))]
     pub fn update_metrics(&self) {
         let (cache_size, blocked_ips) = self.get_cache_stats();
-        crate::metrics::update_cache_size(cache_size);
-        crate::metrics::update_blocked_ips(blocked_ips);
+        self.telemetry.update_cache_stats(cache_size, blocked_ips);
+        #[cfg(feature = "metrics")]
+        if let Some(screener) = self.screener() {
+            self.metrics().update_rule_stats(&screener.rule_stats());
+        }
     }
 }
 
@@ -224,9 +783,34 @@ impl<B: OnBlocked> Clone for RateLimiter<B> {
     fn clone(&self) -> Self {
         Self {
             rate_limit_cache: self.rate_limit_cache.clone(),
-            config: self.config.clone(),
-            on_blocked: self.on_blocked.clone(),
-            screener: self.screener.clone(),
+            settings: self.settings.clone(),
+            dispatcher: self.dispatcher.clone(),
+            events: self.events.clone(),
+            action_checker: self.action_checker.clone(),
+            heavy_hitters: self.heavy_hitters.clone(),
+            honeypot: self.honeypot.clone(),
+            telemetry: self.telemetry.clone(),
         }
     }
 }
+
+struct Settings {
+    config: Arc<RateLimitConfig>,
+    screener: Option<Arc<RequestScreener>>,
+}
+
+/// Drop a shadow block from dry-run mode.
+fn clear_dry_run_block(entry: &mut RateLimitEntry) {
+    entry.blocked_until = None;
+    entry.block_reason = None;
+    entry.dry_run_block = false;
+}
+
+fn read_lock<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn write_lock<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
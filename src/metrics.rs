@@ -15,11 +15,33 @@
  *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+//! Prometheus metrics for the `metrics` feature.
+//!
+//! The free functions record to the global default registry, which a limiter
+//! uses unless `RateLimiter::with_metrics` attaches a `PrometheusMetrics`.
+//! There the block, cache refund, error penalty and screening block counters
+//! carry the raw client address as their `ip` label, and series are never
+//! removed, so label cardinality grows with every distinct client. That is
+//! fine for load tests against a few addresses; anything facing real traffic
+//! should attach a `PrometheusMetrics` with an `IpLabelPolicy`, which drops,
+//! hashes or bounds the `ip` label.
+
+#[cfg(feature = "metrics")]
+use crate::heavy_hitters::SpaceSaving;
+use crate::rule_stats::RuleStats;
 #[cfg(feature = "metrics")]
 use lazy_static::lazy_static;
 #[cfg(feature = "metrics")]
 use prometheus::{
-    register_counter_vec, register_gauge, register_histogram_vec, CounterVec, Gauge, HistogramVec,
+    core::Collector, register_counter, register_counter_vec, register_gauge, register_gauge_vec,
+    register_histogram_vec, Counter, CounterVec, Gauge, GaugeVec, HistogramOpts, HistogramVec,
+    Opts, Registry,
+};
+#[cfg(feature = "metrics")]
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::{Arc, Mutex, OnceLock},
 };
 
 #[cfg(feature = "metrics")]
@@ -64,7 +86,48 @@ lazy_static! {
     pub static ref SCREENING_BLOCKS: CounterVec = register_counter_vec!(
         "screening_blocks_total",
         "Total number of requests blocked by malicious pattern screening",
-        &["ip", "reason"]
+        &["ip", "rule"]
+    )
+    .unwrap();
+    pub static ref DRY_RUN_OUTCOMES: CounterVec = register_counter_vec!(
+        "rate_limit_dry_run_total",
+        "Total number of outcomes that would have applied outside dry-run mode",
+        &["outcome"]
+    )
+    .unwrap();
+    pub static ref ACTION_LIMITED: CounterVec = register_counter_vec!(
+        "rate_limit_action_limited_total",
+        "Total number of requests rejected by a per-action cooldown",
+        &["action"]
+    )
+    .unwrap();
+    pub static ref CALLBACK_EVENTS_DROPPED: CounterVec = register_counter_vec!(
+        "rate_limit_callback_events_dropped_total",
+        "Total number of OnBlocked events dropped, coalesced or timed out",
+        &["reason"]
+    )
+    .unwrap();
+    pub static ref EVENTS_LAGGED: Counter = register_counter!(
+        "rate_limit_events_lagged_total",
+        "Total number of limiter events skipped by slow subscribers"
+    )
+    .unwrap();
+    pub static ref SCREENING_RULE_HITS: GaugeVec = register_gauge_vec!(
+        "rate_limit_screening_rule_hits",
+        "Hits per screening rule since its stats were last reset",
+        &["rule"]
+    )
+    .unwrap();
+    pub static ref SCREENING_RULE_UNIQUE_KEYS: GaugeVec = register_gauge_vec!(
+        "rate_limit_screening_rule_unique_keys",
+        "Distinct keys that hit each screening rule",
+        &["rule"]
+    )
+    .unwrap();
+    pub static ref SCREENING_RULE_LAST_HIT: GaugeVec = register_gauge_vec!(
+        "rate_limit_screening_rule_last_hit_timestamp_seconds",
+        "Unix time of each screening rule's last hit",
+        &["rule"]
     )
     .unwrap();
 }
@@ -107,8 +170,587 @@ pub fn record_http_request(status: u16, duration_seconds: f64) {
 }
 
 #[cfg(feature = "metrics")]
-pub fn record_screening_block(ip: &str, reason: &str) {
-    SCREENING_BLOCKS.with_label_values(&[ip, reason]).inc();
+pub fn record_screening_block(ip: &str, rule_id: &str) {
+    SCREENING_BLOCKS.with_label_values(&[ip, rule_id]).inc();
+}
+
+#[cfg(feature = "metrics")]
+pub fn record_dry_run(outcome: &str) {
+    DRY_RUN_OUTCOMES.with_label_values(&[outcome]).inc();
+}
+
+#[cfg(feature = "metrics")]
+pub fn record_action_limited(action: &str) {
+    ACTION_LIMITED.with_label_values(&[action]).inc();
+}
+
+#[cfg(feature = "metrics")]
+pub fn record_callback_dropped(reason: &str) {
+    CALLBACK_EVENTS_DROPPED.with_label_values(&[reason]).inc();
+}
+
+#[cfg(feature = "metrics")]
+pub fn record_events_lagged(skipped: u64) {
+    EVENTS_LAGGED.inc_by(skipped as f64);
+}
+
+#[cfg(feature = "metrics")]
+pub fn update_rule_stats(stats: &[RuleStats]) {
+    set_rule_stats(
+        &SCREENING_RULE_HITS,
+        &SCREENING_RULE_UNIQUE_KEYS,
+        &SCREENING_RULE_LAST_HIT,
+        stats,
+    );
+}
+
+/// Replace the per-rule gauges, dropping rules that no longer exist.
+#[cfg(feature = "metrics")]
+fn set_rule_stats(
+    hits: &GaugeVec,
+    unique_keys: &GaugeVec,
+    last_hit: &GaugeVec,
+    stats: &[RuleStats],
+) {
+    // Values are overwritten in place and only rules that are gone are
+    // removed, so a concurrent scrape never sees the gauges empty
+    for stat in stats {
+        let rule = [stat.rule_id.as_str()];
+        hits.with_label_values(&rule).set(stat.hits as f64);
+        unique_keys
+            .with_label_values(&rule)
+            .set(stat.unique_keys as f64);
+        if let Some(at) = stat.last_hit {
+            last_hit
+                .with_label_values(&rule)
+                .set(at.timestamp_millis() as f64 / 1000.0);
+        }
+    }
+
+    let current: HashSet<&str> = stats.iter().map(|stat| stat.rule_id.as_str()).collect();
+    let with_hits: HashSet<&str> = stats
+        .iter()
+        .filter(|stat| stat.last_hit.is_some())
+        .map(|stat| stat.rule_id.as_str())
+        .collect();
+    remove_rules_except(hits, &current);
+    remove_rules_except(unique_keys, &current);
+    remove_rules_except(last_hit, &with_hits);
+}
+
+/// Removes the series of every `rule` label value not in `keep`.
+#[cfg(feature = "metrics")]
+fn remove_rules_except(gauges: &GaugeVec, keep: &HashSet<&str>) {
+    let stale: Vec<String> = gauges
+        .collect()
+        .iter()
+        .flat_map(|family| family.get_metric())
+        .filter_map(|metric| {
+            metric
+                .get_label()
+                .iter()
+                .find(|pair| pair.name() == "rule")
+                .map(|pair| pair.value().to_string())
+        })
+        .filter(|rule| !keep.contains(rule.as_str()))
+        .collect();
+    for rule in stale {
+        let _ = gauges.remove_label_values(&[&rule]);
+    }
+}
+
+/// How the client IP is reported in labels by `PrometheusMetrics`.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IpLabelPolicy {
+    /// Leave the `ip` label off entirely.
+    #[default]
+    None,
+    /// Label with a hash of the IP reduced to one of `buckets` values.
+    Hashed { buckets: u64 },
+    /// Label the `n` IPs seen most often with their address and every other
+    /// IP as `other`. Frequencies are estimated in bounded memory, and an IP
+    /// is only labeled once it has certainly been seen more often than one it
+    /// replaces, whose series are then removed. At most `n + 1` `ip` values
+    /// exist at any time.
+    TopN(usize),
+}
+
+/// Options for `PrometheusMetrics::register`.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Default)]
+pub struct MetricsConfig {
+    /// Prefix for every metric name, e.g. `myapp` gives `myapp_rate_limit_blocks_total`
+    pub namespace: Option<String>,
+    /// Constant labels added to every metric, e.g. `limiter="api"`
+    pub const_labels: HashMap<String, String>,
+    pub ip_label_policy: IpLabelPolicy,
+}
+
+#[cfg(feature = "metrics")]
+impl MetricsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_namespace(mut self, namespace: &str) -> Self {
+        self.namespace = Some(namespace.to_string());
+        self
+    }
+
+    /// Add a constant label. Use a distinct value per limiter to register
+    /// several limiters on the same registry.
+    pub fn with_label(mut self, name: &str, value: &str) -> Self {
+        self.const_labels
+            .insert(name.to_string(), value.to_string());
+        self
+    }
+
+    pub fn with_ip_label_policy(mut self, policy: IpLabelPolicy) -> Self {
+        self.ip_label_policy = policy;
+        self
+    }
+}
+
+/// The limiter's metrics, registered on a caller-supplied registry instead of
+/// the global default one. Attach with `RateLimiter::with_metrics` and serve
+/// with `registry_metrics_handler`.
+#[cfg(feature = "metrics")]
+pub struct PrometheusMetrics {
+    blocks: CounterVec,
+    cache_refunds: CounterVec,
+    error_penalties: CounterVec,
+    cache_size: Gauge,
+    blocked_ips: Gauge,
+    http_requests: CounterVec,
+    http_request_duration: HistogramVec,
+    screening_blocks: CounterVec,
+    dry_run_outcomes: CounterVec,
+    action_limited: CounterVec,
+    callback_events_dropped: CounterVec,
+    events_lagged: Counter,
+    screening_rule_hits: GaugeVec,
+    screening_rule_unique_keys: GaugeVec,
+    screening_rule_last_hit: GaugeVec,
+    ip_labels: IpLabeler,
+}
+
+#[cfg(feature = "metrics")]
+impl PrometheusMetrics {
+    pub fn register(registry: &Registry, config: MetricsConfig) -> prometheus::Result<Self> {
+        let with_ip = config.ip_label_policy != IpLabelPolicy::None;
+        let labels = |names: &[&'static str]| -> Vec<&'static str> {
+            names
+                .iter()
+                .copied()
+                .filter(|name| with_ip || *name != "ip")
+                .collect()
+        };
+        let opts = |name: &str, help: &str| {
+            let opts = Opts::new(name, help).const_labels(config.const_labels.clone());
+            match &config.namespace {
+                Some(namespace) => opts.namespace(namespace.clone()),
+                None => opts,
+            }
+        };
+        let counter_vec = |name: &str, help: &str, names: &[&'static str]| {
+            let metric = CounterVec::new(opts(name, help), &labels(names))?;
+            registry.register(Box::new(metric.clone()))?;
+            Ok::<_, prometheus::Error>(metric)
+        };
+        let gauge = |name: &str, help: &str| {
+            let metric = Gauge::with_opts(opts(name, help))?;
+            registry.register(Box::new(metric.clone()))?;
+            Ok::<_, prometheus::Error>(metric)
+        };
+        let rule_gauge = |name: &str, help: &str| {
+            let metric = GaugeVec::new(opts(name, help), &["rule"])?;
+            registry.register(Box::new(metric.clone()))?;
+            Ok::<_, prometheus::Error>(metric)
+        };
+
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::from(opts(
+                "http_request_duration_seconds",
+                "HTTP request duration in seconds",
+            )),
+            &["status"],
+        )?;
+        registry.register(Box::new(http_request_duration.clone()))?;
+        let events_lagged = Counter::with_opts(opts(
+            "rate_limit_events_lagged_total",
+            "Total number of limiter events skipped by slow subscribers",
+        ))?;
+        registry.register(Box::new(events_lagged.clone()))?;
+
+        Ok(Self {
+            blocks: counter_vec(
+                "rate_limit_blocks_total",
+                "Total number of rate limit blocks by IP",
+                &["ip"],
+            )?,
+            cache_refunds: counter_vec(
+                "rate_limit_cache_refunds_total",
+                "Total number of cache refunds (304 responses)",
+                &["ip"],
+            )?,
+            error_penalties: counter_vec(
+                "rate_limit_error_penalties_total",
+                "Total number of error penalties applied",
+                &["ip", "status"],
+            )?,
+            cache_size: gauge(
+                "rate_limit_cache_size",
+                "Current number of IPs in rate limit cache",
+            )?,
+            blocked_ips: gauge("rate_limit_blocked_ips", "Current number of blocked IPs")?,
+            http_requests: counter_vec(
+                "http_requests_total",
+                "Total HTTP requests by status code",
+                &["status"],
+            )?,
+            http_request_duration,
+            screening_blocks: counter_vec(
+                "screening_blocks_total",
+                "Total number of requests blocked by malicious pattern screening",
+                &["ip", "rule"],
+            )?,
+            dry_run_outcomes: counter_vec(
+                "rate_limit_dry_run_total",
+                "Total number of outcomes that would have applied outside dry-run mode",
+                &["outcome"],
+            )?,
+            action_limited: counter_vec(
+                "rate_limit_action_limited_total",
+                "Total number of requests rejected by a per-action cooldown",
+                &["action"],
+            )?,
+            callback_events_dropped: counter_vec(
+                "rate_limit_callback_events_dropped_total",
+                "Total number of OnBlocked events dropped, coalesced or timed out",
+                &["reason"],
+            )?,
+            events_lagged,
+            screening_rule_hits: rule_gauge(
+                "rate_limit_screening_rule_hits",
+                "Hits per screening rule since its stats were last reset",
+            )?,
+            screening_rule_unique_keys: rule_gauge(
+                "rate_limit_screening_rule_unique_keys",
+                "Distinct keys that hit each screening rule",
+            )?,
+            screening_rule_last_hit: rule_gauge(
+                "rate_limit_screening_rule_last_hit_timestamp_seconds",
+                "Unix time of each screening rule's last hit",
+            )?,
+            ip_labels: IpLabeler::new(config.ip_label_policy),
+        })
+    }
+
+    fn ip_metric(&self, metric: IpMetric) -> &CounterVec {
+        match metric {
+            IpMetric::Blocks => &self.blocks,
+            IpMetric::CacheRefunds => &self.cache_refunds,
+            IpMetric::ErrorPenalties => &self.error_penalties,
+            IpMetric::ScreeningBlocks => &self.screening_blocks,
+        }
+    }
+
+    /// Increment `metric`, whose first label is `ip` and remaining labels are
+    /// `rest`.
+    fn inc_with_ip(&self, metric: IpMetric, ip: &str, rest: &[&str]) {
+        let rest = rest.iter().map(|value| value.to_string());
+        match &self.ip_labels {
+            IpLabeler::TopN(top) => {
+                // Held while incrementing so a concurrent eviction can't
+                // remove the series before it is recreated untracked
+                let mut top = top.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+                for (metric, values) in top.observe(ip) {
+                    let _ = self.ip_metric(metric).remove_label_values(&values);
+                }
+                let label = if top.is_labeled(ip) { ip } else { "other" };
+                let values: Vec<String> = std::iter::once(label.to_string()).chain(rest).collect();
+                if label == ip {
+                    top.track(ip, metric, &values);
+                }
+                self.ip_metric(metric).with_label_values(&values).inc();
+            }
+            labeler => {
+                let values: Vec<String> = labeler.label(ip).into_iter().chain(rest).collect();
+                self.ip_metric(metric).with_label_values(&values).inc();
+            }
+        }
+    }
+
+    pub fn record_block(&self, ip: &str) {
+        self.inc_with_ip(IpMetric::Blocks, ip, &[]);
+    }
+
+    pub fn record_cache_refund(&self, ip: &str) {
+        self.inc_with_ip(IpMetric::CacheRefunds, ip, &[]);
+    }
+
+    pub fn record_error_penalty(&self, ip: &str, status: u16) {
+        self.inc_with_ip(IpMetric::ErrorPenalties, ip, &[&status.to_string()]);
+    }
+
+    pub fn update_cache_size(&self, size: usize) {
+        self.cache_size.set(size as f64);
+    }
+
+    pub fn update_blocked_ips(&self, count: usize) {
+        self.blocked_ips.set(count as f64);
+    }
+
+    pub fn record_http_request(&self, status: u16, duration_seconds: f64) {
+        let status = status.to_string();
+        self.http_requests.with_label_values(&[&status]).inc();
+        self.http_request_duration
+            .with_label_values(&[&status])
+            .observe(duration_seconds);
+    }
+
+    /// `rule_id` is the ID of the rule whose action applied, which keeps the
+    /// `rule` label bounded; the reason text stays in logs and events.
+    pub fn record_screening_block(&self, ip: &str, rule_id: &str) {
+        self.inc_with_ip(IpMetric::ScreeningBlocks, ip, &[rule_id]);
+    }
+
+    pub fn record_dry_run(&self, outcome: &str) {
+        self.dry_run_outcomes.with_label_values(&[outcome]).inc();
+    }
+
+    pub fn record_action_limited(&self, action: &str) {
+        self.action_limited.with_label_values(&[action]).inc();
+    }
+
+    pub fn record_callback_dropped(&self, reason: &str) {
+        self.callback_events_dropped
+            .with_label_values(&[reason])
+            .inc();
+    }
+
+    pub fn record_events_lagged(&self, skipped: u64) {
+        self.events_lagged.inc_by(skipped as f64);
+    }
+
+    pub fn update_rule_stats(&self, stats: &[RuleStats]) {
+        set_rule_stats(
+            &self.screening_rule_hits,
+            &self.screening_rule_unique_keys,
+            &self.screening_rule_last_hit,
+            stats,
+        );
+    }
+}
+
+/// The metrics with an `ip` label.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum IpMetric {
+    Blocks,
+    CacheRefunds,
+    ErrorPenalties,
+    ScreeningBlocks,
+}
+
+#[cfg(feature = "metrics")]
+enum IpLabeler {
+    None,
+    Hashed(u64),
+    TopN(Mutex<TopKeys>),
+}
+
+#[cfg(feature = "metrics")]
+impl IpLabeler {
+    fn new(policy: IpLabelPolicy) -> Self {
+        match policy {
+            IpLabelPolicy::None => IpLabeler::None,
+            IpLabelPolicy::Hashed { buckets } => IpLabeler::Hashed(buckets.max(1)),
+            IpLabelPolicy::TopN(n) => IpLabeler::TopN(Mutex::new(TopKeys::new(n))),
+        }
+    }
+
+    /// The `ip` label for the `None` and `Hashed` policies. `TopN` labels
+    /// are handled by `PrometheusMetrics::inc_with_ip`.
+    fn label(&self, ip: &str) -> Option<String> {
+        match self {
+            IpLabeler::None => None,
+            IpLabeler::Hashed(buckets) => {
+                let mut hasher = DefaultHasher::new();
+                ip.hash(&mut hasher);
+                Some(format!("{:04x}", hasher.finish() % buckets))
+            }
+            IpLabeler::TopN(_) => Some("other".to_string()),
+        }
+    }
+}
+
+/// Chooses which IPs are labeled under `IpLabelPolicy::TopN`, from a
+/// space-saving frequency estimate over a fixed number of counters.
+#[cfg(feature = "metrics")]
+struct TopKeys {
+    n: usize,
+    summary: SpaceSaving,
+    /// The labeled IPs, with the label values of each series they appear in
+    labeled: HashMap<String, HashSet<(IpMetric, Vec<String>)>>,
+}
+
+#[cfg(feature = "metrics")]
+impl TopKeys {
+    fn new(n: usize) -> Self {
+        Self {
+            n,
+            summary: SpaceSaving::new((n * 8).max(64)),
+            labeled: HashMap::new(),
+        }
+    }
+
+    /// Count one occurrence of `key`, labeling it if its guaranteed count
+    /// (count minus error) now beats the weakest labeled IP. Returns the
+    /// series of the IP it replaced, to be removed.
+    ///
+    /// A new counter in a full summary has a guaranteed count of 1, so an IP
+    /// needs at least 2 and a single hit from a scan never takes a label.
+    fn observe(&mut self, key: &str) -> Vec<(IpMetric, Vec<String>)> {
+        self.summary.observe(key, 1);
+        if self.n == 0 || self.labeled.contains_key(key) {
+            return Vec::new();
+        }
+
+        let guaranteed = self.summary.guaranteed(key);
+        if guaranteed < 2 {
+            return Vec::new();
+        }
+        if self.labeled.len() < self.n {
+            self.labeled.insert(key.to_string(), HashSet::new());
+            return Vec::new();
+        }
+
+        let weakest = self
+            .labeled
+            .keys()
+            .map(|labeled| (self.summary.guaranteed(labeled), labeled))
+            .min()
+            .map(|(count, labeled)| (count, labeled.clone()));
+        match weakest {
+            Some((count, weakest)) if guaranteed > count => {
+                let series = self.labeled.remove(&weakest).unwrap_or_default();
+                self.labeled.insert(key.to_string(), HashSet::new());
+                series.into_iter().collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn is_labeled(&self, key: &str) -> bool {
+        self.labeled.contains_key(key)
+    }
+
+    fn track(&mut self, key: &str, metric: IpMetric, values: &[String]) {
+        if let Some(series) = self.labeled.get_mut(key) {
+            series.insert((metric, values.to_vec()));
+        }
+    }
+}
+
+/// Where a limiter records metrics: the `PrometheusMetrics` attached with
+/// `RateLimiter::with_metrics`, or the global default registry, whose per-IP
+/// counters are labeled with the unbounded raw `ip`.
+#[cfg(feature = "metrics")]
+#[derive(Clone, Default)]
+pub(crate) struct MetricsSink(Arc<OnceLock<Arc<PrometheusMetrics>>>);
+
+#[cfg(feature = "metrics")]
+impl MetricsSink {
+    /// Returns `false` if metrics were already attached.
+    pub(crate) fn attach(&self, metrics: PrometheusMetrics) -> bool {
+        self.0.set(Arc::new(metrics)).is_ok()
+    }
+
+    pub(crate) fn record_block(&self, ip: &str) {
+        match self.0.get() {
+            Some(metrics) => metrics.record_block(ip),
+            None => record_block(ip),
+        }
+    }
+
+    pub(crate) fn record_cache_refund(&self, ip: &str) {
+        match self.0.get() {
+            Some(metrics) => metrics.record_cache_refund(ip),
+            None => record_cache_refund(ip),
+        }
+    }
+
+    pub(crate) fn record_error_penalty(&self, ip: &str, status: u16) {
+        match self.0.get() {
+            Some(metrics) => metrics.record_error_penalty(ip, status),
+            None => record_error_penalty(ip, status),
+        }
+    }
+
+    pub(crate) fn update_cache_size(&self, size: usize) {
+        match self.0.get() {
+            Some(metrics) => metrics.update_cache_size(size),
+            None => update_cache_size(size),
+        }
+    }
+
+    pub(crate) fn update_blocked_ips(&self, count: usize) {
+        match self.0.get() {
+            Some(metrics) => metrics.update_blocked_ips(count),
+            None => update_blocked_ips(count),
+        }
+    }
+
+    pub(crate) fn record_http_request(&self, status: u16, duration_seconds: f64) {
+        match self.0.get() {
+            Some(metrics) => metrics.record_http_request(status, duration_seconds),
+            None => record_http_request(status, duration_seconds),
+        }
+    }
+
+    pub(crate) fn record_screening_block(&self, ip: &str, rule_id: &str) {
+        match self.0.get() {
+            Some(metrics) => metrics.record_screening_block(ip, rule_id),
+            None => record_screening_block(ip, rule_id),
+        }
+    }
+
+    pub(crate) fn record_dry_run(&self, outcome: &str) {
+        match self.0.get() {
+            Some(metrics) => metrics.record_dry_run(outcome),
+            None => record_dry_run(outcome),
+        }
+    }
+
+    pub(crate) fn record_action_limited(&self, action: &str) {
+        match self.0.get() {
+            Some(metrics) => metrics.record_action_limited(action),
+            None => record_action_limited(action),
+        }
+    }
+
+    pub(crate) fn record_callback_dropped(&self, reason: &str) {
+        match self.0.get() {
+            Some(metrics) => metrics.record_callback_dropped(reason),
+            None => record_callback_dropped(reason),
+        }
+    }
+
+    pub(crate) fn record_events_lagged(&self, skipped: u64) {
+        match self.0.get() {
+            Some(metrics) => metrics.record_events_lagged(skipped),
+            None => record_events_lagged(skipped),
+        }
+    }
+
+    pub(crate) fn update_rule_stats(&self, stats: &[RuleStats]) {
+        match self.0.get() {
+            Some(metrics) => metrics.update_rule_stats(stats),
+            None => update_rule_stats(stats),
+        }
+    }
 }
 
 // No-op versions when metrics feature is disabled
@@ -131,4 +773,281 @@ pub fn update_blocked_ips(_count: usize) {}
 pub fn record_http_request(_status: u16, _duration_seconds: f64) {}
 
 #[cfg(not(feature = "metrics"))]
-pub fn record_screening_block(_ip: &str, _reason: &str) {}
+pub fn record_screening_block(_ip: &str, _rule_id: &str) {}
+
+#[cfg(not(feature = "metrics"))]
+pub fn record_dry_run(_outcome: &str) {}
+
+#[cfg(not(feature = "metrics"))]
+pub fn record_action_limited(_action: &str) {}
+
+#[cfg(not(feature = "metrics"))]
+pub fn record_callback_dropped(_reason: &str) {}
+
+#[cfg(not(feature = "metrics"))]
+pub fn record_events_lagged(_skipped: u64) {}
+
+#[cfg(not(feature = "metrics"))]
+pub fn update_rule_stats(_stats: &[RuleStats]) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::proto::MetricFamily;
+
+    fn family<'a>(families: &'a [MetricFamily], name: &str) -> &'a MetricFamily {
+        families
+            .iter()
+            .find(|family| family.name() == name)
+            .unwrap_or_else(|| panic!("missing metric {}", name))
+    }
+
+    fn label_values(family: &MetricFamily, label: &str) -> Vec<String> {
+        family
+            .get_metric()
+            .iter()
+            .filter_map(|metric| {
+                metric
+                    .get_label()
+                    .iter()
+                    .find(|pair| pair.name() == label)
+                    .map(|pair| pair.value().to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_two_limiters_share_a_registry() {
+        let registry = Registry::new();
+        let api = PrometheusMetrics::register(
+            &registry,
+            MetricsConfig::new()
+                .with_namespace("myapp")
+                .with_label("limiter", "api"),
+        )
+        .unwrap();
+        let admin = PrometheusMetrics::register(
+            &registry,
+            MetricsConfig::new()
+                .with_namespace("myapp")
+                .with_label("limiter", "admin"),
+        )
+        .unwrap();
+
+        api.record_block("10.0.0.1");
+        admin.record_block("10.0.0.1");
+        admin.record_block("10.0.0.2");
+
+        let families = registry.gather();
+        let blocks = family(&families, "myapp_rate_limit_blocks_total");
+        let mut limiters = label_values(blocks, "limiter");
+        limiters.sort();
+        assert_eq!(limiters, vec!["admin", "api"]);
+        assert!(
+            label_values(blocks, "ip").is_empty(),
+            "default policy has no ip label"
+        );
+    }
+
+    #[test]
+    fn test_same_labels_twice_is_an_error() {
+        let registry = Registry::new();
+        PrometheusMetrics::register(&registry, MetricsConfig::new()).unwrap();
+        assert!(PrometheusMetrics::register(&registry, MetricsConfig::new()).is_err());
+    }
+
+    #[test]
+    fn test_rule_stats_gauges() {
+        let registry = Registry::new();
+        let metrics = PrometheusMetrics::register(&registry, MetricsConfig::new()).unwrap();
+        let stats = |rule_id: &str, hits| RuleStats {
+            rule_id: rule_id.to_string(),
+            hits,
+            unique_keys: 1,
+            last_hit: (hits > 0).then(chrono::Utc::now),
+        };
+
+        metrics.update_rule_stats(&[stats("a", 3), stats("b", 0)]);
+        let families = registry.gather();
+        let hits = family(&families, "rate_limit_screening_rule_hits");
+        assert_eq!(label_values(hits, "rule"), vec!["a", "b"]);
+        let last_hit = family(
+            &families,
+            "rate_limit_screening_rule_last_hit_timestamp_seconds",
+        );
+        assert_eq!(label_values(last_hit, "rule"), vec!["a"]);
+
+        // Rules that are gone are dropped
+        metrics.update_rule_stats(&[stats("b", 1)]);
+        let families = registry.gather();
+        let hits = family(&families, "rate_limit_screening_rule_hits");
+        assert_eq!(label_values(hits, "rule"), vec!["b"]);
+        assert_eq!(hits.get_metric()[0].get_gauge().value(), 1.0);
+        let last_hit = family(
+            &families,
+            "rate_limit_screening_rule_last_hit_timestamp_seconds",
+        );
+        assert_eq!(label_values(last_hit, "rule"), vec!["b"]);
+
+        // A rule that loses its last hit, after a reset, drops its timestamp
+        metrics.update_rule_stats(&[stats("a", 0), stats("b", 0)]);
+        let families = registry.gather();
+        let hits = family(&families, "rate_limit_screening_rule_hits");
+        assert_eq!(label_values(hits, "rule"), vec!["a", "b"]);
+        assert!(families
+            .iter()
+            .all(|family| family.name() != "rate_limit_screening_rule_last_hit_timestamp_seconds"));
+    }
+
+    #[test]
+    fn test_hashed_ip_labels_are_bounded() {
+        let registry = Registry::new();
+        let metrics = PrometheusMetrics::register(
+            &registry,
+            MetricsConfig::new().with_ip_label_policy(IpLabelPolicy::Hashed { buckets: 4 }),
+        )
+        .unwrap();
+
+        for i in 0..100 {
+            metrics.record_error_penalty(&format!("10.0.{}.{}", i / 10, i % 10), 404);
+        }
+
+        let families = registry.gather();
+        let penalties = family(&families, "rate_limit_error_penalties_total");
+        let ips = label_values(penalties, "ip");
+        assert!(ips.len() <= 4);
+        assert!(!ips.iter().any(|ip| ip.starts_with("10.")));
+    }
+
+    #[test]
+    fn test_top_n_labels_frequent_ips_only() {
+        let registry = Registry::new();
+        let metrics = PrometheusMetrics::register(
+            &registry,
+            MetricsConfig::new().with_ip_label_policy(IpLabelPolicy::TopN(1)),
+        )
+        .unwrap();
+
+        for _ in 0..10 {
+            metrics.record_block("10.0.0.1");
+        }
+        for i in 2..200 {
+            metrics.record_block(&format!("10.0.{}.{}", i / 100, i % 100));
+        }
+        metrics.record_block("10.0.0.1");
+
+        let families = registry.gather();
+        let mut ips = label_values(family(&families, "rate_limit_blocks_total"), "ip");
+        ips.sort();
+        assert_eq!(ips, vec!["10.0.0.1", "other"]);
+    }
+
+    #[test]
+    fn test_top_n_labels_stay_bounded_under_scan() {
+        let registry = Registry::new();
+        let metrics = PrometheusMetrics::register(
+            &registry,
+            MetricsConfig::new().with_ip_label_policy(IpLabelPolicy::TopN(5)),
+        )
+        .unwrap();
+
+        for i in 0..20_000u32 {
+            let ip = format!("10.{}.{}.{}", i >> 16, (i >> 8) & 0xff, i & 0xff);
+            metrics.record_block(&ip);
+            metrics.record_error_penalty(&ip, 400 + (i % 3) as u16);
+            metrics.record_screening_block(&ip, "scan");
+            if i % 10 == 0 {
+                metrics.record_block("192.0.2.1");
+            }
+        }
+
+        let families = registry.gather();
+        let mut ips = std::collections::HashSet::new();
+        for name in [
+            "rate_limit_blocks_total",
+            "rate_limit_error_penalties_total",
+            "screening_blocks_total",
+        ] {
+            ips.extend(label_values(family(&families, name), "ip"));
+        }
+        assert!(ips.len() <= 6, "{} ip label values", ips.len());
+        assert!(ips.contains("192.0.2.1"));
+        assert!(ips.contains("other"));
+    }
+
+    #[tokio::test]
+    async fn test_limiter_records_on_attached_registry() {
+        use crate::{
+            context::security_context_middleware,
+            limiter::RateLimiter,
+            middleware::rate_limit_middleware,
+            rules::{RuleAction, RuleTarget, ScoringConfig, ScreeningRule, ANOMALY_SCORE_RULE_ID},
+            screener::{RequestScreener, ScreeningConfig},
+            types::NoOpOnBlocked,
+            RateLimitConfig,
+        };
+        use axum::{
+            middleware::{from_fn, from_fn_with_state},
+            routing::get,
+            Router,
+        };
+        use axum_test::TestServer;
+        use std::net::SocketAddr;
+        use std::time::Duration;
+
+        let registry = Registry::new();
+        let metrics =
+            PrometheusMetrics::register(&registry, MetricsConfig::new().with_namespace("test"))
+                .unwrap();
+        let config = RateLimitConfig::new(1, Duration::from_secs(60)).with_grace_period(0);
+        let screening = ScreeningConfig::new()
+            .with_rule(
+                ScreeningRule::new("backup-file", r"\.bak$")
+                    .with_target(RuleTarget::Path)
+                    .with_action(RuleAction::Score(2)),
+            )
+            .with_rule(
+                ScreeningRule::new("curl-ua", "curl")
+                    .with_target(RuleTarget::UserAgent)
+                    .with_action(RuleAction::Score(3)),
+            )
+            .with_scoring(ScoringConfig::new(2));
+        let limiter = RateLimiter::new(config, NoOpOnBlocked)
+            .with_metrics(metrics)
+            .with_screener(RequestScreener::new(&screening).unwrap());
+
+        let app = Router::new()
+            .route("/", get(|| async { "OK" }))
+            .layer(from_fn_with_state(limiter, rate_limit_middleware))
+            .layer(from_fn(security_context_middleware))
+            .layer(axum::extract::connect_info::MockConnectInfo(
+                SocketAddr::from(([127, 0, 0, 1], 8080)),
+            ));
+        let server = TestServer::new(app);
+        for _ in 0..2 {
+            server
+                .get("/")
+                .add_header("X-Forwarded-For", "10.2.0.1")
+                .await;
+        }
+
+        // Different anomaly scores share one series, labeled by rule ID
+        for (ip, user_agent) in [("10.2.0.2", "Mozilla/5.0"), ("10.2.0.3", "curl/8.5.0")] {
+            server
+                .get("/site.bak")
+                .add_header("X-Forwarded-For", ip)
+                .add_header("User-Agent", user_agent)
+                .await;
+        }
+
+        let families = registry.gather();
+        let requests = family(&families, "test_http_requests_total");
+        let mut statuses = label_values(requests, "status");
+        statuses.sort();
+        assert_eq!(statuses, vec!["200", "418", "429"]);
+        let screening = family(&families, "test_screening_blocks_total");
+        assert_eq!(label_values(screening, "rule"), vec![ANOMALY_SCORE_RULE_ID]);
+        let blocks = family(&families, "test_rate_limit_blocks_total");
+        assert_eq!(blocks.get_metric()[0].get_counter().value(), 1.0);
+    }
+}
@@ -0,0 +1,116 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! The same signals as the `metrics` feature, emitted through the `metrics`
+//! crate facade so they reach whichever exporter the application installs.
+//!
+//! The `ip` label is left off, as with the default `IpLabelPolicy::None`: the
+//! facade can't remove series, so any per-IP label would grow without bound
+//! under a scan, so these functions take no IP. Use
+//! `RateLimiter::top_offenders` to find the busiest IPs.
+
+use metrics_rs::{counter, gauge, histogram};
+
+pub fn record_block() {
+    counter!("rate_limit_blocks_total").increment(1);
+}
+
+pub fn record_cache_refund() {
+    counter!("rate_limit_cache_refunds_total").increment(1);
+}
+
+pub fn record_error_penalty(status: u16) {
+    counter!("rate_limit_error_penalties_total", "status" => status.to_string()).increment(1);
+}
+
+pub fn update_cache_size(size: usize) {
+    gauge!("rate_limit_cache_size").set(size as f64);
+}
+
+pub fn update_blocked_ips(count: usize) {
+    gauge!("rate_limit_blocked_ips").set(count as f64);
+}
+
+pub fn record_http_request(status: u16, duration_seconds: f64) {
+    let status = status.to_string();
+    counter!("http_requests_total", "status" => status.clone()).increment(1);
+    histogram!("http_request_duration_seconds", "status" => status).record(duration_seconds);
+}
+
+pub fn record_dry_run(outcome: &str) {
+    counter!("rate_limit_dry_run_total", "outcome" => outcome.to_string()).increment(1);
+}
+
+pub fn record_screening_block(rule_id: &str) {
+    counter!("screening_blocks_total", "rule" => rule_id.to_string()).increment(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+
+    #[test]
+    fn test_signals_reach_installed_recorder() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+
+        metrics_rs::with_local_recorder(&recorder, || {
+            record_block();
+            record_block();
+            record_error_penalty(404);
+            record_http_request(429, 0.25);
+            record_dry_run("block");
+            update_blocked_ips(3);
+        });
+
+        let snapshot = snapshotter.snapshot().into_vec();
+        assert!(
+            snapshot
+                .iter()
+                .all(|(key, _, _, _)| key.key().labels().all(|label| label.key() != "ip")),
+            "no series is labeled by IP"
+        );
+        let values: Vec<_> = snapshot
+            .into_iter()
+            .map(|(key, _, _, value)| (key.key().name().to_string(), value))
+            .collect();
+        let value = |name: &str| {
+            values
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value)
+                .unwrap_or_else(|| panic!("missing metric {}", name))
+        };
+
+        assert_eq!(value("rate_limit_blocks_total"), &DebugValue::Counter(2));
+        assert_eq!(
+            value("rate_limit_error_penalties_total"),
+            &DebugValue::Counter(1)
+        );
+        assert_eq!(value("http_requests_total"), &DebugValue::Counter(1));
+        assert_eq!(value("rate_limit_dry_run_total"), &DebugValue::Counter(1));
+        assert_eq!(
+            value("rate_limit_blocked_ips"),
+            &DebugValue::Gauge(3.0.into())
+        );
+        match value("http_request_duration_seconds") {
+            DebugValue::Histogram(samples) => assert_eq!(samples.len(), 1),
+            other => panic!("expected histogram, got {:?}", other),
+        }
+    }
+}
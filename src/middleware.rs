@@ -16,20 +16,28 @@
  */
 
 use crate::{
+    action::ActionGuard,
+    events::LimiterEvent,
+    heavy_hitters::HeavyHitterKind,
+    honeypot::HoneypotResponse,
     limiter::RateLimiter,
-    types::{AuthRefundCallback, OnBlocked, SecurityContext},
+    rules::RuleAction,
+    screener::ScreeningRequest,
+    types::{
+        AuthRefundCallback, BlockReason, OnBlocked, RateLimitDecision, RateLimitOutcome,
+        SecurityContext,
+    },
 };
 use axum::{
     extract::State,
     http::{Request, StatusCode},
     middleware::Next,
-    response::{IntoResponse, Response},
+    response::{Html, IntoResponse, Response},
 };
 use std::sync::{
     atomic::{AtomicBool, Ordering},
     Arc,
 };
-#[cfg(feature = "metrics")]
 use std::time::Instant;
 
 /// HTTP 418 I'm a teapot - used to indicate obviously malicious requests
@@ -40,7 +48,6 @@ pub async fn rate_limit_middleware<B: OnBlocked + 'static>(
     request: Request<axum::body::Body>,
     next: Next,
 ) -> Response {
-    #[cfg(feature = "metrics")]
     let start = Instant::now();
 
     let security_context = match request.extensions().get::<SecurityContext>() {
@@ -58,19 +65,124 @@ pub async fn rate_limit_middleware<B: OnBlocked + 'static>(
         .check_rate_limit(&rate_limit_key, &security_context, &path)
         .await;
 
+    limiter.record_heavy_hitter(HeavyHitterKind::Requests, &rate_limit_key);
+
     // Store tokens in request extensions for access logging
     let mut request = request;
     request.extensions_mut().insert(tokens);
 
+    // Snapshot the configuration and screener together so a concurrent reload
+    // can't change them mid-request
+    let (config, screener) = limiter.settings();
+    let dry_run = config.dry_run;
+
+    let mut decision = RateLimitDecision {
+        context: security_context.clone(),
+        tokens_before: tokens,
+        tokens_after: tokens,
+        outcome: RateLimitOutcome::Allowed,
+        screening_reason: None,
+        screening_rules: Vec::new(),
+        dry_run,
+    };
     if !is_allowed {
+        decision.outcome = if newly_blocked {
+            RateLimitOutcome::Blocked
+        } else {
+            RateLimitOutcome::RateLimited
+        };
+    }
+
+    // Decoy paths ban the key whatever its current state, so an already
+    // blocked scanner gets the same response as before
+    if let Some(honeypot) = limiter.honeypot().filter(|h| h.is_decoy(&path)) {
+        let newly_blocked = limiter.trip_honeypot(&rate_limit_key, &security_context, &path);
+        limiter.record_heavy_hitter(HeavyHitterKind::ScreeningHits, &rate_limit_key);
+        limiter.emit(|| LimiterEvent::Honeypot {
+            key: rate_limit_key.clone(),
+            path: path.clone(),
+            user_agent: security_context.user_agent.clone(),
+            newly_blocked,
+            dry_run,
+        });
+
+        decision.outcome = RateLimitOutcome::Honeypot;
+        if dry_run {
+            // A dry-run limiter keeps shadow bucket state, so the ban above is
+            // recorded but not enforced
+            tracing::warn!(
+                "Dry run: IP would have been banned by honeypot: {} (path: {}, user-agent: {})",
+                security_context.ip_address,
+                &path,
+                security_context.user_agent
+            );
+            limiter.telemetry().record_dry_run("honeypot");
+        } else {
+            if newly_blocked {
+                tracing::warn!(
+                    "IP banned by honeypot: {} (path: {}, user-agent: {})",
+                    security_context.ip_address,
+                    &path,
+                    security_context.user_agent
+                );
+                limiter.telemetry().record_block(&rate_limit_key);
+            } else {
+                tracing::debug!(
+                    "Banned IP requested honeypot again: {}",
+                    security_context.ip_address
+                );
+            }
+
+            let response = match &honeypot.config().response {
+                HoneypotResponse::Reject => IM_A_TEAPOT.into_response(),
+                HoneypotResponse::FakeOk { body } => Html(body.clone()).into_response(),
+            };
+
+            limiter
+                .telemetry()
+                .record_http_request(response.status().as_u16(), start);
+
+            decision.tokens_after = 0.0;
+            return with_decision(response, decision);
+        }
+    }
+
+    if !is_allowed {
+        limiter.emit(|| LimiterEvent::Blocked {
+            key: rate_limit_key.clone(),
+            path: path.clone(),
+            newly_blocked,
+            dry_run,
+        });
+    }
+
+    if !is_allowed && dry_run {
+        if newly_blocked {
+            tracing::warn!(
+                "Dry run: IP would have been blocked for rate limiting: {} (path: {})",
+                security_context.ip_address,
+                &path
+            );
+        } else {
+            tracing::debug!(
+                "Dry run: blocked IP would have been rejected: {}",
+                security_context.ip_address
+            );
+        }
+
+        limiter.telemetry().record_dry_run(if newly_blocked {
+            "block"
+        } else {
+            "rate_limited"
+        });
+    } else if !is_allowed {
         if newly_blocked {
             tracing::warn!(
                 "IP blocked for rate limiting: {} (path: {})",
                 security_context.ip_address,
                 &path
             );
-            #[cfg(feature = "metrics")]
-            crate::metrics::record_block(&rate_limit_key);
+            limiter.telemetry().record_block(&rate_limit_key);
         } else {
             tracing::debug!(
                 "Blocked IP attempted access: {}",
@@ -78,50 +190,131 @@ pub async fn rate_limit_middleware<B: OnBlocked + 'static>(
             );
         }
 
-        #[cfg(feature = "metrics")]
-        {
-            let duration = start.elapsed().as_secs_f64();
-            crate::metrics::record_http_request(429, duration);
-        }
+        limiter.telemetry().record_http_request(429, start);
 
-        return StatusCode::TOO_MANY_REQUESTS.into_response();
+        return with_decision(StatusCode::TOO_MANY_REQUESTS.into_response(), decision);
     }
 
     // Screen request for malicious patterns (only if not already blocked)
-    if let Some(screener) = limiter.screener() {
-        if let Some(result) = screener.check(&path, &security_context.user_agent) {
-            tracing::warn!(
-                "Malicious request screened: {} from {} (user-agent: {}, reason: {})",
-                path,
-                security_context.ip_address,
-                security_context.user_agent,
-                result.reason
-            );
+    if let Some(screener) = screener {
+        if let Some(result) = screener.check_request(
+            &ScreeningRequest::from_request(&request, &security_context.user_agent)
+                .with_key(&rate_limit_key),
+        ) {
+            limiter.record_heavy_hitter(HeavyHitterKind::ScreeningHits, &rate_limit_key);
+            let screening_dry_run = dry_run || screener.is_dry_run();
+            limiter.emit(|| LimiterEvent::Screened {
+                key: rate_limit_key.clone(),
+                path: path.clone(),
+                user_agent: security_context.user_agent.clone(),
+                reason: result.reason.to_string(),
+                rule_ids: result.matched_rules.clone(),
+                action: result.action.clone(),
+                dry_run: screening_dry_run,
+            });
+            decision.screening_reason = Some(result.reason.to_string());
+            decision.screening_rules = result.matched_rules.clone();
 
-            limiter.block_immediately(&rate_limit_key);
+            if !result.action.rejects() {
+                tracing::warn!(
+                    "Screening rule {} matched (log only): {} from {} (user-agent: {}, reason: {})",
+                    result.rule_id,
+                    path,
+                    security_context.ip_address,
+                    security_context.user_agent,
+                    result.reason
+                );
+            } else if screening_dry_run {
+                decision.outcome = RateLimitOutcome::Screened;
+                decision.dry_run = true;
+                tracing::warn!(
+                    "Dry run: request would have been screened by rule {} ({}): {} from {} (user-agent: {}, reason: {})",
+                    result.rule_id,
+                    result.action,
+                    path,
+                    security_context.ip_address,
+                    security_context.user_agent,
+                    result.reason
+                );
 
-            #[cfg(feature = "metrics")]
-            {
-                crate::metrics::record_screening_block(&rate_limit_key, &result.reason.to_string());
-                let duration = start.elapsed().as_secs_f64();
-                crate::metrics::record_http_request(418, duration);
-            }
+                // A dry-run limiter keeps shadow bucket state, so record the block there
+                if !screener.is_dry_run() && result.action == RuleAction::BlockIp {
+                    limiter.block_with_reason(
+                        &rate_limit_key,
+                        &security_context,
+                        &path,
+                        BlockReason::Screening,
+                        Some(result.reason.pattern()),
+                    );
+                }
+
+                limiter.telemetry().record_dry_run("screening");
+            } else {
+                decision.outcome = RateLimitOutcome::Screened;
+                tracing::warn!(
+                    "Malicious request screened by rule {} ({}): {} from {} (user-agent: {}, reason: {})",
+                    result.rule_id,
+                    result.action,
+                    path,
+                    security_context.ip_address,
+                    security_context.user_agent,
+                    result.reason
+                );
+
+                match result.action {
+                    RuleAction::BlockIp => limiter.block_with_reason(
+                        &rate_limit_key,
+                        &security_context,
+                        &path,
+                        BlockReason::Screening,
+                        Some(result.reason.pattern()),
+                    ),
+                    RuleAction::Tarpit { delay } => tokio::time::sleep(delay).await,
+                    RuleAction::RejectOnly | RuleAction::LogOnly | RuleAction::Score(_) => {}
+                }
 
-            return IM_A_TEAPOT.into_response();
+                let telemetry = limiter.telemetry();
+                telemetry.record_screening_block(&rate_limit_key, &result.rule_id);
+                telemetry.record_http_request(418, start);
+
+                return with_decision(IM_A_TEAPOT.into_response(), decision);
+            }
         }
     }
 
-    let auth_refund_ratio = limiter.config().auth_refund_ratio;
+    if is_allowed {
+        limiter.emit(|| LimiterEvent::Allowed {
+            key: rate_limit_key.clone(),
+            path: path.clone(),
+            tokens,
+        });
+    }
+
+    if let Some(checker) = limiter.action_checker() {
+        let guard = ActionGuard::new(checker, rate_limit_key.clone(), dry_run);
+        #[cfg(feature = "metrics")]
+        let guard = guard.with_metrics_sink(limiter.metrics().clone());
+        request.extensions_mut().insert(guard);
+    }
+
+    let auth_refund_ratio = config.auth_refund_ratio;
     let auth_refund_fired = Arc::new(AtomicBool::new(false));
     if auth_refund_ratio > 0.0 {
         let limiter_for_refund = limiter.clone();
         let key_for_refund = rate_limit_key.clone();
         let fired = auth_refund_fired.clone();
+        let path_for_refund = path.clone();
         request
             .extensions_mut()
             .insert(AuthRefundCallback(Arc::new(move || {
                 if !fired.swap(true, Ordering::Relaxed) {
                     limiter_for_refund.refund_tokens(&key_for_refund, auth_refund_ratio);
+                    limiter_for_refund.emit(|| LimiterEvent::Refunded {
+                        key: key_for_refund.clone(),
+                        path: path_for_refund.clone(),
+                        amount: auth_refund_ratio,
+                        tokens: limiter_for_refund.tokens(&key_for_refund).unwrap_or(0.0),
+                    });
                 }
             })));
     }
@@ -129,24 +322,63 @@ pub async fn rate_limit_middleware<B: OnBlocked + 'static>(
     let response = next.run(request).await;
 
     let status = response.status();
+    // A dry-run rejection stays the reported outcome
+    let rejected = decision.outcome != RateLimitOutcome::Allowed;
+    if auth_refund_fired.load(Ordering::Relaxed) && !rejected {
+        decision.outcome = RateLimitOutcome::Refunded;
+    }
 
     if status == StatusCode::NOT_MODIFIED && !auth_refund_fired.load(Ordering::Relaxed) {
-        let refund_amount = limiter.config().cache_refund_ratio;
+        let refund_amount = config.cache_refund_ratio;
         limiter.refund_tokens(&rate_limit_key, refund_amount);
-        #[cfg(feature = "metrics")]
-        crate::metrics::record_cache_refund(&rate_limit_key);
+        limiter.emit(|| LimiterEvent::Refunded {
+            key: rate_limit_key.clone(),
+            path: path.clone(),
+            amount: refund_amount,
+            tokens: limiter.tokens(&rate_limit_key).unwrap_or(0.0),
+        });
+        if !rejected {
+            decision.outcome = RateLimitOutcome::Refunded;
+        }
+        if dry_run {
+            limiter.telemetry().record_dry_run("refund");
+        } else {
+            limiter.telemetry().record_cache_refund(&rate_limit_key);
+        }
     } else if status.is_client_error() || status.is_server_error() {
-        let penalty_amount = limiter.config().error_penalty_tokens;
+        let penalty_amount = config.error_penalty_tokens;
         limiter.consume_additional_tokens(&rate_limit_key, penalty_amount);
-        #[cfg(feature = "metrics")]
-        crate::metrics::record_error_penalty(&rate_limit_key, status.as_u16());
+        limiter.record_heavy_hitter(HeavyHitterKind::Penalties, &rate_limit_key);
+        limiter.emit(|| LimiterEvent::Penalized {
+            key: rate_limit_key.clone(),
+            path: path.clone(),
+            status: status.as_u16(),
+            amount: penalty_amount,
+            tokens: limiter.tokens(&rate_limit_key).unwrap_or(0.0),
+        });
+        if !rejected {
+            decision.outcome = RateLimitOutcome::Penalized;
+        }
+        if dry_run {
+            limiter.telemetry().record_dry_run("penalty");
+        } else {
+            limiter
+                .telemetry()
+                .record_error_penalty(&rate_limit_key, status.as_u16());
+        }
     }
 
-    #[cfg(feature = "metrics")]
-    {
-        let duration = start.elapsed().as_secs_f64();
-        crate::metrics::record_http_request(status.as_u16(), duration);
-    }
+    limiter
+        .telemetry()
+        .record_http_request(status.as_u16(), start);
+
+    decision.tokens_after = limiter.tokens(&rate_limit_key).unwrap_or(tokens);
+    with_decision(response, decision)
+}
 
+fn with_decision(mut response: Response, decision: RateLimitDecision) -> Response {
+    #[cfg(feature = "otel")]
+    crate::otel::record_decision(&decision);
+    response.extensions_mut().insert(decision);
     response
 }
@@ -0,0 +1,316 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Path normalization applied before screening, so encoded or padded
+//! variants of a path match the same patterns as the plain path.
+
+use std::borrow::Cow;
+
+/// How a request path is normalized before it is matched against screening
+/// patterns. The default undoes percent-encoding (including double encoding),
+/// backslashes, repeated slashes and dot segments, and leaves case alone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct PathNormalization {
+    /// Percent-decoding passes; 2 or more also undoes double encoding
+    /// (`%252e`). 0 disables decoding
+    pub decode_passes: u8,
+    /// Treat `\` as `/`, as some servers do
+    pub backslashes_as_slashes: bool,
+    /// Collapse runs of `/` into one
+    pub collapse_slashes: bool,
+    /// Resolve `.` and `..` segments (RFC 3986 section 5.2.4)
+    pub remove_dot_segments: bool,
+    /// Lowercase the path, for servers with case-insensitive routing
+    pub case_fold: bool,
+}
+
+impl Default for PathNormalization {
+    fn default() -> Self {
+        Self {
+            decode_passes: 3,
+            backslashes_as_slashes: true,
+            collapse_slashes: true,
+            remove_dot_segments: true,
+            case_fold: false,
+        }
+    }
+}
+
+impl PathNormalization {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Match patterns against the raw path.
+    pub fn none() -> Self {
+        Self {
+            decode_passes: 0,
+            backslashes_as_slashes: false,
+            collapse_slashes: false,
+            remove_dot_segments: false,
+            case_fold: false,
+        }
+    }
+
+    pub fn with_decode_passes(mut self, passes: u8) -> Self {
+        self.decode_passes = passes;
+        self
+    }
+
+    pub fn with_backslashes_as_slashes(mut self, enabled: bool) -> Self {
+        self.backslashes_as_slashes = enabled;
+        self
+    }
+
+    pub fn with_collapse_slashes(mut self, enabled: bool) -> Self {
+        self.collapse_slashes = enabled;
+        self
+    }
+
+    pub fn with_remove_dot_segments(mut self, enabled: bool) -> Self {
+        self.remove_dot_segments = enabled;
+        self
+    }
+
+    pub fn with_case_fold(mut self, enabled: bool) -> Self {
+        self.case_fold = enabled;
+        self
+    }
+
+    /// Normalize `path`, borrowing it unchanged when no step applies.
+    pub fn normalize<'a>(&self, path: &'a str) -> Cow<'a, str> {
+        let mut path = Cow::Borrowed(path);
+
+        for _ in 0..self.decode_passes {
+            match percent_decode(&path) {
+                Some(decoded) => path = Cow::Owned(decoded),
+                None => break,
+            }
+        }
+
+        if self.backslashes_as_slashes && path.contains('\\') {
+            path = Cow::Owned(path.replace('\\', "/"));
+        }
+
+        if self.collapse_slashes && path.contains("//") {
+            path = Cow::Owned(collapse_slashes(&path));
+        }
+
+        if self.remove_dot_segments && path.split('/').any(|s| s == "." || s == "..") {
+            path = Cow::Owned(remove_dot_segments(&path));
+        }
+
+        if self.case_fold && path.chars().any(char::is_uppercase) {
+            path = Cow::Owned(path.to_lowercase());
+        }
+
+        path
+    }
+}
+
+/// Decode `%XX` escapes, leaving malformed escapes as they are. Returns
+/// `None` if there was nothing to decode.
+fn percent_decode(input: &str) -> Option<String> {
+    fn hex(byte: u8) -> Option<u8> {
+        (byte as char).to_digit(16).map(|d| d as u8)
+    }
+
+    if !input.contains('%') {
+        return None;
+    }
+
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut changed = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(high), Some(low)) = (hex(bytes[i + 1]), hex(bytes[i + 2])) {
+                decoded.push(high << 4 | low);
+                changed = true;
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    changed.then(|| String::from_utf8_lossy(&decoded).into_owned())
+}
+
+fn collapse_slashes(path: &str) -> String {
+    let mut collapsed = String::with_capacity(path.len());
+    let mut previous_slash = false;
+    for c in path.chars() {
+        if c == '/' && previous_slash {
+            continue;
+        }
+        previous_slash = c == '/';
+        collapsed.push(c);
+    }
+    collapsed
+}
+
+fn remove_dot_segments(path: &str) -> String {
+    let absolute = path.starts_with('/');
+    let segments: Vec<&str> = path.split('/').skip(usize::from(absolute)).collect();
+    let last = segments.len().saturating_sub(1);
+
+    let mut output: Vec<&str> = Vec::with_capacity(segments.len());
+    for (i, segment) in segments.iter().enumerate() {
+        match *segment {
+            "." => {}
+            ".." => {
+                // `..` above the root stays at the root
+                output.pop();
+            }
+            segment => output.push(segment),
+        }
+        // A trailing `.` or `..` still names a directory
+        if i == last && (*segment == "." || *segment == "..") {
+            output.push("");
+        }
+    }
+
+    let joined = output.join("/");
+    if absolute {
+        format!("/{}", joined)
+    } else {
+        joined
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_git_config_evasions_normalize_to_plain_path() {
+        let normalization = PathNormalization::new();
+        let corpus = [
+            "/.git/config",
+            "/%2egit/config",
+            "/%2Egit/config",
+            "/%2e%67%69%74/config",
+            "/%252egit/config",
+            "/%25252egit/config",
+            "//.git//config",
+            "///.git/config",
+            "/.git%2fconfig",
+            "/.git%2Fconfig",
+            "/./.git/./config",
+            "/foo/../.git/config",
+            "/a/b/../../.git/config",
+            "/foo/%2e%2e/.git/config",
+            "/foo/%252e%252e/.git/config",
+            "/foo/%2e%2e%2f.git/config",
+            "/../../.git/config",
+            "/%2e%2e/%2e%2e/.git/config",
+            "/foo\\..\\.git\\config",
+            "/foo/..%5c.git%5cconfig",
+            "/foo/..%255c.git/config",
+            "/foo/.//..//.git/config",
+        ];
+
+        for path in corpus {
+            assert_eq!(
+                normalization.normalize(path),
+                "/.git/config",
+                "evasion not normalized: {}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn test_traversal_evasions_normalize_to_target() {
+        let normalization = PathNormalization::new();
+        let corpus = [
+            "/static/../../../etc/passwd",
+            "/static/..%2f..%2f..%2fetc/passwd",
+            "/static/%2e%2e/%2e%2e/etc/passwd",
+            "/%2e%2e%2f%2e%2e%2fetc%2fpasswd",
+            "/static/%252e%252e%252f%252e%252e%252fetc%252fpasswd",
+            "/static/..\\..\\etc\\passwd",
+            "/static/..%5c..%5cetc%5cpasswd",
+            "/static/....//..//etc/passwd",
+        ];
+
+        for path in corpus {
+            let normalized = normalization.normalize(path);
+            assert!(
+                normalized.ends_with("/etc/passwd"),
+                "traversal not normalized: {} -> {}",
+                path,
+                normalized
+            );
+            assert!(
+                !normalized.split('/').any(|s| s == ".."),
+                "dot segment left in {} -> {}",
+                path,
+                normalized
+            );
+        }
+    }
+
+    #[test]
+    fn test_case_fold_is_opt_in() {
+        assert_eq!(
+            PathNormalization::new().normalize("/.GIT/Config"),
+            "/.GIT/Config"
+        );
+        assert_eq!(
+            PathNormalization::new()
+                .with_case_fold(true)
+                .normalize("/%2EGIT/Config"),
+            "/.git/config"
+        );
+    }
+
+    #[test]
+    fn test_decode_passes_bound_double_encoding() {
+        let single = PathNormalization::new().with_decode_passes(1);
+        assert_eq!(single.normalize("/%252egit/config"), "/%2egit/config");
+        assert_eq!(single.normalize("/%2egit/config"), "/.git/config");
+    }
+
+    #[test]
+    fn test_clean_and_malformed_paths_are_kept() {
+        let normalization = PathNormalization::new();
+        assert!(matches!(
+            normalization.normalize("/blog/hello-world"),
+            Cow::Borrowed("/blog/hello-world")
+        ));
+        assert_eq!(normalization.normalize("/"), "/");
+        assert_eq!(normalization.normalize("/a/.."), "/");
+        assert_eq!(normalization.normalize("/a/b/."), "/a/b/");
+        assert_eq!(normalization.normalize("/100%"), "/100%");
+        assert_eq!(normalization.normalize("/%zz/%4"), "/%zz/%4");
+    }
+
+    #[test]
+    fn test_none_leaves_path_unchanged() {
+        let raw = "//foo/../%2egit\\config";
+        assert_eq!(PathNormalization::none().normalize(raw), raw);
+    }
+}
@@ -0,0 +1,386 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! OpenTelemetry instruments and span attributes for the limiter.
+//!
+//! Counters and histograms are created from the meter given to
+//! `RateLimiter::with_otel_meter`, or from the global meter provider the first
+//! time a limiter records something. Decisions are attached to the current
+//! `tracing` span through `tracing-opentelemetry`, so they show up on the
+//! request's trace.
+
+use crate::types::{RateLimitDecision, RateLimitOutcome};
+use opentelemetry::{
+    metrics::{Counter, Histogram, Meter},
+    Array, KeyValue, StringValue, Value,
+};
+use std::sync::{Arc, OnceLock};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+const METER_NAME: &str = "basic-axum-rate-limit";
+
+/// The limiter's OpenTelemetry instruments.
+pub struct OtelMetrics {
+    blocks: Counter<u64>,
+    cache_refunds: Counter<u64>,
+    error_penalties: Counter<u64>,
+    screening_blocks: Counter<u64>,
+    dry_run_outcomes: Counter<u64>,
+    http_requests: Counter<u64>,
+    http_request_duration: Histogram<f64>,
+}
+
+impl OtelMetrics {
+    pub fn new(meter: &Meter) -> Self {
+        Self {
+            blocks: meter
+                .u64_counter("rate_limit.blocks")
+                .with_description("Number of keys blocked for exhausting their tokens")
+                .build(),
+            cache_refunds: meter
+                .u64_counter("rate_limit.cache_refunds")
+                .with_description("Number of cache refunds (304 responses)")
+                .build(),
+            error_penalties: meter
+                .u64_counter("rate_limit.error_penalties")
+                .with_description("Number of error penalties applied")
+                .build(),
+            screening_blocks: meter
+                .u64_counter("rate_limit.screening_blocks")
+                .with_description("Number of requests blocked by pattern screening")
+                .build(),
+            dry_run_outcomes: meter
+                .u64_counter("rate_limit.dry_run")
+                .with_description("Decisions that dry-run mode did not enforce, by outcome")
+                .build(),
+            http_requests: meter
+                .u64_counter("http.server.requests")
+                .with_description("Number of HTTP requests by status code")
+                .build(),
+            http_request_duration: meter
+                .f64_histogram("http.server.request.duration")
+                .with_description("HTTP request duration")
+                .with_unit("s")
+                .build(),
+        }
+    }
+
+    pub fn record_block(&self) {
+        self.blocks.add(1, &[]);
+    }
+
+    pub fn record_cache_refund(&self) {
+        self.cache_refunds.add(1, &[]);
+    }
+
+    pub fn record_error_penalty(&self, status: u16) {
+        self.error_penalties.add(
+            1,
+            &[KeyValue::new("http.response.status_code", status as i64)],
+        );
+    }
+
+    /// `rule_id` is the ID of the rule whose action applied. The reason text
+    /// is left to the span, since it can hold request details.
+    pub fn record_screening_block(&self, rule_id: &str) {
+        self.screening_blocks.add(
+            1,
+            &[KeyValue::new(
+                "rate_limit.screening_rule",
+                rule_id.to_string(),
+            )],
+        );
+    }
+
+    pub fn record_dry_run(&self, outcome: &str) {
+        self.dry_run_outcomes.add(
+            1,
+            &[KeyValue::new("rate_limit.outcome", outcome.to_string())],
+        );
+    }
+
+    pub fn record_http_request(&self, status: u16, duration_seconds: f64) {
+        let attributes = [KeyValue::new("http.response.status_code", status as i64)];
+        self.http_requests.add(1, &attributes);
+        self.http_request_duration
+            .record(duration_seconds, &attributes);
+    }
+}
+
+/// The instruments a limiter records to, created on first use.
+#[derive(Clone, Default)]
+pub(crate) struct OtelSink(Arc<OnceLock<OtelMetrics>>);
+
+impl OtelSink {
+    /// Returns `false` if instruments were already created.
+    pub(crate) fn attach(&self, meter: &Meter) -> bool {
+        self.0.set(OtelMetrics::new(meter)).is_ok()
+    }
+
+    pub(crate) fn metrics(&self) -> &OtelMetrics {
+        self.0
+            .get_or_init(|| OtelMetrics::new(&opentelemetry::global::meter(METER_NAME)))
+    }
+}
+
+/// Attach the rate limit decision to the current span.
+pub(crate) fn record_decision(decision: &RateLimitDecision) {
+    let span = tracing::Span::current();
+
+    span.set_attribute("rate_limit.decision", outcome_name(decision.outcome));
+    span.set_attribute("rate_limit.key", decision.context.ip_address.clone());
+    span.set_attribute("rate_limit.tokens_remaining", decision.tokens_after);
+    span.set_attribute("rate_limit.dry_run", decision.dry_run);
+    if !decision.screening_rules.is_empty() {
+        let rules: Vec<StringValue> = decision
+            .screening_rules
+            .iter()
+            .map(|rule| rule.clone().into())
+            .collect();
+        span.set_attribute(
+            "rate_limit.screening_rules",
+            Value::Array(Array::from(rules)),
+        );
+    }
+    if let Some(reason) = &decision.screening_reason {
+        span.set_attribute("rate_limit.screening_reason", reason.clone());
+    }
+
+    let delta = decision.tokens_after - decision.tokens_before;
+    match decision.outcome {
+        RateLimitOutcome::Refunded => span.set_attribute("rate_limit.refund", delta),
+        RateLimitOutcome::Penalized => span.set_attribute("rate_limit.penalty", -delta),
+        _ => {}
+    }
+}
+
+fn outcome_name(outcome: RateLimitOutcome) -> &'static str {
+    match outcome {
+        RateLimitOutcome::Allowed => "allowed",
+        RateLimitOutcome::Refunded => "refunded",
+        RateLimitOutcome::Penalized => "penalized",
+        RateLimitOutcome::Screened => "screened",
+        RateLimitOutcome::Honeypot => "honeypot",
+        RateLimitOutcome::Blocked => "blocked",
+        RateLimitOutcome::RateLimited => "rate_limited",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        context::security_context_middleware, limiter::RateLimiter,
+        middleware::rate_limit_middleware, types::NoOpOnBlocked, RateLimitConfig,
+    };
+    use axum::{
+        body::Body,
+        extract::connect_info::MockConnectInfo,
+        http::Request,
+        middleware::{from_fn, from_fn_with_state},
+        routing::get,
+        Router,
+    };
+    use opentelemetry::{metrics::MeterProvider, trace::TracerProvider, Value};
+    use opentelemetry_sdk::{
+        metrics::{
+            data::{AggregatedMetrics, MetricData},
+            InMemoryMetricExporter, PeriodicReader, SdkMeterProvider,
+        },
+        trace::{InMemorySpanExporter, SdkTracerProvider},
+    };
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tower::ServiceExt;
+    use tracing::Instrument;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    fn app(limiter: RateLimiter<NoOpOnBlocked>) -> Router {
+        Router::new()
+            .route("/", get(|| async { "OK" }))
+            .layer(from_fn_with_state(limiter, rate_limit_middleware))
+            .layer(from_fn(security_context_middleware))
+            .layer(MockConnectInfo(SocketAddr::from(([127, 0, 0, 1], 8080))))
+    }
+
+    async fn get_root(app: &Router) {
+        let request = Request::get("/")
+            .header("X-Forwarded-For", "10.3.0.1")
+            .body(Body::empty())
+            .unwrap();
+        app.clone().oneshot(request).await.unwrap();
+    }
+
+    fn limiter() -> RateLimiter<NoOpOnBlocked> {
+        let config = RateLimitConfig::new(1, Duration::from_secs(60)).with_grace_period(0);
+        RateLimiter::new(config, NoOpOnBlocked)
+    }
+
+    #[tokio::test]
+    async fn test_instruments_export_to_meter() {
+        let exporter = InMemoryMetricExporter::default();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(exporter.clone()).build())
+            .build();
+        let enforcing_app = app(limiter().with_otel_meter(&provider.meter("test")));
+        let config = RateLimitConfig::new(1, Duration::from_secs(60))
+            .with_grace_period(0)
+            .with_dry_run(true);
+        let dry_run_app =
+            app(RateLimiter::new(config, NoOpOnBlocked).with_otel_meter(&provider.meter("test")));
+
+        for app in [&enforcing_app, &dry_run_app] {
+            get_root(app).await;
+            get_root(app).await;
+        }
+        provider.force_flush().unwrap();
+
+        let metrics = exporter.get_finished_metrics().unwrap();
+        let sum = |name: &str| -> u64 {
+            metrics
+                .iter()
+                .flat_map(|resource| resource.scope_metrics())
+                .flat_map(|scope| scope.metrics())
+                .filter(|metric| metric.name() == name)
+                .map(|metric| match metric.data() {
+                    AggregatedMetrics::U64(MetricData::Sum(sum)) => {
+                        sum.data_points().map(|point| point.value()).sum()
+                    }
+                    _ => 0,
+                })
+                .last()
+                .unwrap_or_else(|| panic!("missing metric {}", name))
+        };
+
+        assert_eq!(sum("rate_limit.blocks"), 1);
+        assert_eq!(sum("rate_limit.dry_run"), 1);
+        assert_eq!(sum("http.server.requests"), 4);
+    }
+
+    #[tokio::test]
+    async fn test_screening_records_rule_id() {
+        use crate::screener::{RequestScreener, ScreeningConfig};
+        use opentelemetry::{trace::TracerProvider as _, Array, StringValue};
+
+        let metric_exporter = InMemoryMetricExporter::default();
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(metric_exporter.clone()).build())
+            .build();
+        let span_exporter = InMemorySpanExporter::default();
+        let tracer_provider = SdkTracerProvider::builder()
+            .with_simple_exporter(span_exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer("test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let screener =
+            RequestScreener::new(&ScreeningConfig::new().with_path_pattern(r"\.php$")).unwrap();
+        let app = app(limiter()
+            .with_screener(screener)
+            .with_otel_meter(&meter_provider.meter("test")));
+        let request = Request::get("/index.php")
+            .header("X-Forwarded-For", "10.3.0.2")
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(request)
+            .instrument(tracing::info_span!("request"))
+            .await
+            .unwrap();
+        meter_provider.force_flush().unwrap();
+        tracer_provider.force_flush().unwrap();
+
+        let rules: Vec<String> = metric_exporter
+            .get_finished_metrics()
+            .unwrap()
+            .iter()
+            .flat_map(|resource| resource.scope_metrics())
+            .flat_map(|scope| scope.metrics())
+            .filter(|metric| metric.name() == "rate_limit.screening_blocks")
+            .flat_map(|metric| match metric.data() {
+                AggregatedMetrics::U64(MetricData::Sum(sum)) => sum
+                    .data_points()
+                    .flat_map(|point| point.attributes())
+                    .map(|kv| kv.value.to_string())
+                    .collect::<Vec<_>>(),
+                _ => Vec::new(),
+            })
+            .collect();
+        assert_eq!(rules, vec!["path_patterns[0]"]);
+
+        let spans = span_exporter.get_finished_spans().unwrap();
+        let attribute = |key: &str| {
+            spans[0]
+                .attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.clone())
+        };
+        assert_eq!(
+            attribute("rate_limit.screening_rules"),
+            Some(Value::Array(Array::from(vec![StringValue::from(
+                "path_patterns[0]"
+            )])))
+        );
+        assert!(attribute("rate_limit.screening_reason").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_decision_recorded_on_current_span() {
+        let exporter = InMemorySpanExporter::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+        let app = app(limiter());
+
+        for _ in 0..2 {
+            get_root(&app)
+                .instrument(tracing::info_span!("request"))
+                .await;
+        }
+        provider.force_flush().unwrap();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        assert_eq!(spans.len(), 2);
+        let attribute = |span: usize, key: &str| {
+            spans[span]
+                .attributes
+                .iter()
+                .find(|kv| kv.key.as_str() == key)
+                .map(|kv| kv.value.clone())
+        };
+
+        assert_eq!(
+            attribute(0, "rate_limit.decision"),
+            Some(Value::from("allowed"))
+        );
+        assert_eq!(
+            attribute(0, "rate_limit.key"),
+            Some(Value::from("10.3.0.1"))
+        );
+        assert_eq!(
+            attribute(0, "rate_limit.tokens_remaining"),
+            Some(Value::from(0.0))
+        );
+        assert_eq!(
+            attribute(1, "rate_limit.decision"),
+            Some(Value::from("blocked"))
+        );
+    }
+}
@@ -0,0 +1,515 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Structural checks on the request line and headers, for scanners that
+//! give themselves away by what they leave out rather than what they send.
+
+use crate::config::ConfigError;
+use crate::rules::{RuleAction, ScreeningRule};
+use crate::screener::ScreeningRequest;
+use axum::http::{HeaderName, Method};
+use std::net::IpAddr;
+
+/// One of the checks in `ProtocolChecks`. Each enabled check is a screening
+/// rule with the ID from `rule_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProtocolCheck {
+    RequiredHeaders,
+    Host,
+    Method,
+    HeaderCount,
+    HeaderSize,
+}
+
+impl ProtocolCheck {
+    pub const ALL: [ProtocolCheck; 5] = [
+        Self::RequiredHeaders,
+        Self::Host,
+        Self::Method,
+        Self::HeaderCount,
+        Self::HeaderSize,
+    ];
+
+    pub fn rule_id(&self) -> &'static str {
+        match self {
+            Self::RequiredHeaders => "protocol.required_headers",
+            Self::Host => "protocol.host",
+            Self::Method => "protocol.method",
+            Self::HeaderCount => "protocol.header_count",
+            Self::HeaderSize => "protocol.header_size",
+        }
+    }
+}
+
+impl std::fmt::Display for ProtocolCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::RequiredHeaders => "missing required header",
+            Self::Host => "host not allowed",
+            Self::Method => "method not allowed",
+            Self::HeaderCount => "too many headers",
+            Self::HeaderSize => "header too large",
+        })
+    }
+}
+
+/// Checks on the method, `Host` and header set. Every check is off by
+/// default; checks that need headers are skipped when the request was
+/// screened without them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
+pub struct ProtocolChecks {
+    /// Headers every request must send, e.g. `user-agent` and `accept`
+    pub required_headers: Vec<String>,
+    /// Allowed `Host` names, compared case-insensitively without the port.
+    /// `*.example.com` matches any subdomain. Empty allows any host
+    pub allowed_hosts: Vec<String>,
+    /// Reject requests whose `Host` is an IP address
+    pub reject_ip_host: bool,
+    /// Allowed methods; empty allows any method
+    pub allowed_methods: Vec<String>,
+    /// Maximum number of header values
+    pub max_headers: Option<usize>,
+    /// Maximum length of one header's name and value together, in bytes
+    pub max_header_bytes: Option<usize>,
+    /// What happens when a check fails; `RejectOnly` unless set
+    pub action: RuleAction,
+}
+
+impl Default for ProtocolChecks {
+    fn default() -> Self {
+        Self {
+            required_headers: Vec::new(),
+            allowed_hosts: Vec::new(),
+            reject_ip_host: false,
+            allowed_methods: Vec::new(),
+            max_headers: None,
+            max_header_bytes: None,
+            action: RuleAction::RejectOnly,
+        }
+    }
+}
+
+impl ProtocolChecks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require a header, e.g. `user-agent`. Call once per header.
+    pub fn with_required_header(mut self, header: &str) -> Self {
+        self.required_headers.push(header.to_ascii_lowercase());
+        self
+    }
+
+    /// Allow a `Host`, e.g. `example.com` or `*.example.com`. Call once per
+    /// host.
+    pub fn with_allowed_host(mut self, host: &str) -> Self {
+        self.allowed_hosts.push(host.to_ascii_lowercase());
+        self
+    }
+
+    pub fn with_reject_ip_host(mut self, reject: bool) -> Self {
+        self.reject_ip_host = reject;
+        self
+    }
+
+    /// Allow a method, e.g. `GET`. Call once per method.
+    pub fn with_allowed_method(mut self, method: &str) -> Self {
+        self.allowed_methods.push(method.to_ascii_uppercase());
+        self
+    }
+
+    pub fn with_max_headers(mut self, max: usize) -> Self {
+        self.max_headers = Some(max);
+        self
+    }
+
+    pub fn with_max_header_bytes(mut self, max: usize) -> Self {
+        self.max_header_bytes = Some(max);
+        self
+    }
+
+    pub fn with_action(mut self, action: RuleAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        ProtocolCheck::ALL.iter().any(|check| self.enables(*check))
+    }
+
+    pub fn enables(&self, check: ProtocolCheck) -> bool {
+        match check {
+            ProtocolCheck::RequiredHeaders => !self.required_headers.is_empty(),
+            ProtocolCheck::Host => !self.allowed_hosts.is_empty() || self.reject_ip_host,
+            ProtocolCheck::Method => !self.allowed_methods.is_empty(),
+            ProtocolCheck::HeaderCount => self.max_headers.is_some(),
+            ProtocolCheck::HeaderSize => self.max_header_bytes.is_some(),
+        }
+    }
+
+    /// A rule for each enabled check. They have no targets or pattern and are
+    /// never matched by regex.
+    pub(crate) fn rules(&self) -> Vec<ScreeningRule> {
+        let described = |check: ProtocolCheck, description: String| {
+            ScreeningRule::new(check.rule_id(), "")
+                .with_description(&description)
+                .with_action(self.action.clone())
+        };
+        ProtocolCheck::ALL
+            .into_iter()
+            .filter(|check| self.enables(*check))
+            .map(|check| match check {
+                ProtocolCheck::RequiredHeaders => described(
+                    check,
+                    format!("Requires headers: {}", self.required_headers.join(", ")),
+                ),
+                ProtocolCheck::Host if self.allowed_hosts.is_empty() => {
+                    described(check, "Rejects IP address hosts".to_string())
+                }
+                ProtocolCheck::Host => described(
+                    check,
+                    format!("Allows hosts: {}", self.allowed_hosts.join(", ")),
+                ),
+                ProtocolCheck::Method => described(
+                    check,
+                    format!("Allows methods: {}", self.allowed_methods.join(", ")),
+                ),
+                ProtocolCheck::HeaderCount => described(
+                    check,
+                    format!("At most {} headers", self.max_headers.unwrap_or_default()),
+                ),
+                ProtocolCheck::HeaderSize => described(
+                    check,
+                    format!(
+                        "Headers of at most {} bytes",
+                        self.max_header_bytes.unwrap_or_default()
+                    ),
+                ),
+            })
+            .collect()
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), ConfigError> {
+        for (i, header) in self.required_headers.iter().enumerate() {
+            if let Err(e) = HeaderName::from_bytes(header.as_bytes()) {
+                return Err(ConfigError::invalid(
+                    format!("protocol.required_headers[{}]", i),
+                    e.to_string(),
+                ));
+            }
+        }
+        for (i, host) in self.allowed_hosts.iter().enumerate() {
+            if host.trim_start_matches("*.").is_empty() {
+                return Err(ConfigError::invalid(
+                    format!("protocol.allowed_hosts[{}]", i),
+                    "host must not be empty",
+                ));
+            }
+        }
+        for (i, method) in self.allowed_methods.iter().enumerate() {
+            if let Err(e) = Method::from_bytes(method.as_bytes()) {
+                return Err(ConfigError::invalid(
+                    format!("protocol.allowed_methods[{}]", i),
+                    e.to_string(),
+                ));
+            }
+        }
+        if self.max_headers == Some(0) {
+            return Err(ConfigError::invalid(
+                "protocol.max_headers",
+                "must be greater than 0",
+            ));
+        }
+        if self.max_header_bytes == Some(0) {
+            return Err(ConfigError::invalid(
+                "protocol.max_header_bytes",
+                "must be greater than 0",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Failed checks, each with a description of what was wrong.
+    pub(crate) fn violations(
+        &self,
+        request: &ScreeningRequest<'_>,
+    ) -> Vec<(ProtocolCheck, String)> {
+        let mut violations = Vec::new();
+
+        if let Some(headers) = request.headers {
+            let missing: Vec<&str> = self
+                .required_headers
+                .iter()
+                .filter(|header| !headers.contains_key(header.as_str()))
+                .map(String::as_str)
+                .collect();
+            if !missing.is_empty() {
+                violations.push((
+                    ProtocolCheck::RequiredHeaders,
+                    format!("missing {}", missing.join(", ")),
+                ));
+            }
+
+            if let Some(max) = self.max_headers.filter(|max| headers.len() > *max) {
+                violations.push((
+                    ProtocolCheck::HeaderCount,
+                    format!("{} headers (max {})", headers.len(), max),
+                ));
+            }
+
+            if let Some(max) = self.max_header_bytes {
+                let oversized = headers
+                    .iter()
+                    .find(|(name, value)| name.as_str().len() + value.len() > max);
+                if let Some((name, value)) = oversized {
+                    violations.push((
+                        ProtocolCheck::HeaderSize,
+                        format!(
+                            "{} header is {} bytes (max {})",
+                            name,
+                            name.as_str().len() + value.len(),
+                            max
+                        ),
+                    ));
+                }
+            }
+        }
+
+        if self.enables(ProtocolCheck::Host) {
+            let host = request
+                .host
+                .map(|host| host_name(host).to_ascii_lowercase());
+            match host.as_deref() {
+                Some(host) if self.reject_ip_host && host.parse::<IpAddr>().is_ok() => {
+                    violations.push((ProtocolCheck::Host, format!("IP address host {}", host)));
+                }
+                Some(host) if !self.allowed_hosts.is_empty() && !self.allows_host(host) => {
+                    violations.push((ProtocolCheck::Host, format!("host {} is not allowed", host)));
+                }
+                None if !self.allowed_hosts.is_empty() => {
+                    violations.push((ProtocolCheck::Host, "missing host".to_string()));
+                }
+                _ => {}
+            }
+        }
+
+        if let Some(method) = request.method {
+            if !self.allowed_methods.is_empty()
+                && !self
+                    .allowed_methods
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(method))
+            {
+                violations.push((
+                    ProtocolCheck::Method,
+                    format!("method {} is not allowed", method),
+                ));
+            }
+        }
+
+        violations
+    }
+
+    /// Whether `host` (lowercase) is in the allowlist.
+    fn allows_host(&self, host: &str) -> bool {
+        self.allowed_hosts.iter().any(|allowed| {
+            let allowed = allowed.to_ascii_lowercase();
+            match allowed.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+                None => host == allowed,
+            }
+        })
+    }
+}
+
+/// The host name from a `Host` value, without the port, IPv6 brackets or a
+/// trailing dot.
+fn host_name(host: &str) -> &str {
+    let host = match host.strip_prefix('[') {
+        Some(bracketed) => bracketed.split(']').next().unwrap_or(bracketed),
+        None => host.split(':').next().unwrap_or(host),
+    };
+    host.trim_end_matches('.')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderMap;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    fn failed(checks: &ProtocolChecks, request: ScreeningRequest<'_>) -> Vec<ProtocolCheck> {
+        checks
+            .violations(&request)
+            .into_iter()
+            .map(|(check, _)| check)
+            .collect()
+    }
+
+    #[test]
+    fn test_required_headers_and_limits() {
+        let checks = ProtocolChecks::new()
+            .with_required_header("User-Agent")
+            .with_required_header("accept")
+            .with_max_headers(3)
+            .with_max_header_bytes(40);
+
+        let browser = headers(&[("user-agent", "Mozilla/5.0"), ("accept", "*/*")]);
+        let request = ScreeningRequest::new("/", "Mozilla/5.0");
+        assert!(failed(&checks, request.with_headers(&browser)).is_empty());
+
+        let bare = headers(&[("user-agent", "curl/8.0")]);
+        let violations = checks.violations(&request.with_headers(&bare));
+        assert_eq!(violations[0].0, ProtocolCheck::RequiredHeaders);
+        assert_eq!(violations[0].1, "missing accept");
+
+        let stuffed = headers(&[
+            ("user-agent", "x"),
+            ("accept", "*/*"),
+            ("x-a", "1"),
+            ("cookie", &"a".repeat(40)),
+        ]);
+        assert_eq!(
+            failed(&checks, request.with_headers(&stuffed)),
+            vec![ProtocolCheck::HeaderCount, ProtocolCheck::HeaderSize]
+        );
+
+        // Header checks are skipped without headers
+        assert!(failed(&checks, request).is_empty());
+    }
+
+    #[test]
+    fn test_host_and_method() {
+        let checks = ProtocolChecks::new()
+            .with_allowed_host("example.com")
+            .with_allowed_host("*.example.net")
+            .with_reject_ip_host(true)
+            .with_allowed_method("get")
+            .with_allowed_method("POST");
+        let request = |host| {
+            ScreeningRequest::new("/", "Mozilla/5.0")
+                .with_host(host)
+                .with_method("GET")
+        };
+
+        for host in [
+            "example.com",
+            "EXAMPLE.com:8443",
+            "example.com.",
+            "api.example.net",
+        ] {
+            assert!(failed(&checks, request(host)).is_empty(), "{}", host);
+        }
+        for host in [
+            "example.net",
+            "evil.com",
+            "notexample.net",
+            "203.0.113.9",
+            "[::1]:80",
+        ] {
+            assert_eq!(
+                failed(&checks, request(host)),
+                vec![ProtocolCheck::Host],
+                "{}",
+                host
+            );
+        }
+        assert_eq!(
+            failed(&checks, ScreeningRequest::new("/", "x").with_method("GET")),
+            vec![ProtocolCheck::Host]
+        );
+
+        let ip_only = ProtocolChecks::new().with_reject_ip_host(true);
+        assert!(failed(&ip_only, ScreeningRequest::new("/", "x")).is_empty());
+        assert!(failed(&ip_only, request("anything.example")).is_empty());
+
+        assert_eq!(
+            failed(&checks, request("example.com").with_method("PROPFIND")),
+            vec![ProtocolCheck::Method]
+        );
+        assert!(failed(&checks, request("example.com").with_method("post")).is_empty());
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(ProtocolChecks::new().validate().is_ok());
+        assert_eq!(ProtocolChecks::new().action, RuleAction::RejectOnly);
+        assert!(!ProtocolChecks::new().is_enabled());
+        let err = ProtocolChecks::new()
+            .with_required_header("bad header")
+            .validate()
+            .unwrap_err();
+        assert!(err.to_string().contains("protocol.required_headers[0]"));
+        assert!(ProtocolChecks::new()
+            .with_allowed_method("GET POST")
+            .validate()
+            .is_err());
+        assert!(ProtocolChecks::new()
+            .with_max_headers(0)
+            .validate()
+            .is_err());
+        assert!(ProtocolChecks::new()
+            .with_allowed_host("*.")
+            .validate()
+            .is_err());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_from_toml() {
+        let config = crate::screener::ScreeningConfig::from_toml_str(
+            r#"
+            [protocol]
+            required_headers = ["user-agent", "accept"]
+            allowed_hosts = ["example.com", "*.example.com"]
+            reject_ip_host = true
+            allowed_methods = ["GET", "POST"]
+            max_headers = 64
+            action = "block_ip"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.protocol.allowed_hosts.len(), 2);
+        assert_eq!(config.protocol.max_headers, Some(64));
+        assert_eq!(config.protocol.max_header_bytes, None);
+        assert_eq!(config.protocol.action, RuleAction::BlockIp);
+
+        // Leaving the action out keeps the default
+        let config = crate::screener::ScreeningConfig::from_toml_str(
+            r#"
+            [protocol]
+            reject_ip_host = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.protocol.action, RuleAction::RejectOnly);
+    }
+}
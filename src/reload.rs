@@ -0,0 +1,423 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Reloading limiter configuration and screener rules from a file.
+
+use crate::{
+    config::{ConfigError, RateLimitConfig},
+    limiter::RateLimiter,
+    screener::{RequestScreener, ScreeningConfig},
+    types::OnBlocked,
+};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::task::JoinHandle;
+
+/// Configuration produced by a [`ConfigWatcher`] loader.
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct ReloadedConfig {
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub rate_limit: RateLimitConfig,
+    /// `None` removes the screener from the limiter.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub screening: Option<ScreeningConfig>,
+}
+
+impl ReloadedConfig {
+    /// Parse and validate a TOML document with `[rate_limit]` and `[screening]`
+    /// tables. Field errors are reported with their section, e.g.
+    /// `rate_limit.cache_refund_ratio`.
+    #[cfg(feature = "serde")]
+    pub fn from_toml_str(input: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(input)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        self.rate_limit
+            .validate()
+            .map_err(|e| e.in_section("rate_limit"))?;
+        if let Some(screening) = &self.screening {
+            screening
+                .validate()
+                .map_err(|e| e.in_section("screening"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Error returned when a configuration file cannot be reloaded.
+#[derive(Debug)]
+pub enum ReloadError {
+    /// The file could not be read.
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// The loader rejected the file contents.
+    Parse {
+        path: PathBuf,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+    /// The loaded configuration failed validation.
+    Invalid { path: PathBuf, source: ConfigError },
+    /// The screening configuration was rejected by `RequestScreener::new`.
+    Screening { path: PathBuf, source: ConfigError },
+}
+
+impl std::fmt::Display for ReloadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io { path, source } => {
+                write!(f, "Failed to read {}: {}", path.display(), source)
+            }
+            Self::Parse { path, source } => {
+                write!(f, "Invalid configuration in {}: {}", path.display(), source)
+            }
+            Self::Invalid { path, source } => {
+                write!(f, "Invalid configuration in {}: {}", path.display(), source)
+            }
+            Self::Screening { path, source } => {
+                write!(
+                    f,
+                    "Invalid screening pattern in {}: {}",
+                    path.display(),
+                    source
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ReloadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io { source, .. } => Some(source),
+            Self::Parse { source, .. } => Some(source.as_ref()),
+            Self::Invalid { source, .. } => Some(source),
+            Self::Screening { source, .. } => Some(source),
+        }
+    }
+}
+
+type Loader = Arc<
+    dyn Fn(&str) -> Result<ReloadedConfig, Box<dyn std::error::Error + Send + Sync>> + Send + Sync,
+>;
+
+/// Polls a configuration file and applies it to a [`RateLimiter`] when it changes.
+///
+/// The loader turns the file contents into a [`ReloadedConfig`], which is
+/// validated before it is applied. If reading, parsing, validating or
+/// compiling the screening patterns fails, the error is logged and the limiter
+/// keeps its current configuration. Otherwise the configuration and screener
+/// are swapped together with [`RateLimiter::apply_config`].
+///
+/// # Example
+/// ```rust,ignore
+/// let watcher = ConfigWatcher::new("/etc/myapp/rate-limit.conf", |contents| {
+///     Ok(ReloadedConfig {
+///         rate_limit: parse_rate_limit(contents)?,
+///         screening: Some(parse_screening(contents)?),
+///     })
+/// })
+/// .with_poll_interval(Duration::from_secs(10));
+///
+/// let handle = watcher.spawn(rate_limiter.clone());
+/// ```
+#[derive(Clone)]
+pub struct ConfigWatcher {
+    path: PathBuf,
+    poll_interval: Duration,
+    loader: Loader,
+}
+
+impl ConfigWatcher {
+    pub fn new<F>(path: impl Into<PathBuf>, loader: F) -> Self
+    where
+        F: Fn(&str) -> Result<ReloadedConfig, Box<dyn std::error::Error + Send + Sync>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Self {
+            path: path.into(),
+            poll_interval: Duration::from_secs(5),
+            loader: Arc::new(loader),
+        }
+    }
+
+    /// Watch a TOML file in the format accepted by [`ReloadedConfig::from_toml_str`].
+    #[cfg(feature = "serde")]
+    pub fn toml(path: impl Into<PathBuf>) -> Self {
+        Self::new(path, |contents| {
+            Ok(ReloadedConfig::from_toml_str(contents)?)
+        })
+    }
+
+    /// How often the file's modification time is checked (default: 5 seconds).
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read, parse and apply the file immediately.
+    pub fn reload_now<B: OnBlocked + 'static>(
+        &self,
+        limiter: &RateLimiter<B>,
+    ) -> Result<(), ReloadError> {
+        let contents = std::fs::read_to_string(&self.path).map_err(|source| ReloadError::Io {
+            path: self.path.clone(),
+            source,
+        })?;
+
+        let reloaded = (self.loader)(&contents).map_err(|source| ReloadError::Parse {
+            path: self.path.clone(),
+            source,
+        })?;
+        reloaded.validate().map_err(|source| ReloadError::Invalid {
+            path: self.path.clone(),
+            source,
+        })?;
+
+        // Compile before applying anything so a bad pattern leaves both untouched
+        let screener = reloaded
+            .screening
+            .as_ref()
+            .map(RequestScreener::new)
+            .transpose()
+            .map_err(|source| ReloadError::Screening {
+                path: self.path.clone(),
+                source,
+            })?;
+
+        limiter.apply_config(reloaded.rate_limit, screener);
+
+        tracing::info!(
+            "Reloaded rate limit configuration from {}",
+            self.path.display()
+        );
+        Ok(())
+    }
+
+    /// Spawn a task that applies the file once, then reloads it whenever its
+    /// modification time changes. Abort the returned handle to stop watching.
+    pub fn spawn<B: OnBlocked + 'static>(self, limiter: RateLimiter<B>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut last_modified = self.modified();
+            if let Err(e) = self.reload_now(&limiter) {
+                tracing::error!("Keeping current rate limit configuration: {}", e);
+            }
+            let mut interval = tokio::time::interval(self.poll_interval);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+            loop {
+                interval.tick().await;
+
+                let modified = self.modified();
+                if modified.is_none() || modified == last_modified {
+                    continue;
+                }
+                last_modified = modified;
+
+                if let Err(e) = self.reload_now(&limiter) {
+                    tracing::error!("Keeping current rate limit configuration: {}", e);
+                }
+            }
+        })
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::NoOpOnBlocked;
+
+    /// Test format: first line is requests per minute, remaining lines are path patterns.
+    fn load(contents: &str) -> Result<ReloadedConfig, Box<dyn std::error::Error + Send + Sync>> {
+        let mut lines = contents.lines();
+        let rate: u32 = lines.next().unwrap_or_default().trim().parse()?;
+        let patterns: Vec<String> = lines.map(|line| line.to_string()).collect();
+
+        Ok(ReloadedConfig {
+            rate_limit: RateLimitConfig::new(rate, Duration::from_secs(60)),
+            screening: (!patterns.is_empty())
+                .then(|| ScreeningConfig::new().with_path_patterns(patterns)),
+        })
+    }
+
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "basic-axum-rate-limit-{}-{}",
+            std::process::id(),
+            name
+        ));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_reload_now_applies_config_and_screener() {
+        let path = temp_file("reload-now", "20\n\\.php$\n");
+        let limiter = RateLimiter::new(RateLimitConfig::default(), NoOpOnBlocked);
+        let clone = limiter.clone();
+
+        ConfigWatcher::new(&path, load)
+            .reload_now(&limiter)
+            .unwrap();
+
+        assert_eq!(clone.config().rate_limit_per_minute, 20);
+        let screener = clone.screener().expect("screener should be installed");
+        assert!(screener.check("/index.php", "Mozilla/5.0").is_some());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_pattern_keeps_current_config() {
+        let path = temp_file("invalid-pattern", "20\n(unclosed\n");
+        let limiter = RateLimiter::new(RateLimitConfig::default(), NoOpOnBlocked);
+
+        let result = ConfigWatcher::new(&path, load).reload_now(&limiter);
+
+        // Validation catches the pattern before the screener is compiled
+        assert!(
+            matches!(&result, Err(ReloadError::Invalid { source, .. }) if source.to_string().contains("screening."))
+        );
+        assert_eq!(limiter.config().rate_limit_per_minute, 50);
+        assert!(limiter.screener().is_none());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_invalid_config_keeps_current_config() {
+        let path = temp_file("invalid-config", "0\n\\.php$\n");
+        let limiter = RateLimiter::new(RateLimitConfig::default(), NoOpOnBlocked);
+
+        let result = ConfigWatcher::new(&path, load).reload_now(&limiter);
+
+        assert!(matches!(result, Err(ReloadError::Invalid { .. })));
+        assert_eq!(limiter.config().rate_limit_per_minute, 50);
+        assert!(limiter.screener().is_none());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_config_and_screener_swap_together() {
+        let limiter = RateLimiter::new(RateLimitConfig::default(), NoOpOnBlocked);
+        let done = Arc::new(std::sync::atomic::AtomicBool::new(false));
+
+        let reader = {
+            let limiter = limiter.clone();
+            let done = done.clone();
+            std::thread::spawn(move || {
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    let (config, screener) = limiter.settings();
+                    assert_eq!(config.rate_limit_per_minute == 20, screener.is_some());
+                }
+            })
+        };
+
+        for _ in 0..200 {
+            let screening = ScreeningConfig::new().with_path_pattern(r"\.php$");
+            limiter.apply_config(
+                RateLimitConfig::new(20, Duration::from_secs(60)),
+                Some(RequestScreener::new(&screening).unwrap()),
+            );
+            limiter.apply_config(RateLimitConfig::default(), None);
+        }
+        done.store(true, std::sync::atomic::Ordering::Relaxed);
+        reader.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reload_ending_dry_run_drops_shadow_blocks() {
+        let path = temp_file("end-dry-run", "1\n\\.php$\n");
+        let config = RateLimitConfig::new(1, Duration::from_secs(60))
+            .with_grace_period(0)
+            .with_dry_run(true);
+        let limiter = RateLimiter::new(config, NoOpOnBlocked);
+        let ctx = crate::types::SecurityContext::new("10.0.0.1".to_string(), String::new());
+
+        limiter.check_rate_limit("10.0.0.1", &ctx, "/").await;
+        let (_, newly_blocked, _) = limiter.check_rate_limit("10.0.0.1", &ctx, "/").await;
+        assert!(newly_blocked);
+        assert!(limiter.blocked_until("10.0.0.1").is_some());
+
+        ConfigWatcher::new(&path, load)
+            .reload_now(&limiter)
+            .unwrap();
+
+        assert!(!limiter.is_dry_run());
+        assert!(limiter.screener().is_some());
+        assert!(
+            limiter.blocked_until("10.0.0.1").is_none(),
+            "shadow blocks must not become enforced by the reload"
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_watcher_reloads_on_change() {
+        let path = temp_file("watch", "20\n");
+        let limiter = RateLimiter::new(RateLimitConfig::default(), NoOpOnBlocked);
+
+        let handle = ConfigWatcher::new(&path, load)
+            .with_poll_interval(Duration::from_millis(20))
+            .spawn(limiter.clone());
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(
+            limiter.config().rate_limit_per_minute,
+            20,
+            "the file should be applied at startup"
+        );
+        assert!(limiter.screener().is_none());
+
+        // Ensure the modification time moves even on coarse-grained filesystems
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        std::fs::write(&path, "7\n/\\.git/\n").unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        assert_eq!(limiter.config().rate_limit_per_minute, 7);
+        assert!(limiter.screener().is_some());
+
+        handle.abort();
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -15,21 +15,44 @@
  *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+#[cfg(feature = "serde")]
+use crate::{
+    heavy_hitters::{HeavyHitterKind, Offender},
+    limiter::RateLimiter,
+    rule_stats::RuleStats,
+    types::OnBlocked,
+};
 #[cfg(feature = "metrics")]
+use axum::http::header;
+#[cfg(feature = "serde")]
+use axum::{extract::Query, Json};
 use axum::{
-    http::{header, StatusCode},
-    response::IntoResponse,
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
 };
 
 #[cfg(feature = "metrics")]
 pub async fn metrics_handler() -> impl IntoResponse {
+    encode_metrics(&prometheus::gather())
+}
+
+/// Serve the metrics registered on `registry`, e.g. by `PrometheusMetrics`.
+#[cfg(feature = "metrics")]
+pub async fn registry_metrics_handler(
+    State(registry): State<prometheus::Registry>,
+) -> impl IntoResponse {
+    encode_metrics(&registry.gather())
+}
+
+#[cfg(feature = "metrics")]
+fn encode_metrics(metric_families: &[prometheus::proto::MetricFamily]) -> Response {
     use prometheus::{Encoder, TextEncoder};
 
     let encoder = TextEncoder::new();
-    let metric_families = prometheus::gather();
     let mut buffer = vec![];
 
-    match encoder.encode(&metric_families, &mut buffer) {
+    match encoder.encode(metric_families, &mut buffer) {
         Ok(()) => (
             StatusCode::OK,
             [(
@@ -46,3 +69,61 @@ pub async fn metrics_handler() -> impl IntoResponse {
             .into_response(),
     }
 }
+
+#[cfg(feature = "serde")]
+const DEFAULT_TOP_OFFENDERS: usize = 10;
+
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct TopOffendersQuery {
+    /// Number of keys to report per signal (default 10)
+    pub n: Option<usize>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TopOffenders {
+    pub window_secs: u64,
+    pub requests: Vec<Offender>,
+    pub penalties: Vec<Offender>,
+    pub screening_hits: Vec<Offender>,
+}
+
+/// Serve the heaviest keys tracked by `RateLimiter::with_heavy_hitters` as
+/// JSON, e.g. `GET /debug/top-offenders?n=20`. Responds 404 when tracking
+/// is not enabled.
+#[cfg(feature = "serde")]
+pub async fn top_offenders_handler<B: OnBlocked + 'static>(
+    State(limiter): State<RateLimiter<B>>,
+    Query(query): Query<TopOffendersQuery>,
+) -> Response {
+    let Some(tracker) = limiter.heavy_hitters() else {
+        return (
+            StatusCode::NOT_FOUND,
+            "Heavy-hitter tracking is not enabled",
+        )
+            .into_response();
+    };
+
+    let n = query.n.unwrap_or(DEFAULT_TOP_OFFENDERS);
+    let config = tracker.config();
+    Json(TopOffenders {
+        window_secs: config.window().as_secs(),
+        requests: tracker.top(HeavyHitterKind::Requests, n),
+        penalties: tracker.top(HeavyHitterKind::Penalties, n),
+        screening_hits: tracker.top(HeavyHitterKind::ScreeningHits, n),
+    })
+    .into_response()
+}
+
+/// Serve `RequestScreener::rule_stats` as a JSON array, one entry per rule.
+/// Responds 404 when the limiter has no screener.
+#[cfg(feature = "serde")]
+pub async fn rule_stats_handler<B: OnBlocked + 'static>(
+    State(limiter): State<RateLimiter<B>>,
+) -> Response {
+    match limiter.screener() {
+        Some(screener) => Json::<Vec<RuleStats>>(screener.rule_stats()).into_response(),
+        None => (StatusCode::NOT_FOUND, "Request screening is not enabled").into_response(),
+    }
+}
@@ -0,0 +1,329 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Loading screening rules from files.
+//!
+//! The line-based format has one rule per line: an ID, comma-separated
+//! targets, an action and the pattern, which runs to the end of the line.
+//! Blank lines and lines starting with `#` are ignored.
+//!
+//! ```text
+//! # id          targets            action       pattern
+//! php-file      path               block_ip     \.php\d?$
+//! sqli-union    query,header:referer tarpit:5s  (?i)union.+select
+//! curl          user_agent         log_only     ^curl/
+//! ```
+//!
+//! Targets are `path`, `query`, `user_agent`, `any_header` and
+//! `header:<name>`. Actions are `block_ip`, `reject_only`, `log_only`,
+//! `tarpit:<duration>` and `score:<points>`. Files ending in `.toml` are read
+//! as a `[[rules]]` array instead (requires the `serde` feature).
+
+use crate::rules::{RuleAction, RuleTarget, ScreeningRule};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// A rule file that could not be read, parsed or compiled.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleFileError {
+    pub file: PathBuf,
+    /// 1-based line of the offending rule, if known
+    pub line: Option<usize>,
+    /// ID of the offending rule, if known
+    pub rule_id: Option<String>,
+    pub message: String,
+}
+
+impl RuleFileError {
+    fn new(file: &Path, line: Option<usize>, rule_id: Option<&str>, message: String) -> Self {
+        Self {
+            file: file.to_path_buf(),
+            line,
+            rule_id: rule_id.map(str::to_string),
+            message,
+        }
+    }
+}
+
+impl std::fmt::Display for RuleFileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        if let Some(line) = self.line {
+            write!(f, ":{}", line)?;
+        }
+        if let Some(rule_id) = &self.rule_id {
+            write!(f, ": rule '{}'", rule_id)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for RuleFileError {}
+
+/// Read and check the rules in `path`, in the TOML format if the file name
+/// ends in `.toml` and the line-based format otherwise.
+pub fn load_rules(path: impl AsRef<Path>) -> Result<Vec<ScreeningRule>, RuleFileError> {
+    let path = path.as_ref();
+    let input = std::fs::read_to_string(path)
+        .map_err(|e| RuleFileError::new(path, None, None, e.to_string()))?;
+
+    if path.extension().is_some_and(|ext| ext == "toml") {
+        #[cfg(feature = "serde")]
+        return parse_toml_rules(path, &input);
+        #[cfg(not(feature = "serde"))]
+        return Err(RuleFileError::new(
+            path,
+            None,
+            None,
+            "TOML rule files require the `serde` feature".to_string(),
+        ));
+    }
+    parse_rules(path, &input)
+}
+
+/// Parse and check rules in the line-based format. `file` is only used in
+/// errors.
+pub fn parse_rules(file: &Path, input: &str) -> Result<Vec<ScreeningRule>, RuleFileError> {
+    let mut rules = Vec::new();
+    let mut ids = HashSet::new();
+
+    for (index, line) in input.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let error = |rule_id: Option<&str>, message: String| {
+            RuleFileError::new(file, Some(line_number), rule_id, message)
+        };
+
+        let (id, rest) = next_field(line);
+        let (targets, rest) = next_field(rest);
+        let (action, pattern) = next_field(rest);
+        if targets.is_empty() {
+            return Err(error(Some(id), "missing targets".to_string()));
+        }
+        if action.is_empty() || pattern.is_empty() {
+            return Err(error(Some(id), "missing action or pattern".to_string()));
+        }
+
+        let mut rule = ScreeningRule::new(id, pattern)
+            .with_action(parse_action(action).map_err(|e| error(Some(id), e))?);
+        for target in targets.split(',') {
+            rule = rule.with_target(parse_target(target).map_err(|e| error(Some(id), e))?);
+        }
+
+        rule.check().map_err(|e| error(Some(id), e))?;
+        if !ids.insert(rule.id.clone()) {
+            return Err(error(Some(id), "duplicate rule id".to_string()));
+        }
+        rules.push(rule);
+    }
+
+    Ok(rules)
+}
+
+/// Split off the next whitespace-separated field, returning it and the
+/// trimmed rest of the line.
+fn next_field(input: &str) -> (&str, &str) {
+    let input = input.trim();
+    match input.find(char::is_whitespace) {
+        Some(end) => (&input[..end], input[end..].trim_start()),
+        None => (input, ""),
+    }
+}
+
+fn parse_target(input: &str) -> Result<RuleTarget, String> {
+    match input.trim() {
+        "path" => Ok(RuleTarget::Path),
+        "query" => Ok(RuleTarget::Query),
+        "user_agent" => Ok(RuleTarget::UserAgent),
+        "any_header" => Ok(RuleTarget::AnyHeader),
+        other => match other.strip_prefix("header:") {
+            Some(name) if !name.is_empty() => Ok(RuleTarget::Header(name.to_ascii_lowercase())),
+            _ => Err(format!(
+                "unknown target '{}' (expected path, query, user_agent, any_header or header:<name>)",
+                other
+            )),
+        },
+    }
+}
+
+fn parse_action(input: &str) -> Result<RuleAction, String> {
+    match input.split_once(':') {
+        None => match input {
+            "block_ip" => Ok(RuleAction::BlockIp),
+            "reject_only" => Ok(RuleAction::RejectOnly),
+            "log_only" => Ok(RuleAction::LogOnly),
+            other => Err(format!("unknown action '{}'", other)),
+        },
+        Some(("tarpit", delay)) => crate::duration::parse(delay)
+            .map(|delay| RuleAction::Tarpit { delay }),
+        Some(("score", points)) => points
+            .parse()
+            .map(RuleAction::Score)
+            .map_err(|_| format!("invalid score '{}'", points)),
+        Some(_) => Err(format!(
+            "unknown action '{}' (expected block_ip, reject_only, log_only, tarpit:<duration> or score:<points>)",
+            input
+        )),
+    }
+}
+
+/// Parse and check rules from a TOML document with a `[[rules]]` array.
+/// `file` is only used in errors.
+#[cfg(feature = "serde")]
+pub fn parse_toml_rules(file: &Path, input: &str) -> Result<Vec<ScreeningRule>, RuleFileError> {
+    #[derive(serde::Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct RuleFile {
+        #[serde(default)]
+        rules: Vec<toml::Spanned<ScreeningRule>>,
+    }
+
+    let line_of = |offset: usize| input[..offset.min(input.len())].matches('\n').count() + 1;
+
+    let parsed: RuleFile = toml::from_str(input).map_err(|e| {
+        let line = e.span().map(|span| line_of(span.start));
+        RuleFileError::new(file, line, None, e.message().to_string())
+    })?;
+
+    let mut ids = HashSet::new();
+    let mut rules = Vec::with_capacity(parsed.rules.len());
+    for spanned in parsed.rules {
+        let line = Some(line_of(spanned.span().start));
+        let rule = spanned.into_inner();
+        if rule.id.trim().is_empty() {
+            return Err(RuleFileError::new(
+                file,
+                line,
+                None,
+                "rule id must not be empty".to_string(),
+            ));
+        }
+        rule.check()
+            .map_err(|e| RuleFileError::new(file, line, Some(&rule.id), e))?;
+        if !ids.insert(rule.id.clone()) {
+            return Err(RuleFileError::new(
+                file,
+                line,
+                Some(&rule.id),
+                "duplicate rule id".to_string(),
+            ));
+        }
+        rules.push(rule);
+    }
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_line_based_rules() {
+        let rules = parse_rules(
+            Path::new("site.rules"),
+            "# Site rules\n\
+             \n\
+             php-file     path                  block_ip    \\.php\\d?$\n\
+             sqli-union   query,header:Referer  tarpit:5s   (?i)union .+ select\n\
+             curl         user_agent            score:2     ^curl/\n",
+        )
+        .unwrap();
+
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[0].id, "php-file");
+        assert_eq!(rules[0].targets, vec![RuleTarget::Path]);
+        assert_eq!(rules[0].pattern, r"\.php\d?$");
+        assert_eq!(
+            rules[1].targets,
+            vec![RuleTarget::Query, RuleTarget::Header("referer".to_string())]
+        );
+        assert_eq!(
+            rules[1].action,
+            RuleAction::Tarpit {
+                delay: Duration::from_secs(5)
+            }
+        );
+        assert_eq!(rules[1].pattern, "(?i)union .+ select");
+        assert_eq!(rules[2].action, RuleAction::Score(2));
+    }
+
+    #[test]
+    fn test_line_errors_name_file_line_and_rule() {
+        let err = parse_rules(
+            Path::new("site.rules"),
+            "ok path block_ip ok\n# comment\nbroken path block_ip (unclosed\n",
+        )
+        .unwrap_err();
+        assert_eq!(err.file, PathBuf::from("site.rules"));
+        assert_eq!(err.line, Some(3));
+        assert_eq!(err.rule_id.as_deref(), Some("broken"));
+        assert!(err.to_string().starts_with("site.rules:3: rule 'broken': "));
+
+        let err = parse_rules(Path::new("x"), "a bogus block_ip x").unwrap_err();
+        assert!(err.message.contains("unknown target 'bogus'"));
+        let err = parse_rules(Path::new("x"), "a path explode x").unwrap_err();
+        assert!(err.message.contains("unknown action 'explode'"));
+        let err = parse_rules(Path::new("x"), "a path block_ip").unwrap_err();
+        assert!(err.message.contains("missing action or pattern"));
+        let err = parse_rules(Path::new("x"), "a path block_ip x\na query log_only y").unwrap_err();
+        assert_eq!(err.line, Some(2));
+        assert!(err.message.contains("duplicate"));
+    }
+
+    #[test]
+    fn test_load_rules_reports_missing_file() {
+        let err = load_rules("/nonexistent/site.rules").unwrap_err();
+        assert_eq!(err.line, None);
+        assert!(err.to_string().starts_with("/nonexistent/site.rules: "));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_parse_toml_rules() {
+        let input = r#"
+[[rules]]
+id = "php-file"
+pattern = '\.php$'
+targets = ["path"]
+
+[[rules]]
+id = "broken"
+pattern = "(unclosed"
+targets = ["path"]
+"#;
+        let err = parse_toml_rules(Path::new("site.toml"), input).unwrap_err();
+        assert_eq!(err.line, Some(7));
+        assert_eq!(err.rule_id.as_deref(), Some("broken"));
+
+        let rules = parse_toml_rules(
+            Path::new("site.toml"),
+            &input[..input.find("[[rules]]\nid = \"broken\"").unwrap()],
+        )
+        .unwrap();
+        assert_eq!(rules.len(), 1);
+
+        let err = parse_toml_rules(Path::new("site.toml"), "[[rules]]\nid = \"x\"\nbogus = 1\n")
+            .unwrap_err();
+        assert!(err.line.is_some());
+        assert!(err.message.contains("bogus"));
+    }
+}
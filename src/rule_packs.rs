@@ -0,0 +1,160 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Curated screening rule packs, each behind its own cargo feature.
+//!
+//! Pack rules keep their own actions and get IDs prefixed with the pack
+//! name, e.g. `php-probes/php-file`, so exceptions and alerts can refer to
+//! them. Packs are versioned; a version bump means rules were added, removed
+//! or changed.
+
+use crate::rule_file::parse_rules;
+use crate::rules::ScreeningRule;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RulePack {
+    pub name: &'static str,
+    pub version: &'static str,
+    pub description: &'static str,
+    source: &'static str,
+}
+
+impl RulePack {
+    /// The pack's rules, with IDs prefixed by the pack name.
+    pub fn rules(&self) -> Vec<ScreeningRule> {
+        parse_rules(Path::new(self.name), self.source)
+            .unwrap_or_else(|e| panic!("built-in rule pack is invalid: {}", e))
+            .into_iter()
+            .map(|mut rule| {
+                rule.id = format!("{}/{}", self.name, rule.id);
+                rule
+            })
+            .collect()
+    }
+}
+
+/// Scanner and content discovery user agents, router and IoT exploit probes.
+#[cfg(feature = "rules-common-scanners")]
+pub const COMMON_SCANNERS: RulePack = RulePack {
+    name: "common-scanners",
+    version: "1.0.0",
+    description: "Scanner user agents and router/IoT exploit probes",
+    source: include_str!("../rules/common-scanners.rules"),
+};
+
+/// PHP files, phpMyAdmin, PHPUnit and PHP wrapper probes.
+#[cfg(feature = "rules-php-probes")]
+pub const PHP_PROBES: RulePack = RulePack {
+    name: "php-probes",
+    version: "1.0.0",
+    description: "PHP application and tooling probes",
+    source: include_str!("../rules/php-probes.rules"),
+};
+
+/// Environment files, credentials, VCS metadata and backups.
+#[cfg(feature = "rules-secrets-exposure")]
+pub const SECRETS_EXPOSURE: RulePack = RulePack {
+    name: "secrets-exposure",
+    version: "1.0.0",
+    description: "Credential, environment file, VCS metadata and backup requests",
+    source: include_str!("../rules/secrets-exposure.rules"),
+};
+
+/// WordPress, Joomla, Drupal and Magento probes.
+#[cfg(feature = "rules-cms-probes")]
+pub const CMS_PROBES: RulePack = RulePack {
+    name: "cms-probes",
+    version: "1.0.0",
+    description: "WordPress, Joomla, Drupal and Magento probes",
+    source: include_str!("../rules/cms-probes.rules"),
+};
+
+/// Every pack enabled by cargo features.
+#[allow(clippy::vec_init_then_push)]
+pub fn enabled() -> Vec<RulePack> {
+    #[allow(unused_mut)]
+    let mut packs = Vec::new();
+    #[cfg(feature = "rules-common-scanners")]
+    packs.push(COMMON_SCANNERS);
+    #[cfg(feature = "rules-php-probes")]
+    packs.push(PHP_PROBES);
+    #[cfg(feature = "rules-secrets-exposure")]
+    packs.push(SECRETS_EXPOSURE);
+    #[cfg(feature = "rules-cms-probes")]
+    packs.push(CMS_PROBES);
+    packs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::screener::{RequestScreener, ScreeningConfig};
+
+    #[test]
+    fn test_enabled_packs_are_valid() {
+        let mut config = ScreeningConfig::new();
+        for pack in enabled() {
+            let header = pack.source.lines().next().unwrap_or_default();
+            assert_eq!(
+                header,
+                format!("# {} {}", pack.name, pack.version),
+                "pack file header out of sync with {}",
+                pack.name
+            );
+            let rules = pack.rules();
+            assert!(!rules.is_empty(), "{} has no rules", pack.name);
+            assert!(rules
+                .iter()
+                .all(|rule| rule.id.starts_with(&format!("{}/", pack.name))));
+            config = config.with_rule_pack(&pack);
+        }
+        config.validate().unwrap();
+        RequestScreener::new(&config).unwrap();
+    }
+
+    #[cfg(all(feature = "rules-php-probes", feature = "rules-secrets-exposure"))]
+    #[test]
+    fn test_pack_rules_match_probes() {
+        let screener = RequestScreener::new(
+            &ScreeningConfig::new()
+                .with_rule_pack(&PHP_PROBES)
+                .with_rule_pack(&SECRETS_EXPOSURE),
+        )
+        .unwrap();
+
+        let rule = |path: &str| screener.check(path, "Mozilla/5.0").map(|r| r.rule_id);
+        assert_eq!(
+            rule("/wp-login.php").as_deref(),
+            Some("php-probes/php-file")
+        );
+        assert_eq!(
+            rule("/%2egit/config").as_deref(),
+            Some("secrets-exposure/git-metadata")
+        );
+        assert_eq!(
+            rule("/.env.production").as_deref(),
+            Some("secrets-exposure/dotenv")
+        );
+        assert_eq!(
+            rule("/dump.sql").as_deref(),
+            Some("secrets-exposure/backup-archive")
+        );
+        assert_eq!(rule("/environment/settings"), None);
+        assert_eq!(rule("/blog/hello-world"), None);
+    }
+}
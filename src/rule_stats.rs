@@ -0,0 +1,157 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Per-rule screening hit counters.
+
+use crate::rules::ScreeningRule;
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use std::collections::HashSet;
+use std::hash::{BuildHasher, RandomState};
+
+/// Distinct keys remembered per rule; `unique_keys` stops growing here.
+pub const MAX_TRACKED_KEYS: usize = 10_000;
+
+/// How often a screening rule has matched since the screener was created or
+/// its stats were reset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct RuleStats {
+    pub rule_id: String,
+    pub hits: u64,
+    /// Distinct keys among the hits, up to `MAX_TRACKED_KEYS`
+    pub unique_keys: usize,
+    pub last_hit: Option<DateTime<Utc>>,
+}
+
+#[derive(Default)]
+struct RuleHits {
+    hits: u64,
+    /// Hashes of the keys seen, bounded by `MAX_TRACKED_KEYS`
+    keys: HashSet<u64>,
+    last_hit: Option<DateTime<Utc>>,
+}
+
+/// Hit counters by rule ID. Matches suppressed by an exception are not hits.
+#[derive(Default)]
+pub(crate) struct RuleHitCounters {
+    rules: DashMap<String, RuleHits>,
+    hasher: RandomState,
+}
+
+impl RuleHitCounters {
+    pub(crate) fn record<'a>(
+        &self,
+        rule_ids: impl IntoIterator<Item = &'a str>,
+        key: Option<&str>,
+    ) {
+        let now = Utc::now();
+        let key = key.map(|key| self.hasher.hash_one(key));
+        for rule_id in rule_ids {
+            let mut entry = match self.rules.get_mut(rule_id) {
+                Some(entry) => entry,
+                None => self.rules.entry(rule_id.to_string()).or_default(),
+            };
+            entry.hits += 1;
+            entry.last_hit = Some(now);
+            if let Some(key) = key {
+                if entry.keys.len() < MAX_TRACKED_KEYS {
+                    entry.keys.insert(key);
+                }
+            }
+        }
+    }
+
+    /// Stats for every rule in `rules`, in order, including rules that never
+    /// matched.
+    pub(crate) fn snapshot(&self, rules: &[ScreeningRule]) -> Vec<RuleStats> {
+        rules.iter().map(|rule| self.get(&rule.id)).collect()
+    }
+
+    pub(crate) fn get(&self, rule_id: &str) -> RuleStats {
+        match self.rules.get(rule_id) {
+            Some(entry) => RuleStats {
+                rule_id: rule_id.to_string(),
+                hits: entry.hits,
+                unique_keys: entry.keys.len(),
+                last_hit: entry.last_hit,
+            },
+            None => RuleStats {
+                rule_id: rule_id.to_string(),
+                hits: 0,
+                unique_keys: 0,
+                last_hit: None,
+            },
+        }
+    }
+
+    /// Drop counters for rules no longer in `rules`.
+    pub(crate) fn retain(&self, rules: &[ScreeningRule]) {
+        self.rules
+            .retain(|rule_id, _| rules.iter().any(|rule| rule.id == *rule_id));
+    }
+
+    pub(crate) fn remove(&self, rule_id: &str) {
+        self.rules.remove(rule_id);
+    }
+
+    pub(crate) fn reset(&self) {
+        self.rules.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::RuleTarget;
+
+    #[test]
+    fn test_counts_hits_and_unique_keys() {
+        let counters = RuleHitCounters::default();
+        counters.record(["a", "b"], Some("10.0.0.1"));
+        counters.record(["a"], Some("10.0.0.1"));
+        counters.record(["a"], Some("10.0.0.2"));
+        counters.record(["a"], None);
+
+        let rules: Vec<ScreeningRule> = ["a", "b", "c"]
+            .into_iter()
+            .map(|id| ScreeningRule::new(id, "x").with_target(RuleTarget::Path))
+            .collect();
+        let stats = counters.snapshot(&rules);
+        assert_eq!((stats[0].hits, stats[0].unique_keys), (4, 2));
+        assert_eq!((stats[1].hits, stats[1].unique_keys), (1, 1));
+        assert!(stats[0].last_hit.is_some());
+        assert_eq!(stats[2], counters.get("c"));
+        assert_eq!((stats[2].hits, stats[2].last_hit), (0, None));
+
+        counters.retain(&rules[1..]);
+        assert_eq!(counters.get("a").hits, 0);
+        counters.reset();
+        assert_eq!(counters.get("b").hits, 0);
+    }
+
+    #[test]
+    fn test_unique_keys_are_bounded() {
+        let counters = RuleHitCounters::default();
+        for i in 0..MAX_TRACKED_KEYS + 10 {
+            counters.record(["a"], Some(&i.to_string()));
+        }
+        let stats = counters.get("a");
+        assert_eq!(stats.hits, (MAX_TRACKED_KEYS + 10) as u64);
+        assert_eq!(stats.unique_keys, MAX_TRACKED_KEYS);
+    }
+}
@@ -0,0 +1,357 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Named screening rules with stable IDs and per-rule actions.
+
+use crate::config::ConfigError;
+use axum::http::HeaderName;
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+
+/// The part of a request a `ScreeningRule` pattern is matched against.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum RuleTarget {
+    /// The normalized request path
+    Path,
+    /// The raw query string
+    Query,
+    /// The user agent (always case-insensitive)
+    UserAgent,
+    /// The values of one header, by name
+    Header(String),
+    /// The value of every header
+    AnyHeader,
+}
+
+impl std::fmt::Display for RuleTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleTarget::Path => write!(f, "path"),
+            RuleTarget::Query => write!(f, "query"),
+            RuleTarget::UserAgent => write!(f, "user agent"),
+            RuleTarget::Header(name) => write!(f, "{} header", name),
+            RuleTarget::AnyHeader => write!(f, "any header"),
+        }
+    }
+}
+
+/// What happens to a request that matches a rule. When several rules match,
+/// the most severe action wins, in the order listed here.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum RuleAction {
+    /// Block the key for the configured block duration and respond 418
+    #[default]
+    BlockIp,
+    /// Hold the response for `delay`, then respond 418 without blocking
+    Tarpit {
+        #[cfg_attr(feature = "serde", serde(with = "crate::duration::serde_format"))]
+        delay: Duration,
+    },
+    /// Respond 418 without blocking the key
+    RejectOnly,
+    /// Log the match and let the request through
+    LogOnly,
+    /// Add points to the anomaly score instead of acting on its own; see
+    /// `ScoringConfig`
+    Score(u32),
+}
+
+impl RuleAction {
+    pub(crate) fn severity(&self) -> u8 {
+        match self {
+            RuleAction::BlockIp => 3,
+            RuleAction::Tarpit { .. } => 2,
+            RuleAction::RejectOnly => 1,
+            RuleAction::LogOnly | RuleAction::Score(_) => 0,
+        }
+    }
+
+    /// Whether the request is rejected.
+    pub fn rejects(&self) -> bool {
+        !matches!(self, RuleAction::LogOnly | RuleAction::Score(_))
+    }
+}
+
+impl std::fmt::Display for RuleAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleAction::BlockIp => write!(f, "block_ip"),
+            RuleAction::Tarpit { .. } => write!(f, "tarpit"),
+            RuleAction::RejectOnly => write!(f, "reject_only"),
+            RuleAction::LogOnly => write!(f, "log_only"),
+            RuleAction::Score(points) => write!(f, "score({})", points),
+        }
+    }
+}
+
+/// Rule ID reported when the anomaly score crosses `ScoringConfig::threshold`.
+pub const ANOMALY_SCORE_RULE_ID: &str = "anomaly_score";
+
+/// Anomaly scoring: rules with a `RuleAction::Score` action add their points
+/// to a score, and `action` applies once the score reaches `threshold`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct ScoringConfig {
+    pub threshold: u32,
+    /// Accumulate each key's score over this window instead of per request.
+    /// Zero scores every request on its own
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, with = "crate::duration::serde_format")
+    )]
+    pub window: Duration,
+    /// Applied when the threshold is reached
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub action: RuleAction,
+}
+
+impl ScoringConfig {
+    pub fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            window: Duration::ZERO,
+            action: RuleAction::default(),
+        }
+    }
+
+    pub fn with_window(mut self, window: Duration) -> Self {
+        self.window = window;
+        self
+    }
+
+    pub fn with_action(mut self, action: RuleAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), ConfigError> {
+        if self.threshold == 0 {
+            return Err(ConfigError::invalid(
+                "scoring.threshold",
+                "must be greater than 0",
+            ));
+        }
+        if let RuleAction::Score(_) = self.action {
+            return Err(ConfigError::invalid(
+                "scoring.action",
+                "must not be a score action",
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A screening pattern with a stable ID, matched against one or more targets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct ScreeningRule {
+    /// Stable identifier reported in results, events and logs
+    pub id: String,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub description: String,
+    /// Regex pattern
+    pub pattern: String,
+    /// Where the pattern is matched; the rule fires if any target matches
+    pub targets: Vec<RuleTarget>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub action: RuleAction,
+}
+
+impl ScreeningRule {
+    /// A rule blocking the key when `pattern` matches. Add at least one
+    /// target with `with_target`.
+    pub fn new(id: &str, pattern: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            description: String::new(),
+            pattern: pattern.to_string(),
+            targets: Vec::new(),
+            action: RuleAction::default(),
+        }
+    }
+
+    pub fn with_description(mut self, description: &str) -> Self {
+        self.description = description.to_string();
+        self
+    }
+
+    pub fn with_target(mut self, target: RuleTarget) -> Self {
+        self.targets.push(target);
+        self
+    }
+
+    pub fn with_action(mut self, action: RuleAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Check the ID, targets and pattern. `field` names the rule in errors.
+    pub(crate) fn validate(&self, field: &str) -> Result<(), ConfigError> {
+        if self.id.trim().is_empty() {
+            return Err(ConfigError::invalid(field, "rule id must not be empty"));
+        }
+        self.check().map_err(|message| {
+            ConfigError::invalid(field, format!("rule '{}': {}", self.id, message))
+        })
+    }
+
+    /// Check the targets and pattern, without naming the rule.
+    pub(crate) fn check(&self) -> Result<(), String> {
+        if self.targets.is_empty() {
+            return Err("rule has no targets".to_string());
+        }
+        for target in &self.targets {
+            if let RuleTarget::Header(name) = target {
+                if let Err(e) = HeaderName::from_bytes(name.as_bytes()) {
+                    return Err(format!("invalid header name '{}': {}", name, e));
+                }
+            }
+        }
+        regex::Regex::new(&self.pattern)
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+}
+
+/// Suppresses rules for requests matching a path pattern, a user agent
+/// pattern, or both (a pair, which must match together).
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(deny_unknown_fields)
+)]
+pub struct ScreeningException {
+    /// Identifier shown in debug logs
+    pub id: String,
+    /// Regex matched against the normalized path
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub path: Option<String>,
+    /// Regex matched against the user agent (case-insensitive)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub user_agent: Option<String>,
+    /// IDs of the rules suppressed; empty suppresses every rule
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub rules: Vec<String>,
+}
+
+impl ScreeningException {
+    pub fn new(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            path: None,
+            user_agent: None,
+            rules: Vec::new(),
+        }
+    }
+
+    pub fn with_path(mut self, pattern: &str) -> Self {
+        self.path = Some(pattern.to_string());
+        self
+    }
+
+    pub fn with_user_agent(mut self, pattern: &str) -> Self {
+        self.user_agent = Some(pattern.to_string());
+        self
+    }
+
+    /// Only suppress the rule with this ID. Call once per rule.
+    pub fn for_rule(mut self, rule_id: &str) -> Self {
+        self.rules.push(rule_id.to_string());
+        self
+    }
+
+    pub(crate) fn validate(&self, field: &str) -> Result<(), ConfigError> {
+        if self.id.trim().is_empty() {
+            return Err(ConfigError::invalid(
+                field,
+                "exception id must not be empty",
+            ));
+        }
+        if self.path.is_none() && self.user_agent.is_none() {
+            return Err(ConfigError::invalid(
+                field,
+                format!("exception '{}' needs a path or user_agent pattern", self.id),
+            ));
+        }
+        for pattern in self.path.iter().chain(&self.user_agent) {
+            if let Err(e) = regex::Regex::new(pattern) {
+                return Err(ConfigError::invalid(
+                    field,
+                    format!("exception '{}': {}", self.id, e),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A runtime change to a screener's rules, passed to the audit callback set
+/// with `RequestScreener::with_rule_audit`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuleChange {
+    /// Added with `RequestScreener::add_rule`, expiring at `expires_at` if
+    /// it was given a TTL
+    Added {
+        rule: ScreeningRule,
+        expires_at: Option<DateTime<Utc>>,
+    },
+    /// Removed with `RequestScreener::remove_rule`
+    Removed { rule: ScreeningRule },
+    /// Removed because its TTL passed
+    Expired { rule: ScreeningRule },
+}
+
+impl RuleChange {
+    pub fn rule(&self) -> &ScreeningRule {
+        match self {
+            Self::Added { rule, .. } | Self::Removed { rule } | Self::Expired { rule } => rule,
+        }
+    }
+}
+
+impl std::fmt::Display for RuleChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Added {
+                rule,
+                expires_at: Some(expires_at),
+            } => write!(f, "added rule {} until {}", rule.id, expires_at),
+            Self::Added { rule, .. } => write!(f, "added rule {}", rule.id),
+            Self::Removed { rule } => write!(f, "removed rule {}", rule.id),
+            Self::Expired { rule } => write!(f, "expired rule {}", rule.id),
+        }
+    }
+}
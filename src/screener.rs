@@ -15,14 +15,55 @@
  *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
  */
 
+use crate::config::{parse_env, parse_env_bool, parse_env_number, ConfigError};
+use crate::normalize::PathNormalization;
+use crate::protocol::{ProtocolCheck, ProtocolChecks};
+use crate::rule_packs::RulePack;
+use crate::rule_stats::{RuleHitCounters, RuleStats};
+use crate::rules::{
+    RuleAction, RuleChange, RuleTarget, ScoringConfig, ScreeningException, ScreeningRule,
+    ANOMALY_SCORE_RULE_ID,
+};
+use axum::http::{header::HOST, HeaderMap, HeaderName, Request};
+use chrono::Utc;
+use dashmap::DashMap;
 use regex::RegexSet;
+use std::collections::{BTreeMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
 
 #[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
 pub struct ScreeningConfig {
     /// Regex patterns that match malicious paths
     pub path_patterns: Vec<String>,
     /// Regex patterns that match malicious user agents (case-insensitive)
     pub user_agent_patterns: Vec<String>,
+    /// Regex patterns matched against the raw query string
+    pub query_patterns: Vec<String>,
+    /// Regex patterns matched against the values of specific headers, keyed
+    /// by header name (case-insensitive)
+    pub header_patterns: BTreeMap<String, Vec<String>>,
+    /// Regex patterns matched against the value of every header
+    pub any_header_patterns: Vec<String>,
+    /// Named rules with their own targets and actions, checked alongside the
+    /// pattern lists above
+    pub rules: Vec<ScreeningRule>,
+    /// Anomaly scoring for rules with a `RuleAction::Score` action
+    pub scoring: Option<ScoringConfig>,
+    /// Requests for which some or all rules are suppressed
+    pub exceptions: Vec<ScreeningException>,
+    /// How paths are normalized before path patterns are matched
+    pub path_normalization: PathNormalization,
+    /// Structural checks on the method, `Host` and headers
+    pub protocol: ProtocolChecks,
+    /// Log and meter matches as "would have screened" without blocking
+    pub dry_run: bool,
 }
 
 impl ScreeningConfig {
@@ -30,9 +71,30 @@ impl ScreeningConfig {
         Self {
             path_patterns: Vec::new(),
             user_agent_patterns: Vec::new(),
+            query_patterns: Vec::new(),
+            header_patterns: BTreeMap::new(),
+            any_header_patterns: Vec::new(),
+            rules: Vec::new(),
+            scoring: None,
+            exceptions: Vec::new(),
+            path_normalization: PathNormalization::default(),
+            protocol: ProtocolChecks::default(),
+            dry_run: false,
         }
     }
 
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Replace the default path normalization; `PathNormalization::none()`
+    /// matches the raw path.
+    pub fn with_path_normalization(mut self, normalization: PathNormalization) -> Self {
+        self.path_normalization = normalization;
+        self
+    }
+
     pub fn with_path_pattern(mut self, pattern: &str) -> Self {
         self.path_patterns.push(pattern.to_string());
         self
@@ -52,17 +114,446 @@ impl ScreeningConfig {
         self.user_agent_patterns.extend(patterns);
         self
     }
+
+    pub fn with_query_pattern(mut self, pattern: &str) -> Self {
+        self.query_patterns.push(pattern.to_string());
+        self
+    }
+
+    pub fn with_query_patterns(mut self, patterns: Vec<String>) -> Self {
+        self.query_patterns.extend(patterns);
+        self
+    }
+
+    /// Match `pattern` against the value of the `header` header, e.g.
+    /// `Referer` or `X-Api-Version`.
+    pub fn with_header_pattern(mut self, header: &str, pattern: &str) -> Self {
+        self.header_patterns
+            .entry(header.to_ascii_lowercase())
+            .or_default()
+            .push(pattern.to_string());
+        self
+    }
+
+    pub fn with_any_header_pattern(mut self, pattern: &str) -> Self {
+        self.any_header_patterns.push(pattern.to_string());
+        self
+    }
+
+    pub fn with_any_header_patterns(mut self, patterns: Vec<String>) -> Self {
+        self.any_header_patterns.extend(patterns);
+        self
+    }
+
+    pub fn with_rule(mut self, rule: ScreeningRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    pub fn with_rules(mut self, rules: Vec<ScreeningRule>) -> Self {
+        self.rules.extend(rules);
+        self
+    }
+
+    /// Add a built-in rule pack, e.g. `rule_packs::PHP_PROBES`.
+    pub fn with_rule_pack(self, pack: &RulePack) -> Self {
+        self.with_rules(pack.rules())
+    }
+
+    pub fn with_scoring(mut self, scoring: ScoringConfig) -> Self {
+        self.scoring = Some(scoring);
+        self
+    }
+
+    pub fn with_exception(mut self, exception: ScreeningException) -> Self {
+        self.exceptions.push(exception);
+        self
+    }
+
+    pub fn with_protocol_checks(mut self, checks: ProtocolChecks) -> Self {
+        self.protocol = checks;
+        self
+    }
+
+    /// Every rule the screener checks, in order: the pattern lists as
+    /// `BlockIp` rules identified by field and index (e.g. `path_patterns[2]`
+    /// or `header_patterns[referer][0]`), the enabled protocol checks (e.g.
+    /// `protocol.method`), then `rules`.
+    pub fn all_rules(&self) -> Vec<ScreeningRule> {
+        let mut all = Vec::new();
+        let mut push = |field: String, patterns: &[String], target: RuleTarget| {
+            for (i, pattern) in patterns.iter().enumerate() {
+                all.push(
+                    ScreeningRule::new(&format!("{}[{}]", field, i), pattern)
+                        .with_target(target.clone()),
+                );
+            }
+        };
+        push(
+            "path_patterns".to_string(),
+            &self.path_patterns,
+            RuleTarget::Path,
+        );
+        push(
+            "query_patterns".to_string(),
+            &self.query_patterns,
+            RuleTarget::Query,
+        );
+        push(
+            "user_agent_patterns".to_string(),
+            &self.user_agent_patterns,
+            RuleTarget::UserAgent,
+        );
+        for (header, patterns) in &self.header_patterns {
+            push(
+                format!("header_patterns[{}]", header),
+                patterns,
+                RuleTarget::Header(header.clone()),
+            );
+        }
+        push(
+            "any_header_patterns".to_string(),
+            &self.any_header_patterns,
+            RuleTarget::AnyHeader,
+        );
+        all.extend(self.protocol.rules());
+        all.extend(self.rules.iter().cloned());
+        all
+    }
+
+    /// Check that every pattern compiles, every header name is valid and
+    /// rule IDs are unique, reporting the first failure by field and index
+    /// (e.g. `path_patterns[2]`, `header_patterns[referer][0]` or `rules[1]`).
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut fields = vec![
+            ("path_patterns".to_string(), &self.path_patterns),
+            ("user_agent_patterns".to_string(), &self.user_agent_patterns),
+            ("query_patterns".to_string(), &self.query_patterns),
+            ("any_header_patterns".to_string(), &self.any_header_patterns),
+        ];
+        for (header, patterns) in &self.header_patterns {
+            if let Err(e) = HeaderName::from_bytes(header.as_bytes()) {
+                return Err(ConfigError::invalid(
+                    format!("header_patterns[{}]", header),
+                    e.to_string(),
+                ));
+            }
+            fields.push((format!("header_patterns[{}]", header), patterns));
+        }
+
+        for (field, patterns) in fields {
+            for (i, pattern) in patterns.iter().enumerate() {
+                if let Err(e) = regex::Regex::new(pattern) {
+                    return Err(ConfigError::invalid(
+                        format!("{}[{}]", field, i),
+                        e.to_string(),
+                    ));
+                }
+            }
+        }
+
+        for (i, rule) in self.rules.iter().enumerate() {
+            rule.validate(&format!("rules[{}]", i))?;
+            if matches!(rule.action, RuleAction::Score(_)) && self.scoring.is_none() {
+                return Err(ConfigError::invalid(
+                    format!("rules[{}]", i),
+                    format!(
+                        "rule '{}' has a score action but scoring is not configured",
+                        rule.id
+                    ),
+                ));
+            }
+        }
+        if let Some(scoring) = &self.scoring {
+            scoring.validate()?;
+        }
+        self.protocol.validate()?;
+        if matches!(self.protocol.action, RuleAction::Score(_))
+            && self.protocol.is_enabled()
+            && self.scoring.is_none()
+        {
+            return Err(ConfigError::invalid(
+                "protocol.action",
+                "score action but scoring is not configured",
+            ));
+        }
+
+        let mut ids = HashSet::new();
+        for rule in self.all_rules() {
+            if !ids.insert(rule.id.clone()) {
+                return Err(ConfigError::invalid(
+                    "rules",
+                    format!("duplicate rule id '{}'", rule.id),
+                ));
+            }
+        }
+
+        let mut exception_ids = HashSet::new();
+        for (i, exception) in self.exceptions.iter().enumerate() {
+            let field = format!("exceptions[{}]", i);
+            exception.validate(&field)?;
+            if !exception_ids.insert(&exception.id) {
+                return Err(ConfigError::invalid(
+                    field,
+                    format!("duplicate exception id '{}'", exception.id),
+                ));
+            }
+            if let Some(unknown) = exception.rules.iter().find(|id| !ids.contains(*id)) {
+                return Err(ConfigError::invalid(
+                    field,
+                    format!(
+                        "exception '{}' refers to unknown rule '{}'",
+                        exception.id, unknown
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Parse and validate a TOML document containing the config fields at the
+    /// top level.
+    #[cfg(feature = "serde")]
+    pub fn from_toml_str(input: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(input)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Load from `{prefix}PATH_PATTERNS`, `{prefix}USER_AGENT_PATTERNS`,
+    /// `{prefix}QUERY_PATTERNS`, `{prefix}HEADER_PATTERNS`,
+    /// `{prefix}ANY_HEADER_PATTERNS` and `{prefix}DRY_RUN`. Pattern variables
+    /// hold one regex per line, since patterns may themselves contain commas.
+    /// `HEADER_PATTERNS` lines have the form `Header-Name: regex`.
+    /// `{prefix}NORMALIZE_PATHS=false` disables path normalization and
+    /// `{prefix}CASE_FOLD_PATHS=true` lowercases paths before matching.
+    ///
+    /// Protocol checks come from `{prefix}REQUIRED_HEADERS`,
+    /// `{prefix}ALLOWED_HOSTS` and `{prefix}ALLOWED_METHODS`, each a
+    /// comma-separated list, `{prefix}REJECT_IP_HOST` (a boolean), and
+    /// `{prefix}MAX_HEADERS` and `{prefix}MAX_HEADER_BYTES` (numbers). Their
+    /// action stays the `ProtocolChecks` default, `RejectOnly`.
+    pub fn from_env(prefix: &str) -> Result<Self, ConfigError> {
+        Self::from_env_with(prefix, |name| std::env::var(name).ok())
+    }
+
+    pub(crate) fn from_env_with(
+        prefix: &str,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let lines = |value: &str| -> Result<Vec<String>, String> {
+            Ok(value
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect())
+        };
+        let var = |name: &str| format!("{}{}", prefix, name);
+        let mut config = Self::new();
+
+        if let Some(v) = parse_env(&lookup, &var("PATH_PATTERNS"), lines)? {
+            config.path_patterns = v;
+        }
+        if let Some(v) = parse_env(&lookup, &var("USER_AGENT_PATTERNS"), lines)? {
+            config.user_agent_patterns = v;
+        }
+        if let Some(v) = parse_env(&lookup, &var("QUERY_PATTERNS"), lines)? {
+            config.query_patterns = v;
+        }
+        if let Some(v) = parse_env(&lookup, &var("HEADER_PATTERNS"), |value: &str| {
+            let mut headers: BTreeMap<String, Vec<String>> = BTreeMap::new();
+            for line in lines(value)? {
+                let (header, pattern) = line
+                    .split_once(':')
+                    .ok_or_else(|| format!("expected `Header-Name: regex`, got `{}`", line))?;
+                headers
+                    .entry(header.trim().to_ascii_lowercase())
+                    .or_default()
+                    .push(pattern.trim().to_string());
+            }
+            Ok(headers)
+        })? {
+            config.header_patterns = v;
+        }
+        if let Some(v) = parse_env(&lookup, &var("ANY_HEADER_PATTERNS"), lines)? {
+            config.any_header_patterns = v;
+        }
+        if let Some(false) = parse_env(&lookup, &var("NORMALIZE_PATHS"), parse_env_bool)? {
+            config.path_normalization = PathNormalization::none();
+        }
+        if let Some(v) = parse_env(&lookup, &var("CASE_FOLD_PATHS"), parse_env_bool)? {
+            config.path_normalization.case_fold = v;
+        }
+        let list = |value: &str| -> Result<Vec<String>, String> {
+            Ok(value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(str::to_string)
+                .collect())
+        };
+        if let Some(v) = parse_env(&lookup, &var("REQUIRED_HEADERS"), list)? {
+            config.protocol.required_headers = v;
+        }
+        if let Some(v) = parse_env(&lookup, &var("ALLOWED_HOSTS"), list)? {
+            config.protocol.allowed_hosts = v;
+        }
+        if let Some(v) = parse_env(&lookup, &var("REJECT_IP_HOST"), parse_env_bool)? {
+            config.protocol.reject_ip_host = v;
+        }
+        if let Some(v) = parse_env(&lookup, &var("ALLOWED_METHODS"), list)? {
+            config.protocol.allowed_methods = v;
+        }
+        if let Some(v) = parse_env(&lookup, &var("MAX_HEADERS"), parse_env_number)? {
+            config.protocol.max_headers = Some(v);
+        }
+        if let Some(v) = parse_env(&lookup, &var("MAX_HEADER_BYTES"), parse_env_number)? {
+            config.protocol.max_header_bytes = Some(v);
+        }
+        if let Some(v) = parse_env(&lookup, &var("DRY_RUN"), parse_env_bool)? {
+            config.dry_run = v;
+        }
+
+        // Report pattern errors against the variable, keeping the line index
+        config.validate().map_err(|e| match e {
+            ConfigError::InvalidField { field, message } => {
+                let (name, index) = field.split_once('[').unwrap_or((&field, ""));
+                let name = name.strip_prefix("protocol.").unwrap_or(name);
+                ConfigError::invalid(
+                    format!("{}[{}", var(&name.to_ascii_uppercase()), index),
+                    message,
+                )
+            }
+            other => other,
+        })?;
+        Ok(config)
+    }
+}
+
+/// The parts of a request a `RequestScreener` inspects.
+#[derive(Debug, Clone, Copy)]
+pub struct ScreeningRequest<'a> {
+    pub path: &'a str,
+    pub query: Option<&'a str>,
+    pub user_agent: &'a str,
+    pub headers: Option<&'a HeaderMap>,
+    pub method: Option<&'a str>,
+    /// `Host` header, or the URI authority for HTTP/2
+    pub host: Option<&'a str>,
+    /// Rate limit key, used to accumulate anomaly scores over a window
+    pub key: Option<&'a str>,
+}
+
+impl<'a> ScreeningRequest<'a> {
+    pub fn new(path: &'a str, user_agent: &'a str) -> Self {
+        Self {
+            path,
+            query: None,
+            user_agent,
+            headers: None,
+            method: None,
+            host: None,
+            key: None,
+        }
+    }
+
+    /// Take the path, query string, headers, method and host from `request`.
+    pub fn from_request<B>(request: &'a Request<B>, user_agent: &'a str) -> Self {
+        let host = request
+            .headers()
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .or_else(|| {
+                request
+                    .uri()
+                    .authority()
+                    .map(|authority| authority.as_str())
+            });
+        Self {
+            path: request.uri().path(),
+            query: request.uri().query(),
+            user_agent,
+            headers: Some(request.headers()),
+            method: Some(request.method().as_str()),
+            host,
+            key: None,
+        }
+    }
+
+    pub fn with_query(mut self, query: &'a str) -> Self {
+        self.query = Some(query);
+        self
+    }
+
+    pub fn with_headers(mut self, headers: &'a HeaderMap) -> Self {
+        self.headers = Some(headers);
+        self
+    }
+
+    pub fn with_method(mut self, method: &'a str) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    pub fn with_host(mut self, host: &'a str) -> Self {
+        self.host = Some(host);
+        self
+    }
+
+    pub fn with_key(mut self, key: &'a str) -> Self {
+        self.key = Some(key);
+        self
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct ScreeningResult {
+    /// Why the rule whose action applies matched
     pub reason: ScreeningReason,
+    /// ID of the rule whose action applies
+    pub rule_id: String,
+    /// The most severe action among the matched rules
+    pub action: RuleAction,
+    /// IDs of every matched rule, in configuration order
+    pub matched_rules: Vec<String>,
+    /// Anomaly score after this request; 0 when no scoring rule matched
+    pub score: u32,
 }
 
 #[derive(Debug, Clone)]
 pub enum ScreeningReason {
     MaliciousPath(String),
     MaliciousUserAgent(String),
+    MaliciousQuery(String),
+    MaliciousHeader {
+        header: String,
+        pattern: String,
+    },
+    /// Scoring rules added up to the threshold
+    AnomalyScore {
+        score: u32,
+        threshold: u32,
+    },
+    /// A protocol check failed; `detail` describes the offending value
+    ProtocolViolation {
+        check: ProtocolCheck,
+        detail: String,
+    },
+}
+
+impl ScreeningReason {
+    /// The configured pattern that matched.
+    pub fn pattern(&self) -> &str {
+        match self {
+            ScreeningReason::MaliciousPath(pattern) => pattern,
+            ScreeningReason::MaliciousUserAgent(pattern) => pattern,
+            ScreeningReason::MaliciousQuery(pattern) => pattern,
+            ScreeningReason::MaliciousHeader { pattern, .. } => pattern,
+            ScreeningReason::AnomalyScore { .. } => ANOMALY_SCORE_RULE_ID,
+            ScreeningReason::ProtocolViolation { check, .. } => check.rule_id(),
+        }
+    }
 }
 
 impl std::fmt::Display for ScreeningReason {
@@ -74,56 +565,711 @@ impl std::fmt::Display for ScreeningReason {
             ScreeningReason::MaliciousUserAgent(pattern) => {
                 write!(f, "malicious user agent: {}", pattern)
             }
+            ScreeningReason::MaliciousQuery(pattern) => {
+                write!(f, "malicious query string: {}", pattern)
+            }
+            ScreeningReason::MaliciousHeader { header, pattern } => {
+                write!(f, "malicious {} header: {}", header, pattern)
+            }
+            ScreeningReason::AnomalyScore { score, threshold } => {
+                write!(f, "anomaly score {} reached threshold {}", score, threshold)
+            }
+            // The detail is left out as it may hold arbitrary client input
+            ScreeningReason::ProtocolViolation { check, .. } => {
+                write!(f, "protocol violation: {}", check)
+            }
         }
     }
 }
 
+/// Patterns for one target, compiled into a single `RegexSet`.
 #[derive(Clone)]
-pub struct RequestScreener {
-    path_regex_set: RegexSet,
-    path_patterns: Vec<String>,
-    user_agent_regex_set: RegexSet,
-    user_agent_patterns: Vec<String>,
+struct TargetSet {
+    regex_set: RegexSet,
+    /// Rule (or exception) index for each pattern in the set
+    owners: Vec<usize>,
 }
 
-impl RequestScreener {
-    pub fn new(config: &ScreeningConfig) -> Result<Self, regex::Error> {
-        let path_regex_set = RegexSet::new(&config.path_patterns)?;
+impl TargetSet {
+    fn new(entries: Vec<(usize, String)>) -> Result<Self, regex::Error> {
+        let (owners, patterns): (Vec<usize>, Vec<String>) = entries.into_iter().unzip();
+        Ok(Self {
+            regex_set: RegexSet::new(patterns)?,
+            owners,
+        })
+    }
+
+    fn is_empty(&self) -> bool {
+        self.owners.is_empty()
+    }
 
-        // Make UA patterns case-insensitive
-        let ua_regexes: Vec<String> = config
-            .user_agent_patterns
+    /// Indexes of the rules (or exceptions) matching `haystack`.
+    fn matches<'a>(&'a self, haystack: &str) -> impl Iterator<Item = usize> + 'a {
+        self.regex_set
+            .matches(haystack)
+            .into_iter()
+            .map(|i| self.owners[i])
+    }
+}
+
+/// Rules compiled into per-target sets, swapped as a whole when runtime
+/// rules change.
+struct CompiledRules {
+    /// Runtime rule generation this was built from
+    generation: u64,
+    rules: Vec<ScreeningRule>,
+    /// Expiry of each rule, for runtime rules added with a TTL
+    expires: Vec<Option<Instant>>,
+    path: TargetSet,
+    query: TargetSet,
+    user_agent: TargetSet,
+    headers: Vec<(HeaderName, TargetSet)>,
+    any_header: TargetSet,
+}
+
+impl CompiledRules {
+    fn new(
+        generation: u64,
+        base: &[ScreeningRule],
+        runtime: &[RuntimeRule],
+    ) -> Result<Self, regex::Error> {
+        let rules: Vec<ScreeningRule> = base
             .iter()
-            .map(|p| format!("(?i){}", p))
+            .chain(runtime.iter().map(|r| &r.rule))
+            .cloned()
+            .collect();
+        let expires = std::iter::repeat_n(None, base.len())
+            .chain(runtime.iter().map(|r| r.expires_at))
             .collect();
 
-        let user_agent_regex_set = RegexSet::new(&ua_regexes)?;
+        let mut path = Vec::new();
+        let mut query = Vec::new();
+        let mut user_agent = Vec::new();
+        let mut headers: BTreeMap<String, Vec<(usize, String)>> = BTreeMap::new();
+        let mut any_header = Vec::new();
+        for (i, rule) in rules.iter().enumerate() {
+            for target in &rule.targets {
+                match target {
+                    RuleTarget::Path => path.push((i, rule.pattern.clone())),
+                    RuleTarget::Query => query.push((i, rule.pattern.clone())),
+                    // Make UA patterns case-insensitive
+                    RuleTarget::UserAgent => user_agent.push((i, format!("(?i){}", rule.pattern))),
+                    RuleTarget::Header(name) => headers
+                        .entry(name.to_ascii_lowercase())
+                        .or_default()
+                        .push((i, rule.pattern.clone())),
+                    RuleTarget::AnyHeader => any_header.push((i, rule.pattern.clone())),
+                }
+            }
+        }
+
+        let mut header_sets = Vec::with_capacity(headers.len());
+        for (header, entries) in headers {
+            let name = HeaderName::from_bytes(header.as_bytes()).map_err(|e| {
+                regex::Error::Syntax(format!("invalid header name {:?}: {}", header, e))
+            })?;
+            header_sets.push((name, TargetSet::new(entries)?));
+        }
 
         Ok(Self {
-            path_regex_set,
-            path_patterns: config.path_patterns.clone(),
-            user_agent_regex_set,
-            user_agent_patterns: config.user_agent_patterns.clone(),
+            generation,
+            rules,
+            expires,
+            path: TargetSet::new(path)?,
+            query: TargetSet::new(query)?,
+            user_agent: TargetSet::new(user_agent)?,
+            headers: header_sets,
+            any_header: TargetSet::new(any_header)?,
         })
     }
 
+    fn is_expired(&self, rule: usize, now: Instant) -> bool {
+        self.expires[rule].is_some_and(|expires_at| now >= expires_at)
+    }
+}
+
+#[derive(Clone)]
+struct RuntimeRule {
+    rule: ScreeningRule,
+    expires_at: Option<Instant>,
+}
+
+/// Rules added at runtime, and a generation bumped on every change.
+#[derive(Default)]
+struct RuntimeRules {
+    generation: u64,
+    rules: Vec<RuntimeRule>,
+}
+
+type RuleAudit = Arc<dyn Fn(&RuleChange) + Send + Sync>;
+
+/// Clones share the compiled rules, runtime rules and anomaly scores.
+#[derive(Clone)]
+pub struct RequestScreener {
+    /// Rules from the configuration, which runtime rules are added to
+    base_rules: Arc<Vec<ScreeningRule>>,
+    compiled: Arc<RwLock<Arc<CompiledRules>>>,
+    runtime: Arc<Mutex<RuntimeRules>>,
+    /// Latest generation swapped into `compiled`
+    rebuilt: Arc<watch::Sender<u64>>,
+    audit: Option<RuleAudit>,
+    hits: Arc<RuleHitCounters>,
+    exceptions: Vec<ScreeningException>,
+    exception_paths: TargetSet,
+    exception_user_agents: TargetSet,
+    scoring: Option<ScoringConfig>,
+    /// Per-key (window start, score) when scoring over a window
+    scores: Arc<DashMap<String, (Instant, u32)>>,
+    path_normalization: PathNormalization,
+    protocol: ProtocolChecks,
+    /// Index of each enabled protocol check's rule among the base rules
+    protocol_rules: Vec<(ProtocolCheck, usize)>,
+    dry_run: bool,
+}
+
+impl RequestScreener {
+    /// Validates `config` with `ScreeningConfig::validate` and compiles its
+    /// patterns.
+    pub fn new(config: &ScreeningConfig) -> Result<Self, ConfigError> {
+        config.validate()?;
+        let base_rules = config.all_rules();
+        // Patterns compile on their own once validated, but the combined
+        // sets can still exceed the regex size limit
+        let compiled = CompiledRules::new(0, &base_rules, &[])
+            .map_err(|e| ConfigError::invalid("rules", e.to_string()))?;
+        let protocol_rules = ProtocolCheck::ALL
+            .into_iter()
+            .filter_map(|check| {
+                let index = base_rules
+                    .iter()
+                    .position(|rule| rule.id == check.rule_id())?;
+                Some((check, index))
+            })
+            .collect();
+
+        let mut exception_paths = Vec::new();
+        let mut exception_user_agents = Vec::new();
+        for (i, exception) in config.exceptions.iter().enumerate() {
+            if let Some(pattern) = &exception.path {
+                exception_paths.push((i, pattern.clone()));
+            }
+            if let Some(pattern) = &exception.user_agent {
+                exception_user_agents.push((i, format!("(?i){}", pattern)));
+            }
+        }
+
+        Ok(Self {
+            base_rules: Arc::new(base_rules),
+            compiled: Arc::new(RwLock::new(Arc::new(compiled))),
+            runtime: Arc::new(Mutex::new(RuntimeRules::default())),
+            rebuilt: Arc::new(watch::Sender::new(0)),
+            audit: None,
+            hits: Arc::new(RuleHitCounters::default()),
+            exceptions: config.exceptions.clone(),
+            exception_paths: TargetSet::new(exception_paths)
+                .map_err(|e| ConfigError::invalid("exceptions", e.to_string()))?,
+            exception_user_agents: TargetSet::new(exception_user_agents)
+                .map_err(|e| ConfigError::invalid("exceptions", e.to_string()))?,
+            scoring: config.scoring.clone(),
+            scores: Arc::new(DashMap::new()),
+            path_normalization: config.path_normalization.clone(),
+            protocol: config.protocol.clone(),
+            protocol_rules,
+            dry_run: config.dry_run,
+        })
+    }
+
+    /// Call `audit` for every rule added, removed or expired at runtime.
+    pub fn with_rule_audit<F>(mut self, audit: F) -> Self
+    where
+        F: Fn(&RuleChange) + Send + Sync + 'static,
+    {
+        self.audit = Some(Arc::new(audit));
+        self
+    }
+
+    /// Whether matches should only be logged and metered, not enforced.
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// Every rule this screener checks, including the pattern lists and
+    /// runtime rules whose rebuild has completed.
+    pub fn rules(&self) -> Vec<ScreeningRule> {
+        self.compiled().rules.clone()
+    }
+
+    /// Hit stats for every rule, in the order of `rules()`. Rules that never
+    /// matched are included with zero hits.
+    pub fn rule_stats(&self) -> Vec<RuleStats> {
+        self.hits.snapshot(&self.compiled().rules)
+    }
+
+    /// Hit stats for one rule, or `None` if there is no such rule.
+    pub fn rule_stats_for(&self, rule_id: &str) -> Option<RuleStats> {
+        self.compiled()
+            .rules
+            .iter()
+            .any(|rule| rule.id == rule_id)
+            .then(|| self.hits.get(rule_id))
+    }
+
+    /// Zero every rule's hit stats.
+    pub fn reset_rule_stats(&self) {
+        self.hits.reset();
+    }
+
+    /// Add a rule at runtime, removed again after `ttl` if given. The rule is
+    /// validated immediately; the rule sets are rebuilt in the background
+    /// and swapped in atomically, so it takes effect shortly after this
+    /// returns (see `wait_for_rebuild`). A `ttl` too large to represent as
+    /// an expiry time is rejected.
+    pub fn add_rule(&self, rule: ScreeningRule, ttl: Option<Duration>) -> Result<(), ConfigError> {
+        rule.validate("rule")?;
+        // The enforced and audited expiry come from the same TTL, so both are
+        // computed up front and the rule is rejected if either overflows
+        let expiry = ttl
+            .map(|ttl| {
+                let instant = Instant::now().checked_add(ttl);
+                let wall_clock = chrono::Duration::from_std(ttl)
+                    .ok()
+                    .and_then(|ttl| Utc::now().checked_add_signed(ttl));
+                instant
+                    .zip(wall_clock)
+                    .ok_or_else(|| ConfigError::invalid("ttl", format!("TTL {ttl:?} is too large")))
+            })
+            .transpose()?;
+        if matches!(rule.action, RuleAction::Score(_)) && self.scoring.is_none() {
+            return Err(ConfigError::invalid(
+                "rule",
+                format!(
+                    "rule '{}' has a score action but scoring is not configured",
+                    rule.id
+                ),
+            ));
+        }
+
+        let (generation, snapshot) = {
+            let mut runtime = lock(&self.runtime);
+            if self.has_rule(&runtime, &rule.id) {
+                return Err(ConfigError::invalid(
+                    "rule",
+                    format!("duplicate rule id '{}'", rule.id),
+                ));
+            }
+            runtime.rules.push(RuntimeRule {
+                rule: rule.clone(),
+                expires_at: expiry.map(|(instant, _)| instant),
+            });
+            runtime.generation += 1;
+            (runtime.generation, runtime.rules.clone())
+        };
+
+        let expires_at = expiry.map(|(_, wall_clock)| wall_clock);
+        self.audit(RuleChange::Added { rule, expires_at });
+        self.spawn_rebuild(generation, snapshot);
+        Ok(())
+    }
+
+    /// Remove a rule added with `add_rule`. Returns false if there is no
+    /// such runtime rule; rules from the configuration cannot be removed.
+    pub fn remove_rule(&self, id: &str) -> bool {
+        let (removed, generation, snapshot) = {
+            let mut runtime = lock(&self.runtime);
+            let Some(index) = runtime.rules.iter().position(|r| r.rule.id == id) else {
+                return false;
+            };
+            let removed = runtime.rules.remove(index);
+            runtime.generation += 1;
+            (removed, runtime.generation, runtime.rules.clone())
+        };
+
+        self.hits.remove(id);
+        self.audit(RuleChange::Removed { rule: removed.rule });
+        self.spawn_rebuild(generation, snapshot);
+        true
+    }
+
+    /// Remove runtime rules whose TTL has passed, returning how many were
+    /// removed. Expired rules stop matching immediately; this only drops
+    /// them from the rule sets. Called from `RateLimiter::cleanup_cache`.
+    pub fn remove_expired_rules(&self) -> usize {
+        let now = Instant::now();
+        let (expired, generation, snapshot) = {
+            let mut runtime = lock(&self.runtime);
+            let (expired, live): (Vec<RuntimeRule>, Vec<RuntimeRule>) =
+                std::mem::take(&mut runtime.rules)
+                    .into_iter()
+                    .partition(|r| r.expires_at.is_some_and(|expires_at| now >= expires_at));
+            runtime.rules = live;
+            if expired.is_empty() {
+                return 0;
+            }
+            runtime.generation += 1;
+            (expired, runtime.generation, runtime.rules.clone())
+        };
+
+        let count = expired.len();
+        for expired in expired {
+            self.hits.remove(&expired.rule.id);
+            self.audit(RuleChange::Expired { rule: expired.rule });
+        }
+        self.spawn_rebuild(generation, snapshot);
+        count
+    }
+
+    /// Wait until every rule change made so far is in effect.
+    pub async fn wait_for_rebuild(&self) {
+        let generation = lock(&self.runtime).generation;
+        let mut rebuilt = self.rebuilt.subscribe();
+        // The sender is held by self, so the channel cannot close
+        let _ = rebuilt.wait_for(|built| *built >= generation).await;
+    }
+
+    /// Take over runtime rules, rule hit stats and the audit callback (if
+    /// none is set) from the screener this one replaces, so a configuration
+    /// reload keeps rules added during an incident. Runtime rules whose ID is
+    /// now in the configuration are dropped.
+    pub(crate) fn inherit_from(&mut self, previous: &RequestScreener) {
+        if self.audit.is_none() {
+            self.audit = previous.audit.clone();
+        }
+        self.hits = previous.hits.clone();
+
+        let now = Instant::now();
+        let inherited: Vec<RuntimeRule> = lock(&previous.runtime)
+            .rules
+            .iter()
+            .filter(|r| r.expires_at.is_none_or(|expires_at| now < expires_at))
+            .filter(|r| {
+                let clash = self.base_rules.iter().any(|rule| rule.id == r.rule.id);
+                if clash {
+                    tracing::warn!(
+                        "Dropping runtime screening rule {}: the configuration now defines it",
+                        r.rule.id
+                    );
+                }
+                !clash
+            })
+            .cloned()
+            .collect();
+
+        if !inherited.is_empty() {
+            let mut runtime = lock(&self.runtime);
+            runtime.generation += 1;
+            match CompiledRules::new(runtime.generation, &self.base_rules, &inherited) {
+                Ok(compiled) => {
+                    runtime.rules = inherited;
+                    self.rebuilt.send_replace(compiled.generation);
+                    *write_lock(&self.compiled) = Arc::new(compiled);
+                }
+                Err(e) => tracing::error!("Dropping runtime screening rules: {}", e),
+            }
+        }
+        self.hits.retain(&self.compiled().rules);
+    }
+
+    fn has_rule(&self, runtime: &RuntimeRules, id: &str) -> bool {
+        self.base_rules
+            .iter()
+            .chain(runtime.rules.iter().map(|r| &r.rule))
+            .any(|rule| rule.id == id)
+    }
+
+    fn audit(&self, change: RuleChange) {
+        tracing::info!("Screening {}", change);
+        if let Some(audit) = &self.audit {
+            audit(&change);
+        }
+    }
+
+    /// Compile the rule sets off the request path and swap them in, unless a
+    /// newer generation got there first.
+    fn spawn_rebuild(&self, generation: u64, runtime: Vec<RuntimeRule>) {
+        let base_rules = self.base_rules.clone();
+        let compiled = self.compiled.clone();
+        let rebuilt = self.rebuilt.clone();
+        let rebuild = move || match CompiledRules::new(generation, &base_rules, &runtime) {
+            Ok(new) => {
+                let mut current = write_lock(&compiled);
+                if new.generation > current.generation {
+                    *current = Arc::new(new);
+                    rebuilt.send_if_modified(|built| {
+                        let newer = generation > *built;
+                        if newer {
+                            *built = generation;
+                        }
+                        newer
+                    });
+                }
+            }
+            Err(e) => tracing::error!("Failed to rebuild screening rules: {}", e),
+        };
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn_blocking(rebuild);
+            }
+            Err(_) => rebuild(),
+        }
+    }
+
+    fn compiled(&self) -> Arc<CompiledRules> {
+        read_lock(&self.compiled).clone()
+    }
+
+    /// Screen only the path and user agent. Use `check_request` to also
+    /// screen the query string and headers.
     pub fn check(&self, path: &str, user_agent: &str) -> Option<ScreeningResult> {
+        self.check_request(&ScreeningRequest::new(path, user_agent))
+    }
+
+    pub fn check_request(&self, request: &ScreeningRequest<'_>) -> Option<ScreeningResult> {
+        let compiled = self.compiled();
+        let rules = &compiled.rules;
+        let now = Instant::now();
+
+        // (rule index, reason it first matched), in the order targets are checked
+        let mut matches: Vec<(usize, ScreeningReason)> = Vec::new();
+        let mut record = |rule: usize, reason: &dyn Fn(&str) -> ScreeningReason| {
+            if !compiled.is_expired(rule, now)
+                && !matches.iter().any(|(matched, _)| *matched == rule)
+            {
+                matches.push((rule, reason(&rules[rule].pattern)));
+            }
+        };
+
         // Single-pass check against all path patterns
-        if let Some(idx) = self.path_regex_set.matches(path).iter().next() {
-            return Some(ScreeningResult {
-                reason: ScreeningReason::MaliciousPath(self.path_patterns[idx].clone()),
+        let path = self.path_normalization.normalize(request.path);
+        for rule in compiled.path.matches(&path) {
+            record(rule, &|pattern| {
+                ScreeningReason::MaliciousPath(pattern.to_string())
             });
         }
 
-        // Single-pass check against all user agent patterns (no allocation)
-        if let Some(idx) = self.user_agent_regex_set.matches(user_agent).iter().next() {
-            return Some(ScreeningResult {
-                reason: ScreeningReason::MaliciousUserAgent(self.user_agent_patterns[idx].clone()),
+        if let Some(query) = request.query {
+            for rule in compiled.query.matches(query) {
+                record(rule, &|pattern| {
+                    ScreeningReason::MaliciousQuery(pattern.to_string())
+                });
+            }
+        }
+
+        for rule in compiled.user_agent.matches(request.user_agent) {
+            record(rule, &|pattern| {
+                ScreeningReason::MaliciousUserAgent(pattern.to_string())
             });
         }
 
-        None
+        if let Some(headers) = request.headers {
+            for (name, set) in &compiled.headers {
+                for value in headers.get_all(name) {
+                    let value = String::from_utf8_lossy(value.as_bytes());
+                    for rule in set.matches(&value) {
+                        record(rule, &|pattern| ScreeningReason::MaliciousHeader {
+                            header: name.to_string(),
+                            pattern: pattern.to_string(),
+                        });
+                    }
+                }
+            }
+
+            if !compiled.any_header.is_empty() {
+                for (name, value) in headers {
+                    let value = String::from_utf8_lossy(value.as_bytes());
+                    for rule in compiled.any_header.matches(&value) {
+                        record(rule, &|pattern| ScreeningReason::MaliciousHeader {
+                            header: name.to_string(),
+                            pattern: pattern.to_string(),
+                        });
+                    }
+                }
+            }
+        }
+
+        if !self.protocol_rules.is_empty() {
+            for (check, detail) in self.protocol.violations(request) {
+                let Some(&(_, rule)) = self.protocol_rules.iter().find(|(c, _)| *c == check) else {
+                    continue;
+                };
+                tracing::debug!("Protocol check {} failed: {}", check.rule_id(), detail);
+                record(rule, &|_| ScreeningReason::ProtocolViolation {
+                    check,
+                    detail: detail.clone(),
+                });
+            }
+        }
+
+        if !matches.is_empty() && !self.exceptions.is_empty() {
+            self.apply_exceptions(rules, &path, request.user_agent, &mut matches);
+        }
+        if matches.is_empty() {
+            return None;
+        }
+        self.hits.record(
+            matches.iter().map(|(rule, _)| rules[*rule].id.as_str()),
+            request.key,
+        );
+        matches.sort_by_key(|(rule, _)| *rule);
+
+        let points = matches
+            .iter()
+            .filter_map(|(rule, _)| match rules[*rule].action {
+                RuleAction::Score(points) => Some(points),
+                _ => None,
+            })
+            .fold(0u32, u32::saturating_add);
+        let (score, anomaly) = match &self.scoring {
+            Some(scoring) if points > 0 => {
+                let score = self.add_score(request.key, points, scoring);
+                if score < scoring.threshold {
+                    tracing::debug!(
+                        "Anomaly score {} below threshold {} (key: {})",
+                        score,
+                        scoring.threshold,
+                        request.key.unwrap_or("-")
+                    );
+                }
+                (score, (score >= scoring.threshold).then_some(scoring))
+            }
+            _ => (0, None),
+        };
+
+        // The most severe action applies; the earliest rule breaks ties and
+        // the anomaly score comes after every rule
+        let mut deciding: Option<usize> = None;
+        for (i, (rule, _)) in matches.iter().enumerate() {
+            let action = &rules[*rule].action;
+            if matches!(action, RuleAction::Score(_)) {
+                continue;
+            }
+            if deciding.is_none_or(|d| action.severity() > rules[matches[d].0].action.severity()) {
+                deciding = Some(i);
+            }
+        }
+        if let Some(scoring) = anomaly {
+            if deciding
+                .is_none_or(|d| scoring.action.severity() > rules[matches[d].0].action.severity())
+            {
+                deciding = None;
+            }
+        }
+
+        let matched_rules = matches
+            .iter()
+            .map(|(rule, _)| rules[*rule].id.clone())
+            .collect();
+        match (deciding, anomaly) {
+            (Some(deciding), _) => {
+                let (rule, reason) = matches.swap_remove(deciding);
+                Some(ScreeningResult {
+                    reason,
+                    rule_id: rules[rule].id.clone(),
+                    action: rules[rule].action.clone(),
+                    matched_rules,
+                    score,
+                })
+            }
+            (None, Some(scoring)) => Some(ScreeningResult {
+                reason: ScreeningReason::AnomalyScore {
+                    score,
+                    threshold: scoring.threshold,
+                },
+                rule_id: ANOMALY_SCORE_RULE_ID.to_string(),
+                action: scoring.action.clone(),
+                matched_rules,
+                score,
+            }),
+            // Only scoring rules matched, below the threshold
+            (None, None) => None,
+        }
     }
+
+    /// Drop the matches suppressed by exceptions matching this request.
+    fn apply_exceptions(
+        &self,
+        rules: &[ScreeningRule],
+        path: &str,
+        user_agent: &str,
+        matches: &mut Vec<(usize, ScreeningReason)>,
+    ) {
+        let paths: Vec<usize> = self.exception_paths.matches(path).collect();
+        let user_agents: Vec<usize> = self.exception_user_agents.matches(user_agent).collect();
+
+        for (i, exception) in self.exceptions.iter().enumerate() {
+            let applies = (exception.path.is_none() || paths.contains(&i))
+                && (exception.user_agent.is_none() || user_agents.contains(&i));
+            if !applies {
+                continue;
+            }
+
+            let mut suppressed = Vec::new();
+            matches.retain(|(rule, _)| {
+                let id = &rules[*rule].id;
+                let suppress = exception.rules.is_empty() || exception.rules.contains(id);
+                if suppress {
+                    suppressed.push(id.as_str());
+                }
+                !suppress
+            });
+            if !suppressed.is_empty() {
+                tracing::debug!(
+                    "Screening exception {} suppressed rules {:?} (path: {}, user-agent: {})",
+                    exception.id,
+                    suppressed,
+                    path,
+                    user_agent
+                );
+            }
+        }
+    }
+
+    /// Add `points` to the score for this request, accumulated per key when
+    /// scoring over a window. A key's score restarts once it reaches the
+    /// threshold.
+    fn add_score(&self, key: Option<&str>, points: u32, scoring: &ScoringConfig) -> u32 {
+        let Some(key) = key.filter(|_| !scoring.window.is_zero()) else {
+            return points;
+        };
+
+        let now = Instant::now();
+        let score = {
+            let mut entry = self.scores.entry(key.to_string()).or_insert((now, 0));
+            if now.duration_since(entry.0) >= scoring.window {
+                *entry = (now, 0);
+            }
+            entry.1 = entry.1.saturating_add(points);
+            entry.1
+        };
+        if score >= scoring.threshold {
+            self.scores.remove(key);
+        }
+        score
+    }
+
+    /// Drop accumulated scores whose window has passed.
+    pub(crate) fn cleanup_scores(&self) {
+        if let Some(scoring) = &self.scoring {
+            let now = Instant::now();
+            self.scores
+                .retain(|_, (started, _)| now.duration_since(*started) < scoring.window);
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn read_lock<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn write_lock<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
 }
 
 #[cfg(test)]
@@ -191,5 +1337,664 @@ mod tests {
         let config = ScreeningConfig::default();
         assert!(config.path_patterns.is_empty());
         assert!(config.user_agent_patterns.is_empty());
+        assert!(config.query_patterns.is_empty());
+        assert!(config.header_patterns.is_empty());
+        assert!(config.any_header_patterns.is_empty());
+        assert!(!config.dry_run);
+    }
+
+    #[test]
+    fn test_dry_run_still_reports_matches() {
+        let screener = RequestScreener::new(&test_config().with_dry_run(true)).unwrap();
+
+        assert!(screener.is_dry_run());
+        assert!(screener.check("/.git/config", "Mozilla/5.0").is_some());
+    }
+
+    #[test]
+    fn test_catches_query_and_header_payloads() {
+        let screener = RequestScreener::new(
+            &test_config()
+                .with_query_pattern(r"(?i)union(\s|\+|%20)+select")
+                .with_query_pattern(r"\.\./")
+                .with_header_pattern("Referer", r"(?i)<script")
+                .with_any_header_pattern(r"\$\{jndi:"),
+        )
+        .unwrap();
+
+        let result = screener
+            .check_request(
+                &ScreeningRequest::new("/search", "Mozilla/5.0").with_query("q=1+UNION+SELECT+1"),
+            )
+            .unwrap();
+        assert!(matches!(result.reason, ScreeningReason::MaliciousQuery(_)));
+        assert!(screener
+            .check_request(
+                &ScreeningRequest::new("/", "Mozilla/5.0").with_query("file=../../etc/passwd")
+            )
+            .is_some());
+
+        let mut headers = HeaderMap::new();
+        headers.insert("referer", "https://x/<SCRIPT>".parse().unwrap());
+        let result = screener
+            .check_request(&ScreeningRequest::new("/", "Mozilla/5.0").with_headers(&headers))
+            .unwrap();
+        match result.reason {
+            ScreeningReason::MaliciousHeader { header, .. } => assert_eq!(header, "referer"),
+            other => panic!("unexpected reason: {}", other),
+        }
+
+        let request = Request::builder()
+            .uri("/?page=2")
+            .header("x-api-version", "${jndi:ldap://x/a}")
+            .body(())
+            .unwrap();
+        assert!(screener
+            .check_request(&ScreeningRequest::from_request(&request, "Mozilla/5.0"))
+            .is_some());
+
+        // The path-and-user-agent check ignores the extra pattern sets
+        assert!(screener.check("/search", "Mozilla/5.0").is_none());
+        let clean = Request::builder()
+            .uri("/search?q=rust")
+            .header("referer", "https://example.com/")
+            .body(())
+            .unwrap();
+        assert!(screener
+            .check_request(&ScreeningRequest::from_request(&clean, "Mozilla/5.0"))
+            .is_none());
+    }
+
+    #[test]
+    fn test_validate_reports_header_pattern_field() {
+        let err = ScreeningConfig::new()
+            .with_header_pattern("Referer", "(")
+            .validate()
+            .unwrap_err();
+        assert!(err.to_string().contains("header_patterns[referer][0]"));
+
+        let err = ScreeningConfig::new()
+            .with_header_pattern("bad header", "x")
+            .validate()
+            .unwrap_err();
+        assert!(err.to_string().contains("header_patterns[bad header]"));
+    }
+
+    #[test]
+    fn test_path_normalization_defeats_evasion() {
+        let screener = RequestScreener::new(&test_config()).unwrap();
+        for path in ["/%2egit/config", "//.git//config", "/foo/../.git/config"] {
+            assert!(screener.check(path, "Mozilla/5.0").is_some(), "{}", path);
+        }
+
+        let raw =
+            RequestScreener::new(&test_config().with_path_normalization(PathNormalization::none()))
+                .unwrap();
+        assert!(raw.check("/%2egit/config", "Mozilla/5.0").is_none());
+
+        let folded = RequestScreener::new(
+            &test_config().with_path_normalization(PathNormalization::new().with_case_fold(true)),
+        )
+        .unwrap();
+        assert!(screener.check("/.GIT/config", "Mozilla/5.0").is_none());
+        assert!(folded.check("/.GIT/config", "Mozilla/5.0").is_some());
+    }
+
+    #[test]
+    fn test_rules_report_every_match_and_most_severe_action() {
+        let screener = RequestScreener::new(
+            &test_config()
+                .with_rule(
+                    ScreeningRule::new("scanner-ua-curl", "curl/")
+                        .with_description("Scripted clients")
+                        .with_target(RuleTarget::UserAgent)
+                        .with_action(RuleAction::LogOnly),
+                )
+                .with_rule(
+                    ScreeningRule::new("backup-file", r"\.(bak|old)$")
+                        .with_target(RuleTarget::Path)
+                        .with_action(RuleAction::RejectOnly),
+                ),
+        )
+        .unwrap();
+
+        let result = screener.check("/index.html", "CURL/8.0").unwrap();
+        assert_eq!(result.rule_id, "scanner-ua-curl");
+        assert_eq!(result.action, RuleAction::LogOnly);
+        assert!(!result.action.rejects());
+        assert_eq!(result.matched_rules, vec!["scanner-ua-curl"]);
+
+        let result = screener.check("/config.php.bak", "curl/8.0").unwrap();
+        assert_eq!(result.rule_id, "backup-file");
+        assert_eq!(result.action, RuleAction::RejectOnly);
+        assert_eq!(result.matched_rules, vec!["scanner-ua-curl", "backup-file"]);
+
+        // Pattern lists are BlockIp rules and outrank the named rules here
+        let result = screener.check("/.git/config.bak", "curl/8.0").unwrap();
+        assert_eq!(result.rule_id, "path_patterns[2]");
+        assert_eq!(result.action, RuleAction::BlockIp);
+        assert_eq!(
+            result.matched_rules,
+            vec!["path_patterns[2]", "scanner-ua-curl", "backup-file"]
+        );
+    }
+
+    #[test]
+    fn test_rule_with_several_targets() {
+        let screener = RequestScreener::new(
+            &ScreeningConfig::new().with_rule(
+                ScreeningRule::new("log4shell", r"\$\{jndi:")
+                    .with_target(RuleTarget::Query)
+                    .with_target(RuleTarget::AnyHeader)
+                    .with_action(RuleAction::Tarpit {
+                        delay: std::time::Duration::from_secs(5),
+                    }),
+            ),
+        )
+        .unwrap();
+
+        let request = Request::builder()
+            .uri("/")
+            .header("x-forwarded-host", "${jndi:ldap://x}")
+            .body(())
+            .unwrap();
+        let result = screener
+            .check_request(&ScreeningRequest::from_request(&request, "Mozilla/5.0"))
+            .unwrap();
+        assert_eq!(result.matched_rules, vec!["log4shell"]);
+        assert!(matches!(
+            result.reason,
+            ScreeningReason::MaliciousHeader { ref header, .. } if header == "x-forwarded-host"
+        ));
+        assert!(screener
+            .check_request(&ScreeningRequest::new("/", "Mozilla/5.0").with_query("q=${jndi:x}"))
+            .is_some());
+        assert!(screener.check("/${jndi:x}", "Mozilla/5.0").is_none());
+    }
+
+    #[test]
+    fn test_validate_rules() {
+        let duplicate = ScreeningConfig::new()
+            .with_rule(ScreeningRule::new("a", "x").with_target(RuleTarget::Path))
+            .with_rule(ScreeningRule::new("a", "y").with_target(RuleTarget::Path));
+        assert!(duplicate
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("duplicate rule id 'a'"));
+
+        let no_targets = ScreeningConfig::new().with_rule(ScreeningRule::new("a", "x"));
+        assert!(no_targets
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("rules[0]"));
+
+        let bad_pattern = ScreeningConfig::new()
+            .with_path_pattern("ok")
+            .with_rule(ScreeningRule::new("broken", "(").with_target(RuleTarget::Path));
+        let err = bad_pattern.validate().unwrap_err().to_string();
+        assert!(
+            err.contains("rules[0]") && err.contains("broken"),
+            "{}",
+            err
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_rules_from_toml() {
+        let config = ScreeningConfig::from_toml_str(
+            r#"
+            [[rules]]
+            id = "php-probe"
+            description = "PHP files on a Rust service"
+            pattern = '\.php$'
+            targets = ["path"]
+
+            [[rules]]
+            id = "sqli-union"
+            pattern = '(?i)union.+select'
+            targets = ["query", { header = "referer" }]
+            action = { tarpit = { delay = "2s" } }
+
+            [[rules]]
+            id = "curl"
+            pattern = "curl"
+            targets = ["user_agent"]
+            action = "log_only"
+
+            [[rules]]
+            id = "wget"
+            pattern = "wget"
+            targets = ["user_agent"]
+            action = { score = 3 }
+
+            [scoring]
+            threshold = 5
+            window = "10m"
+            action = "reject_only"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.rules.len(), 4);
+        assert_eq!(config.rules[3].action, RuleAction::Score(3));
+        assert_eq!(
+            config.scoring,
+            Some(
+                ScoringConfig::new(5)
+                    .with_window(std::time::Duration::from_secs(600))
+                    .with_action(RuleAction::RejectOnly)
+            )
+        );
+        assert_eq!(config.rules[0].action, RuleAction::BlockIp);
+        assert_eq!(
+            config.rules[1].targets,
+            vec![RuleTarget::Query, RuleTarget::Header("referer".to_string())]
+        );
+        assert_eq!(
+            config.rules[1].action,
+            RuleAction::Tarpit {
+                delay: std::time::Duration::from_secs(2)
+            }
+        );
+        assert_eq!(config.rules[2].action, RuleAction::LogOnly);
+    }
+
+    fn scoring_config(scoring: ScoringConfig) -> ScreeningConfig {
+        ScreeningConfig::new()
+            .with_rule(
+                ScreeningRule::new("curl-ua", "curl")
+                    .with_target(RuleTarget::UserAgent)
+                    .with_action(RuleAction::Score(2)),
+            )
+            .with_rule(
+                ScreeningRule::new("backup-file", r"\.bak$")
+                    .with_target(RuleTarget::Path)
+                    .with_action(RuleAction::Score(3)),
+            )
+            .with_scoring(scoring)
+    }
+
+    #[test]
+    fn test_scores_add_up_per_request() {
+        let screener = RequestScreener::new(&scoring_config(ScoringConfig::new(5))).unwrap();
+
+        assert!(screener.check("/site.bak", "Mozilla/5.0").is_none());
+        assert!(screener.check("/", "curl/8.0").is_none());
+
+        let result = screener.check("/site.bak", "curl/8.0").unwrap();
+        assert_eq!(result.rule_id, ANOMALY_SCORE_RULE_ID);
+        assert_eq!(result.action, RuleAction::BlockIp);
+        assert_eq!(result.score, 5);
+        assert_eq!(result.matched_rules, vec!["curl-ua", "backup-file"]);
+        assert!(matches!(
+            result.reason,
+            ScreeningReason::AnomalyScore {
+                score: 5,
+                threshold: 5
+            }
+        ));
+    }
+
+    #[test]
+    fn test_scores_accumulate_per_key_over_window() {
+        let screener = RequestScreener::new(&scoring_config(
+            ScoringConfig::new(6)
+                .with_window(std::time::Duration::from_secs(60))
+                .with_action(RuleAction::RejectOnly),
+        ))
+        .unwrap();
+        let request = |key| ScreeningRequest::new("/site.bak", "Mozilla/5.0").with_key(key);
+
+        assert!(screener.check_request(&request("10.0.0.1")).is_none());
+        assert!(screener.check_request(&request("10.0.0.2")).is_none());
+        let result = screener.check_request(&request("10.0.0.1")).unwrap();
+        assert_eq!(result.score, 6);
+        assert_eq!(result.action, RuleAction::RejectOnly);
+
+        // The score restarts once the threshold is reached
+        assert!(screener.check_request(&request("10.0.0.1")).is_none());
+        // Requests without a key are scored on their own
+        assert!(screener.check("/site.bak", "Mozilla/5.0").is_none());
+    }
+
+    #[test]
+    fn test_rule_actions_outrank_lower_anomaly_action() {
+        let screener = RequestScreener::new(
+            &scoring_config(ScoringConfig::new(2).with_action(RuleAction::LogOnly))
+                .with_path_pattern(r"\.php$"),
+        )
+        .unwrap();
+
+        let result = screener.check("/x.php", "curl/8.0").unwrap();
+        assert_eq!(result.rule_id, "path_patterns[0]");
+        assert_eq!(result.score, 2);
+
+        let result = screener.check("/", "curl/8.0").unwrap();
+        assert_eq!(result.rule_id, ANOMALY_SCORE_RULE_ID);
+        assert_eq!(result.action, RuleAction::LogOnly);
+    }
+
+    #[test]
+    fn test_validate_scoring() {
+        let unscored = ScreeningConfig::new().with_rule(
+            ScreeningRule::new("a", "x")
+                .with_target(RuleTarget::Path)
+                .with_action(RuleAction::Score(1)),
+        );
+        assert!(unscored
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("scoring is not configured"));
+
+        assert!(scoring_config(ScoringConfig::new(0)).validate().is_err());
+        assert!(
+            scoring_config(ScoringConfig::new(1).with_action(RuleAction::Score(1)))
+                .validate()
+                .is_err()
+        );
+        assert!(scoring_config(ScoringConfig::new(5)).validate().is_ok());
+    }
+
+    #[test]
+    fn test_exceptions_suppress_rules() {
+        let screener = RequestScreener::new(
+            &test_config()
+                .with_user_agent_pattern("health")
+                .with_exception(
+                    ScreeningException::new("frontend-vendor")
+                        .with_path("^/assets/vendor/")
+                        .for_rule("path_patterns[1]"),
+                )
+                .with_exception(
+                    ScreeningException::new("health-checker").with_user_agent("^HealthChecker/"),
+                )
+                .with_exception(
+                    ScreeningException::new("ops-git-mirror")
+                        .with_path("^/mirror/")
+                        .with_user_agent("^GitMirror/"),
+                ),
+        )
+        .unwrap();
+
+        // Path exception, limited to one rule
+        assert!(screener
+            .check("/assets/vendor/app.js", "Mozilla/5.0")
+            .is_none());
+        assert!(screener.check("/vendor/app.js", "Mozilla/5.0").is_some());
+        assert!(screener
+            .check("/assets/vendor/x.php", "Mozilla/5.0")
+            .is_some_and(|r| r.matched_rules == vec!["path_patterns[0]"]));
+
+        // User agent exception, suppressing every rule
+        assert!(screener.check("/health", "healthchecker/1.0").is_none());
+        assert!(screener.check("/health", "curl-health").is_some());
+
+        // Path and user agent pair must match together
+        assert!(screener.check("/mirror/.git/HEAD", "GitMirror/2").is_none());
+        assert!(screener.check("/mirror/.git/HEAD", "Mozilla/5.0").is_some());
+        assert!(screener.check("/.git/HEAD", "GitMirror/2").is_some());
+    }
+
+    #[test]
+    fn test_validate_exceptions() {
+        let empty = ScreeningConfig::new().with_exception(ScreeningException::new("x"));
+        assert!(empty
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("exceptions[0]"));
+
+        let unknown = test_config().with_exception(
+            ScreeningException::new("x")
+                .with_path("^/a")
+                .for_rule("nope"),
+        );
+        assert!(unknown
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("unknown rule 'nope'"));
+
+        let known = test_config().with_exception(
+            ScreeningException::new("x")
+                .with_path("^/a")
+                .for_rule("path_patterns[0]"),
+        );
+        assert!(known.validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_runtime_rules() {
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let audit = changes.clone();
+        let screener = RequestScreener::new(&test_config())
+            .unwrap()
+            .with_rule_audit(move |change| audit.lock().unwrap().push(change.to_string()));
+        let rule = ScreeningRule::new("campaign", "^/cgi-bin/luci").with_target(RuleTarget::Path);
+
+        screener.add_rule(rule.clone(), None).unwrap();
+        screener.wait_for_rebuild().await;
+        assert!(screener
+            .check("/cgi-bin/luci/;stok=/locale", "Mozilla/5.0")
+            .is_some_and(|r| r.rule_id == "campaign"));
+        assert_eq!(screener.rules().len(), 5);
+
+        // Duplicate IDs and invalid patterns are rejected without a rebuild
+        assert!(screener.add_rule(rule, None).is_err());
+        let invalid = ScreeningRule::new("bad", "(").with_target(RuleTarget::Path);
+        assert!(screener.add_rule(invalid, None).is_err());
+        let configured = ScreeningRule::new("path_patterns[0]", "x").with_target(RuleTarget::Path);
+        assert!(screener.add_rule(configured, None).is_err());
+
+        assert!(!screener.remove_rule("path_patterns[0]"));
+        assert!(screener.remove_rule("campaign"));
+        assert!(!screener.remove_rule("campaign"));
+        screener.wait_for_rebuild().await;
+        assert!(screener.check("/cgi-bin/luci", "Mozilla/5.0").is_none());
+        assert!(screener.check("/index.php", "Mozilla/5.0").is_some());
+
+        assert_eq!(
+            *changes.lock().unwrap(),
+            vec!["added rule campaign", "removed rule campaign"]
+        );
+    }
+
+    #[test]
+    fn test_runtime_rule_ttl() {
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let audit = changes.clone();
+        let screener = RequestScreener::new(&test_config())
+            .unwrap()
+            .with_rule_audit(move |change| audit.lock().unwrap().push(change.clone()));
+
+        // Outside a runtime the rebuild happens before add_rule returns
+        let rule = ScreeningRule::new("scanner", "masscan").with_target(RuleTarget::UserAgent);
+        screener
+            .add_rule(rule, Some(Duration::from_millis(50)))
+            .unwrap();
+        assert!(screener.check("/", "masscan/1.3").is_some());
+        assert_eq!(screener.remove_expired_rules(), 0);
+
+        std::thread::sleep(Duration::from_millis(60));
+        // Expired rules stop matching before they are removed
+        assert!(screener.check("/", "masscan/1.3").is_none());
+        assert_eq!(screener.remove_expired_rules(), 1);
+        assert_eq!(screener.rules().len(), 4);
+
+        let changes = changes.lock().unwrap();
+        assert!(matches!(
+            changes[0],
+            RuleChange::Added {
+                expires_at: Some(_),
+                ..
+            }
+        ));
+        assert!(matches!(&changes[1], RuleChange::Expired { rule } if rule.id == "scanner"));
+    }
+
+    #[test]
+    fn test_runtime_rule_ttl_overflow_rejected() {
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let audit = changes.clone();
+        let screener = RequestScreener::new(&test_config())
+            .unwrap()
+            .with_rule_audit(move |change| audit.lock().unwrap().push(change.clone()));
+
+        let rule = ScreeningRule::new("scanner", "masscan").with_target(RuleTarget::UserAgent);
+        assert!(screener.add_rule(rule, Some(Duration::MAX)).is_err());
+        assert!(screener.check("/", "masscan/1.3").is_none());
+        assert_eq!(screener.rules().len(), 4);
+        assert!(changes.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_replaced_screener_keeps_runtime_rules() {
+        let previous = RequestScreener::new(&test_config()).unwrap();
+        previous
+            .add_rule(
+                ScreeningRule::new("campaign", "^/boaform/").with_target(RuleTarget::Path),
+                None,
+            )
+            .unwrap();
+        previous
+            .add_rule(
+                ScreeningRule::new("hnap", "^/hnap1").with_target(RuleTarget::Path),
+                None,
+            )
+            .unwrap();
+
+        let config = ScreeningConfig::new()
+            .with_rule(ScreeningRule::new("hnap", "(?i)^/hnap1").with_target(RuleTarget::Path));
+        let mut screener = RequestScreener::new(&config).unwrap();
+        screener.inherit_from(&previous);
+
+        let ids: Vec<String> = screener.rules().into_iter().map(|r| r.id).collect();
+        assert_eq!(ids, vec!["hnap", "campaign"]);
+        assert!(screener.check("/boaform/admin", "Mozilla/5.0").is_some());
+        assert!(screener.remove_rule("campaign"));
+    }
+
+    #[test]
+    fn test_rule_stats() {
+        let screener = RequestScreener::new(
+            &test_config()
+                .with_exception(ScreeningException::new("assets").with_path("^/assets/vendor/")),
+        )
+        .unwrap();
+
+        let request = |path| ScreeningRequest::new(path, "Mozilla/5.0");
+        screener.check_request(&request("/index.php").with_key("10.0.0.1"));
+        screener.check_request(&request("/index.php").with_key("10.0.0.2"));
+        screener.check_request(&request("/vendor/x.php").with_key("10.0.0.1"));
+        // Suppressed by the exception, so not a hit
+        screener.check_request(&request("/assets/vendor/app.js").with_key("10.0.0.3"));
+
+        let stats = screener.rule_stats();
+        assert_eq!(stats.len(), 4);
+        assert_eq!(stats[0].rule_id, "path_patterns[0]");
+        assert_eq!((stats[0].hits, stats[0].unique_keys), (3, 2));
+        assert_eq!((stats[1].hits, stats[1].unique_keys), (1, 1));
+        assert!(stats[1].last_hit.is_some());
+        assert_eq!((stats[3].hits, stats[3].last_hit), (0, None));
+        assert!(screener.rule_stats_for("nope").is_none());
+
+        // Clones share stats
+        screener.clone().reset_rule_stats();
+        assert_eq!(screener.rule_stats_for("path_patterns[0]").unwrap().hits, 0);
+    }
+
+    #[test]
+    fn test_protocol_checks() {
+        let config = test_config()
+            .with_protocol_checks(
+                ProtocolChecks::new()
+                    .with_required_header("accept")
+                    .with_allowed_method("GET")
+                    .with_reject_ip_host(true)
+                    .with_action(RuleAction::RejectOnly),
+            )
+            .with_exception(
+                ScreeningException::new("webdav")
+                    .with_path("^/dav/")
+                    .for_rule("protocol.method"),
+            );
+        config.validate().unwrap();
+        let screener = RequestScreener::new(&config).unwrap();
+
+        let ids: Vec<String> = screener.rules().into_iter().map(|r| r.id).collect();
+        assert_eq!(
+            ids[4..],
+            [
+                "protocol.required_headers",
+                "protocol.host",
+                "protocol.method"
+            ]
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert("accept", "*/*".parse().unwrap());
+        let request = |path| {
+            ScreeningRequest::new(path, "Mozilla/5.0")
+                .with_headers(&headers)
+                .with_host("example.com")
+                .with_method("GET")
+        };
+        assert!(screener.check_request(&request("/")).is_none());
+
+        let result = screener
+            .check_request(&request("/").with_host("198.51.100.7:80"))
+            .unwrap();
+        assert_eq!(result.rule_id, "protocol.host");
+        assert_eq!(result.action, RuleAction::RejectOnly);
+        assert!(matches!(
+            &result.reason,
+            ScreeningReason::ProtocolViolation { check: ProtocolCheck::Host, detail }
+                if detail == "IP address host 198.51.100.7"
+        ));
+        assert_eq!(
+            result.reason.to_string(),
+            "protocol violation: host not allowed"
+        );
+
+        // A pattern rule's more severe action wins over the protocol check
+        let result = screener
+            .check_request(&request("/index.php").with_method("PROPFIND"))
+            .unwrap();
+        assert_eq!(result.rule_id, "path_patterns[0]");
+        assert_eq!(
+            result.matched_rules,
+            vec!["path_patterns[0]", "protocol.method"]
+        );
+
+        assert!(screener
+            .check_request(&request("/dav/file").with_method("PROPFIND"))
+            .is_none());
+
+        let score_without_scoring = ScreeningConfig::new().with_protocol_checks(
+            ProtocolChecks::new()
+                .with_max_headers(10)
+                .with_action(RuleAction::Score(2)),
+        );
+        assert!(score_without_scoring.validate().is_err());
+    }
+
+    #[test]
+    fn test_new_validates_config() {
+        let err = RequestScreener::new(
+            &ScreeningConfig::new()
+                .with_protocol_checks(ProtocolChecks::new().with_allowed_method("GET POST")),
+        )
+        .err()
+        .unwrap();
+        assert!(err.to_string().contains("protocol.allowed_methods[0]"));
+
+        let err = RequestScreener::new(&ScreeningConfig::new().with_path_pattern("(unclosed"))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("path_patterns[0]"));
     }
 }
@@ -0,0 +1,133 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! One place for the limiter's telemetry, whichever backends are enabled.
+//!
+//! `Telemetry` fans each signal out to the `metrics`, `metrics-facade` and
+//! `otel` backends, so request paths record it with a single call. With none
+//! of those features enabled every method is a no-op.
+
+// Each backend uses a different subset of the arguments, so some go unused
+// depending on which features are enabled
+#![allow(unused_variables)]
+
+#[cfg(feature = "metrics")]
+use crate::metrics::{MetricsSink, PrometheusMetrics};
+#[cfg(feature = "otel")]
+use crate::otel::OtelSink;
+use std::time::Instant;
+
+#[derive(Clone, Default)]
+pub(crate) struct Telemetry {
+    #[cfg(feature = "metrics")]
+    metrics: MetricsSink,
+    #[cfg(feature = "otel")]
+    otel: OtelSink,
+}
+
+impl Telemetry {
+    /// Returns `false` if metrics were already attached.
+    #[cfg(feature = "metrics")]
+    pub(crate) fn attach_metrics(&self, metrics: PrometheusMetrics) -> bool {
+        self.metrics.attach(metrics)
+    }
+
+    /// Returns `false` if instruments were already created.
+    #[cfg(feature = "otel")]
+    pub(crate) fn attach_otel(&self, meter: &opentelemetry::metrics::Meter) -> bool {
+        self.otel.attach(meter)
+    }
+
+    /// The Prometheus sink, for components that record their own counters.
+    #[cfg(feature = "metrics")]
+    pub(crate) fn metrics(&self) -> &MetricsSink {
+        &self.metrics
+    }
+
+    pub(crate) fn record_block(&self, key: &str) {
+        #[cfg(feature = "metrics")]
+        self.metrics.record_block(key);
+        #[cfg(feature = "metrics-facade")]
+        crate::metrics_facade::record_block();
+        #[cfg(feature = "otel")]
+        self.otel.metrics().record_block();
+    }
+
+    /// Labeled by `rule_id` on every backend; the reason text can hold
+    /// request details or scores, so it stays in logs and events.
+    pub(crate) fn record_screening_block(&self, key: &str, rule_id: &str) {
+        #[cfg(feature = "metrics")]
+        self.metrics.record_screening_block(key, rule_id);
+        #[cfg(feature = "metrics-facade")]
+        crate::metrics_facade::record_screening_block(rule_id);
+        #[cfg(feature = "otel")]
+        self.otel.metrics().record_screening_block(rule_id);
+    }
+
+    pub(crate) fn record_cache_refund(&self, key: &str) {
+        #[cfg(feature = "metrics")]
+        self.metrics.record_cache_refund(key);
+        #[cfg(feature = "metrics-facade")]
+        crate::metrics_facade::record_cache_refund();
+        #[cfg(feature = "otel")]
+        self.otel.metrics().record_cache_refund();
+    }
+
+    pub(crate) fn record_error_penalty(&self, key: &str, status: u16) {
+        #[cfg(feature = "metrics")]
+        self.metrics.record_error_penalty(key, status);
+        #[cfg(feature = "metrics-facade")]
+        crate::metrics_facade::record_error_penalty(status);
+        #[cfg(feature = "otel")]
+        self.otel.metrics().record_error_penalty(status);
+    }
+
+    /// Record a response with the time elapsed since `started`.
+    pub(crate) fn record_http_request(&self, status: u16, started: Instant) {
+        #[cfg(any(feature = "metrics", feature = "metrics-facade", feature = "otel"))]
+        let duration = started.elapsed().as_secs_f64();
+        #[cfg(feature = "metrics")]
+        self.metrics.record_http_request(status, duration);
+        #[cfg(feature = "metrics-facade")]
+        crate::metrics_facade::record_http_request(status, duration);
+        #[cfg(feature = "otel")]
+        self.otel.metrics().record_http_request(status, duration);
+    }
+
+    pub(crate) fn record_dry_run(&self, outcome: &str) {
+        #[cfg(feature = "metrics")]
+        self.metrics.record_dry_run(outcome);
+        #[cfg(feature = "metrics-facade")]
+        crate::metrics_facade::record_dry_run(outcome);
+        #[cfg(feature = "otel")]
+        self.otel.metrics().record_dry_run(outcome);
+    }
+
+    #[cfg(any(feature = "metrics", feature = "metrics-facade"))]
+    pub(crate) fn update_cache_stats(&self, cache_size: usize, blocked_keys: usize) {
+        #[cfg(feature = "metrics")]
+        {
+            self.metrics.update_cache_size(cache_size);
+            self.metrics.update_blocked_ips(blocked_keys);
+        }
+        #[cfg(feature = "metrics-facade")]
+        {
+            crate::metrics_facade::update_cache_size(cache_size);
+            crate::metrics_facade::update_blocked_ips(blocked_keys);
+        }
+    }
+}
@@ -20,6 +20,7 @@ use crate::{
     limiter::RateLimiter,
     types::{NoOpOnBlocked, SecurityContext},
 };
+use std::sync::Arc;
 use std::time::Duration;
 
 #[tokio::test]
@@ -505,3 +506,1439 @@ async fn test_auth_refund_callback_idempotent() {
         );
     }
 }
+
+#[tokio::test]
+async fn test_dry_run_limiter_passes_requests_through() {
+    // A dry-run limiter tracks the bucket exactly like an enforcing one but
+    // never rejects. The shadow state still reports the key as blocked.
+    use crate::{context::security_context_middleware, middleware::rate_limit_middleware};
+    use axum::{
+        extract::connect_info::MockConnectInfo, http::StatusCode, middleware::from_fn_with_state,
+        routing::get, Router,
+    };
+    use axum_test::TestServer;
+    use std::net::SocketAddr;
+
+    let config = RateLimitConfig::new(5, Duration::from_secs(60))
+        .with_grace_period(0)
+        .with_dry_run(true);
+    let limiter = RateLimiter::new(config, NoOpOnBlocked);
+
+    let socket_addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+    let app = Router::new()
+        .route("/", get(|| async { "OK" }))
+        .layer(from_fn_with_state(limiter.clone(), rate_limit_middleware))
+        .layer(axum::middleware::from_fn(security_context_middleware))
+        .layer(MockConnectInfo(socket_addr));
+
+    let server = TestServer::new(app);
+
+    for i in 1..=10 {
+        let resp = server
+            .get("/")
+            .add_header("X-Forwarded-For", "10.9.8.1")
+            .await;
+        assert_eq!(
+            resp.status_code(),
+            StatusCode::OK,
+            "request {} should pass in dry-run mode",
+            i
+        );
+    }
+
+    assert!(
+        limiter.blocked_until("10.9.8.1").is_some(),
+        "shadow bucket should record the block that would have happened"
+    );
+}
+
+#[tokio::test]
+async fn test_dry_run_limiter_does_not_fire_on_blocked() {
+    use crate::types::OnBlocked;
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    struct CountingOnBlocked(Arc<AtomicUsize>);
+
+    #[async_trait::async_trait]
+    impl OnBlocked for CountingOnBlocked {
+        async fn on_blocked(&self, _ip: &str, _path: &str, _context: &SecurityContext) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let config = RateLimitConfig::new(1, Duration::from_secs(60))
+        .with_grace_period(0)
+        .with_dry_run(true);
+    let limiter = RateLimiter::new(config, CountingOnBlocked(calls.clone()));
+    let ctx = SecurityContext::new("192.168.1.9".to_string(), "test-agent".to_string());
+
+    limiter.check_rate_limit("192.168.1.9", &ctx, "/test").await;
+    let (allowed, newly_blocked, _) = limiter.check_rate_limit("192.168.1.9", &ctx, "/test").await;
+    assert!(
+        !allowed && newly_blocked,
+        "decision should still be evaluated"
+    );
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(
+        calls.load(Ordering::SeqCst),
+        0,
+        "dry run must not call on_blocked"
+    );
+}
+
+#[tokio::test]
+async fn test_dry_run_blocks_are_not_enforced_after_dry_run_ends() {
+    let events = RecordingOnBlocked::default();
+    let config = RateLimitConfig::new(1, Duration::from_secs(60))
+        .with_grace_period(0)
+        .with_dry_run(true);
+    let limiter = RateLimiter::new(config.clone(), events.clone());
+    let ctx = SecurityContext::new("192.168.1.10".to_string(), "test-agent".to_string());
+
+    limiter
+        .check_rate_limit("192.168.1.10", &ctx, "/test")
+        .await;
+    let (_, newly_blocked, _) = limiter
+        .check_rate_limit("192.168.1.10", &ctx, "/test")
+        .await;
+    assert!(newly_blocked);
+    assert!(limiter.blocked_until("192.168.1.10").is_some());
+    assert_eq!(
+        limiter.strikes("192.168.1.10"),
+        0,
+        "shadow blocks add no strike"
+    );
+
+    // The shadow block is dropped, not enforced unreported
+    limiter.update_config(config.with_dry_run(false));
+    assert!(limiter.blocked_until("192.168.1.10").is_none());
+    assert_eq!(limiter.get_cache_stats().1, 0);
+
+    // The bucket is still empty, so the next request starts a real, reported block
+    let (allowed, newly_blocked, _) = limiter
+        .check_rate_limit("192.168.1.10", &ctx, "/test")
+        .await;
+    assert!(!allowed && newly_blocked);
+    assert_eq!(limiter.strikes("192.168.1.10"), 1);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(events.blocked.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn test_dry_run_screener_does_not_block() {
+    use crate::{
+        context::security_context_middleware,
+        middleware::rate_limit_middleware,
+        screener::{RequestScreener, ScreeningConfig},
+    };
+    use axum::{
+        extract::connect_info::MockConnectInfo, http::StatusCode, middleware::from_fn_with_state,
+        routing::get, Router,
+    };
+    use axum_test::TestServer;
+    use std::net::SocketAddr;
+
+    let config = RateLimitConfig::new(50, Duration::from_secs(60)).with_grace_period(0);
+    let screener = RequestScreener::new(
+        &ScreeningConfig::new()
+            .with_path_pattern(r"\.php$")
+            .with_dry_run(true),
+    )
+    .unwrap();
+    let limiter = RateLimiter::new(config, NoOpOnBlocked).with_screener(screener);
+
+    let socket_addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+    let app = Router::new()
+        .route("/index.php", get(|| async { "OK" }))
+        .route("/", get(|| async { "OK" }))
+        .layer(from_fn_with_state(limiter.clone(), rate_limit_middleware))
+        .layer(axum::middleware::from_fn(security_context_middleware))
+        .layer(MockConnectInfo(socket_addr));
+
+    let server = TestServer::new(app);
+
+    let resp = server
+        .get("/index.php")
+        .add_header("X-Forwarded-For", "10.9.8.2")
+        .await;
+    assert_eq!(resp.status_code(), StatusCode::OK);
+
+    let resp = server
+        .get("/")
+        .add_header("X-Forwarded-For", "10.9.8.2")
+        .await;
+    assert_eq!(resp.status_code(), StatusCode::OK);
+    assert!(limiter.blocked_until("10.9.8.2").is_none());
+}
+
+#[tokio::test]
+async fn test_update_config_keeps_bucket_state() {
+    let config = RateLimitConfig::new(3, Duration::from_secs(60)).with_grace_period(0);
+    let limiter = RateLimiter::new(config, NoOpOnBlocked);
+    let in_flight = limiter.clone();
+    let ctx = SecurityContext::new("192.168.1.10".to_string(), "test-agent".to_string());
+
+    for _ in 1..=3 {
+        limiter
+            .check_rate_limit("192.168.1.10", &ctx, "/test")
+            .await;
+    }
+    let (allowed, _, _) = limiter
+        .check_rate_limit("192.168.1.10", &ctx, "/test")
+        .await;
+    assert!(!allowed, "should be blocked before the update");
+
+    limiter.update_config(RateLimitConfig::new(100, Duration::from_secs(60)).with_grace_period(0));
+
+    assert_eq!(in_flight.config().rate_limit_per_minute, 100);
+    let (allowed, _, _) = in_flight
+        .check_rate_limit("192.168.1.10", &ctx, "/test")
+        .await;
+    assert!(!allowed, "existing block must survive a config update");
+
+    let (allowed, _, tokens) = in_flight
+        .check_rate_limit("192.168.1.11", &ctx, "/test")
+        .await;
+    assert!(allowed);
+    assert_eq!(tokens, 99.0, "new buckets use the updated capacity");
+}
+
+#[test]
+fn test_config_validate_rejects_out_of_range_values() {
+    use crate::config::ConfigError;
+
+    assert!(RateLimitConfig::default().validate().is_ok());
+
+    let config = RateLimitConfig {
+        cache_refund_ratio: 1.5,
+        ..Default::default()
+    };
+    assert!(matches!(
+        config.validate(),
+        Err(ConfigError::InvalidField { field, .. }) if field == "cache_refund_ratio"
+    ));
+
+    let config = RateLimitConfig::new(0, Duration::from_secs(60));
+    assert!(matches!(
+        config.validate(),
+        Err(ConfigError::InvalidField { field, .. }) if field == "rate_limit_per_minute"
+    ));
+
+    // Parses fine, but is longer than any block expiry chrono can represent
+    let result = RateLimitConfig::from_env_with("APP_", |name| {
+        (name == "APP_BLOCK_DURATION").then(|| "100000000d".to_string())
+    });
+    assert!(matches!(
+        result,
+        Err(ConfigError::InvalidField { field, .. }) if field == "APP_BLOCK_DURATION"
+    ));
+}
+
+#[test]
+fn test_unvalidated_huge_block_duration_saturates() {
+    // Configs built in code skip validation; blocking must still not panic
+    let config = RateLimitConfig::new(1, Duration::MAX).with_grace_period(0);
+    let limiter = RateLimiter::new(config, NoOpOnBlocked);
+    limiter.block_immediately("192.168.9.1");
+    assert_eq!(
+        limiter.blocked_until("192.168.9.1"),
+        Some(chrono::DateTime::<chrono::Utc>::MAX_UTC)
+    );
+    limiter.cleanup_cache();
+    assert!(limiter.blocked_until("192.168.9.1").is_some());
+}
+
+#[test]
+fn test_config_from_env() {
+    use crate::config::ConfigError;
+    use std::collections::HashMap;
+
+    let vars: HashMap<&str, &str> = [
+        ("APP_RATE_LIMIT_PER_MINUTE", "120"),
+        ("APP_BLOCK_DURATION", "1h30m"),
+        ("APP_DRY_RUN", "true"),
+    ]
+    .into_iter()
+    .collect();
+    let config =
+        RateLimitConfig::from_env_with("APP_", |name| vars.get(name).map(|v| v.to_string()))
+            .unwrap();
+    assert_eq!(config.rate_limit_per_minute, 120);
+    assert_eq!(config.block_duration, Duration::from_secs(5400));
+    assert!(config.dry_run);
+    assert_eq!(config.grace_period_seconds, 1, "unset values use defaults");
+
+    let result = RateLimitConfig::from_env_with("APP_", |name| {
+        (name == "APP_AUTH_REFUND_RATIO").then(|| "2.0".to_string())
+    });
+    assert_eq!(
+        result.unwrap_err(),
+        ConfigError::InvalidField {
+            field: "APP_AUTH_REFUND_RATIO".to_string(),
+            message: "must be between 0.0 and 1.0, got 2".to_string(),
+        }
+    );
+
+    let result = RateLimitConfig::from_env_with("APP_", |name| {
+        (name == "APP_BLOCK_DURATION").then(|| "soon".to_string())
+    });
+    assert!(matches!(
+        result,
+        Err(ConfigError::InvalidField { field, .. }) if field == "APP_BLOCK_DURATION"
+    ));
+}
+
+#[test]
+fn test_screening_and_security_context_from_env() {
+    use crate::{
+        config::ConfigError,
+        context::{IpExtractionStrategy, SecurityContextConfig},
+        screener::ScreeningConfig,
+    };
+
+    let config = ScreeningConfig::from_env_with("APP_", |name| match name {
+        "APP_PATH_PATTERNS" => Some("\\.php$\n/\\.git/\n".to_string()),
+        "APP_USER_AGENT_PATTERNS" => Some("zgrab".to_string()),
+        "APP_QUERY_PATTERNS" => Some("\\.\\./".to_string()),
+        "APP_HEADER_PATTERNS" => Some("Referer: <script\nX-Debug: .".to_string()),
+        _ => None,
+    })
+    .unwrap();
+    assert_eq!(config.path_patterns, vec![r"\.php$", r"/\.git/"]);
+    assert_eq!(config.user_agent_patterns, vec!["zgrab"]);
+    assert_eq!(config.query_patterns, vec![r"\.\./"]);
+    assert_eq!(config.header_patterns["referer"], vec!["<script"]);
+    assert_eq!(config.header_patterns["x-debug"], vec!["."]);
+
+    let config = ScreeningConfig::from_env_with("APP_", |name| match name {
+        "APP_REQUIRED_HEADERS" => Some("user-agent, accept".to_string()),
+        "APP_ALLOWED_METHODS" => Some("GET,POST".to_string()),
+        "APP_REJECT_IP_HOST" => Some("true".to_string()),
+        "APP_MAX_HEADERS" => Some("50".to_string()),
+        _ => None,
+    })
+    .unwrap();
+    assert_eq!(
+        config.protocol.required_headers,
+        vec!["user-agent", "accept"]
+    );
+    assert_eq!(config.protocol.allowed_methods, vec!["GET", "POST"]);
+    assert!(config.protocol.reject_ip_host);
+    assert_eq!(config.protocol.max_headers, Some(50));
+
+    let result = ScreeningConfig::from_env_with("APP_", |name| {
+        (name == "APP_ALLOWED_METHODS").then(|| "GET,NOT A METHOD".to_string())
+    });
+    assert!(matches!(
+        result,
+        Err(ConfigError::InvalidField { field, .. }) if field == "APP_ALLOWED_METHODS[1]"
+    ));
+
+    let result = ScreeningConfig::from_env_with("APP_", |name| {
+        (name == "APP_PATH_PATTERNS").then(|| "ok\n(unclosed".to_string())
+    });
+    assert!(matches!(
+        result,
+        Err(ConfigError::InvalidField { field, .. }) if field == "APP_PATH_PATTERNS[1]"
+    ));
+
+    let config = SecurityContextConfig::from_env_with("APP_", |name| match name {
+        "APP_IP_EXTRACTION" => Some("CF-Connecting-IP".to_string()),
+        _ => None,
+    })
+    .unwrap();
+    assert!(matches!(
+        config.ip_extraction,
+        IpExtractionStrategy::ForwardedHeader { ref header_name, proxy_depth: 1 }
+            if header_name == "CF-Connecting-IP"
+    ));
+
+    let result = SecurityContextConfig::from_env_with("APP_", |name| match name {
+        "APP_IP_EXTRACTION" => Some("X-Forwarded-For".to_string()),
+        "APP_PROXY_DEPTH" => Some("0".to_string()),
+        _ => None,
+    });
+    assert!(matches!(
+        result,
+        Err(ConfigError::InvalidField { field, .. }) if field == "APP_PROXY_DEPTH"
+    ));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_config_from_toml_str() {
+    use crate::{
+        config::ConfigError,
+        context::{IpExtractionStrategy, SecurityContextConfig},
+        reload::ReloadedConfig,
+    };
+
+    let config = RateLimitConfig::from_toml_str(
+        r#"
+        rate_limit_per_minute = 30
+        block_duration = "15m"
+        error_penalty_tokens = 1.0
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.rate_limit_per_minute, 30);
+    assert_eq!(config.block_duration, Duration::from_secs(900));
+    assert_eq!(config.error_penalty_tokens, 1.0);
+    assert_eq!(config.cache_refund_ratio, 0.5);
+
+    assert!(matches!(
+        RateLimitConfig::from_toml_str("rate_limit_per_mintue = 30"),
+        Err(ConfigError::Parse { .. })
+    ));
+
+    let config = SecurityContextConfig::from_toml_str(
+        r#"ip_extraction = { strategy = "forwarded_header", header_name = "X-Real-IP", proxy_depth = 1 }"#,
+    )
+    .unwrap();
+    assert!(matches!(
+        config.ip_extraction,
+        IpExtractionStrategy::ForwardedHeader { ref header_name, .. } if header_name == "X-Real-IP"
+    ));
+    let config =
+        SecurityContextConfig::from_toml_str(r#"ip_extraction = { strategy = "socket_addr" }"#)
+            .unwrap();
+    assert!(matches!(
+        config.ip_extraction,
+        IpExtractionStrategy::SocketAddr
+    ));
+
+    let result = ReloadedConfig::from_toml_str(
+        r#"
+        [rate_limit]
+        rate_limit_per_minute = 30
+
+        [screening]
+        path_patterns = ['\.php$', '(unclosed']
+        "#,
+    );
+    assert!(matches!(
+        result,
+        Err(ConfigError::InvalidField { field, .. }) if field == "screening.path_patterns[1]"
+    ));
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_config_serializes_human_friendly_durations() {
+    let config = RateLimitConfig::new(30, Duration::from_secs(2 * 60 * 60));
+    let toml = toml::to_string(&config).unwrap();
+    assert!(toml.contains(r#"block_duration = "2h""#), "got: {}", toml);
+    let parsed = RateLimitConfig::from_toml_str(&toml).unwrap();
+    assert_eq!(parsed.block_duration, config.block_duration);
+}
+
+/// Reports an action as recently performed when its name is in `recent`.
+struct FixedActionChecker {
+    recent: Vec<&'static str>,
+}
+
+#[async_trait::async_trait]
+impl crate::types::ActionChecker for FixedActionChecker {
+    async fn check_recent_action(
+        &self,
+        _ip: &str,
+        action: &str,
+        _within: Duration,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(self.recent.contains(&action))
+    }
+}
+
+#[tokio::test]
+async fn test_action_limit_middleware_returns_429_with_action_name() {
+    use crate::{
+        action::{action_limit_middleware, ActionLimit},
+        context::security_context_middleware,
+        middleware::rate_limit_middleware,
+    };
+    use axum::{
+        extract::connect_info::MockConnectInfo,
+        http::StatusCode,
+        middleware::{from_fn, from_fn_with_state},
+        routing::post,
+        Router,
+    };
+    use axum_test::TestServer;
+    use std::net::SocketAddr;
+
+    let config = RateLimitConfig::new(50, Duration::from_secs(60)).with_grace_period(0);
+    let limiter = RateLimiter::new(config, NoOpOnBlocked).with_action_checker(FixedActionChecker {
+        recent: vec!["password-reset"],
+    });
+
+    let socket_addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+    let app = Router::new()
+        .route(
+            "/password-reset",
+            post(|| async { "sent" }).route_layer(from_fn_with_state(
+                ActionLimit::new("password-reset", Duration::from_secs(600)),
+                action_limit_middleware,
+            )),
+        )
+        .route(
+            "/signup",
+            post(|| async { "created" }).route_layer(from_fn_with_state(
+                ActionLimit::new("signup", Duration::from_secs(600)),
+                action_limit_middleware,
+            )),
+        )
+        .layer(from_fn_with_state(limiter, rate_limit_middleware))
+        .layer(from_fn(security_context_middleware))
+        .layer(MockConnectInfo(socket_addr));
+
+    let server = TestServer::new(app);
+
+    let resp = server
+        .post("/password-reset")
+        .add_header("X-Forwarded-For", "10.9.7.1")
+        .await;
+    assert_eq!(resp.status_code(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(resp.text().contains("password-reset"));
+
+    let resp = server
+        .post("/signup")
+        .add_header("X-Forwarded-For", "10.9.7.1")
+        .await;
+    assert_eq!(resp.status_code(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_action_guard_extractor() {
+    use crate::{
+        action::ActionGuard, context::security_context_middleware,
+        middleware::rate_limit_middleware,
+    };
+    use axum::{
+        extract::{connect_info::MockConnectInfo, Path},
+        http::StatusCode,
+        middleware::{from_fn, from_fn_with_state},
+        response::IntoResponse,
+        routing::post,
+        Router,
+    };
+    use axum_test::TestServer;
+    use std::net::SocketAddr;
+
+    let config = RateLimitConfig::new(50, Duration::from_secs(60)).with_grace_period(0);
+    let limiter = RateLimiter::new(config, NoOpOnBlocked).with_action_checker(FixedActionChecker {
+        recent: vec!["invite"],
+    });
+
+    let socket_addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+    let app = Router::new()
+        .route(
+            "/actions/{action}",
+            post(
+                |guard: ActionGuard, Path(action): Path<String>| async move {
+                    match guard.check(&action, Duration::from_secs(60)).await {
+                        Ok(()) => "done".into_response(),
+                        Err(limited) => limited.into_response(),
+                    }
+                },
+            ),
+        )
+        .layer(from_fn_with_state(limiter, rate_limit_middleware))
+        .layer(from_fn(security_context_middleware))
+        .layer(MockConnectInfo(socket_addr));
+
+    let server = TestServer::new(app);
+
+    let resp = server
+        .post("/actions/invite")
+        .add_header("X-Forwarded-For", "10.9.7.2")
+        .await;
+    assert_eq!(resp.status_code(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(resp.text(), "Too many requests for action: invite");
+
+    let resp = server
+        .post("/actions/comment")
+        .add_header("X-Forwarded-For", "10.9.7.2")
+        .await;
+    assert_eq!(resp.status_code(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_in_memory_action_checker_enforces_cooldown() {
+    // action_limit_middleware records successful actions, so the second
+    // password reset inside the cooldown is rejected.
+    use crate::{
+        action::{action_limit_middleware, ActionLimit, InMemoryActionChecker},
+        context::security_context_middleware,
+        middleware::rate_limit_middleware,
+    };
+    use axum::{
+        extract::connect_info::MockConnectInfo,
+        http::StatusCode,
+        middleware::{from_fn, from_fn_with_state},
+        routing::post,
+        Router,
+    };
+    use axum_test::TestServer;
+    use std::net::SocketAddr;
+
+    let checker = InMemoryActionChecker::new(Duration::from_secs(600));
+    let config = RateLimitConfig::new(50, Duration::from_secs(60)).with_grace_period(0);
+    let limiter = RateLimiter::new(config, NoOpOnBlocked).with_action_checker(checker.clone());
+
+    let socket_addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+    let app = Router::new()
+        .route(
+            "/password-reset",
+            post(|| async { "sent" }).route_layer(from_fn_with_state(
+                ActionLimit::new("password-reset", Duration::from_secs(600)),
+                action_limit_middleware,
+            )),
+        )
+        .layer(from_fn_with_state(limiter, rate_limit_middleware))
+        .layer(from_fn(security_context_middleware))
+        .layer(MockConnectInfo(socket_addr));
+
+    let server = TestServer::new(app);
+
+    let resp = server
+        .post("/password-reset")
+        .add_header("X-Forwarded-For", "10.9.6.1")
+        .await;
+    assert_eq!(resp.status_code(), StatusCode::OK);
+    assert!(checker.performed_within("10.9.6.1", "password-reset", Duration::from_secs(600)));
+
+    let resp = server
+        .post("/password-reset")
+        .add_header("X-Forwarded-For", "10.9.6.1")
+        .await;
+    assert_eq!(resp.status_code(), StatusCode::TOO_MANY_REQUESTS);
+
+    let resp = server
+        .post("/password-reset")
+        .add_header("X-Forwarded-For", "10.9.6.2")
+        .await;
+    assert_eq!(
+        resp.status_code(),
+        StatusCode::OK,
+        "other IPs are unaffected"
+    );
+}
+
+#[tokio::test]
+async fn test_concurrent_actions_reserve_cooldown_once() {
+    use crate::{
+        action::{action_limit_middleware, ActionLimit, InMemoryActionChecker},
+        context::security_context_middleware,
+        middleware::rate_limit_middleware,
+    };
+    use axum::{
+        extract::connect_info::MockConnectInfo,
+        http::StatusCode,
+        middleware::{from_fn, from_fn_with_state},
+        routing::post,
+        Router,
+    };
+    use axum_test::TestServer;
+    use std::net::SocketAddr;
+
+    let checker = InMemoryActionChecker::new(Duration::from_secs(600));
+    let config = RateLimitConfig::new(50, Duration::from_secs(60)).with_grace_period(0);
+    let limiter = RateLimiter::new(config, NoOpOnBlocked).with_action_checker(checker.clone());
+
+    let socket_addr: SocketAddr = "127.0.0.1:8080".parse().unwrap();
+    let limit = ActionLimit::new("password-reset", Duration::from_secs(600));
+    let app = Router::new()
+        .route(
+            "/password-reset",
+            post(|| async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                "sent"
+            })
+            .route_layer(from_fn_with_state(limit.clone(), action_limit_middleware)),
+        )
+        .route(
+            "/failing-reset",
+            post(|| async { StatusCode::INTERNAL_SERVER_ERROR })
+                .route_layer(from_fn_with_state(limit, action_limit_middleware)),
+        )
+        .layer(from_fn_with_state(limiter, rate_limit_middleware))
+        .layer(from_fn(security_context_middleware))
+        .layer(MockConnectInfo(socket_addr));
+
+    let server = TestServer::new(app);
+
+    let (first, second) = tokio::join!(
+        server
+            .post("/password-reset")
+            .add_header("X-Forwarded-For", "10.9.7.1"),
+        server
+            .post("/password-reset")
+            .add_header("X-Forwarded-For", "10.9.7.1"),
+    );
+    let mut statuses = vec![first.status_code(), second.status_code()];
+    statuses.sort();
+    assert_eq!(
+        statuses,
+        vec![StatusCode::OK, StatusCode::TOO_MANY_REQUESTS],
+        "only one concurrent request may perform the action"
+    );
+
+    // A failed action releases its reservation
+    server
+        .post("/failing-reset")
+        .add_header("X-Forwarded-For", "10.9.7.2")
+        .await;
+    assert!(!checker.performed_within("10.9.7.2", "password-reset", Duration::from_secs(600)));
+    server
+        .post("/password-reset")
+        .add_header("X-Forwarded-For", "10.9.7.2")
+        .await
+        .assert_status_ok();
+}
+
+#[derive(Clone, Default)]
+struct RecordingOnBlocked {
+    blocked: Arc<std::sync::Mutex<Vec<crate::types::BlockEvent>>>,
+    unblocked: Arc<std::sync::Mutex<Vec<crate::types::UnblockEvent>>>,
+    expired: Arc<std::sync::Mutex<Vec<crate::types::UnblockEvent>>>,
+}
+
+#[async_trait::async_trait]
+impl crate::types::OnBlocked for RecordingOnBlocked {
+    async fn on_block_event(&self, event: &crate::types::BlockEvent) {
+        self.blocked.lock().unwrap().push(event.clone());
+    }
+
+    async fn on_unblocked(&self, event: &crate::types::UnblockEvent) {
+        self.unblocked.lock().unwrap().push(event.clone());
+    }
+
+    async fn on_expired(&self, event: &crate::types::UnblockEvent) {
+        self.expired.lock().unwrap().push(event.clone());
+    }
+}
+
+#[tokio::test]
+async fn test_block_event_for_token_exhaustion() {
+    use crate::types::BlockReason;
+
+    let events = RecordingOnBlocked::default();
+    let config = RateLimitConfig::new(1, Duration::from_secs(60)).with_grace_period(0);
+    let limiter = RateLimiter::new(config, events.clone());
+    let ctx = SecurityContext::new("192.168.2.1".to_string(), "test-agent".to_string());
+
+    limiter.check_rate_limit("192.168.2.1", &ctx, "/a").await;
+    limiter.check_rate_limit("192.168.2.1", &ctx, "/b").await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let blocked = events.blocked.lock().unwrap();
+    assert_eq!(blocked.len(), 1);
+    assert_eq!(blocked[0].reason, BlockReason::TokenExhaustion);
+    assert_eq!(blocked[0].path, "/b");
+    assert_eq!(blocked[0].strikes, 1);
+    assert!(blocked[0].matched_pattern.is_none());
+    assert_eq!(limiter.strikes("192.168.2.1"), 1);
+}
+
+#[tokio::test]
+async fn test_block_event_for_screening() {
+    use crate::{
+        context::security_context_middleware,
+        middleware::rate_limit_middleware,
+        screener::{RequestScreener, ScreeningConfig},
+        types::BlockReason,
+    };
+    use axum::{
+        http::StatusCode,
+        middleware::{from_fn, from_fn_with_state},
+        routing::get,
+        Router,
+    };
+    use axum_test::TestServer;
+    use std::net::SocketAddr;
+
+    let events = RecordingOnBlocked::default();
+    let config = RateLimitConfig::new(50, Duration::from_secs(60)).with_grace_period(0);
+    let screener =
+        RequestScreener::new(&ScreeningConfig::new().with_path_pattern(r"\.php$")).unwrap();
+    let limiter = RateLimiter::new(config, events.clone()).with_screener(screener);
+
+    let app = Router::new()
+        .route("/{*path}", get(|| async { "OK" }))
+        .layer(from_fn_with_state(limiter, rate_limit_middleware))
+        .layer(from_fn(security_context_middleware))
+        .layer(axum::extract::connect_info::MockConnectInfo(
+            SocketAddr::from(([127, 0, 0, 1], 8080)),
+        ));
+    let server = TestServer::new(app);
+
+    let response = server
+        .get("/wp-login.php")
+        .add_header("X-Forwarded-For", "192.168.2.2")
+        .await;
+    assert_eq!(response.status_code(), StatusCode::IM_A_TEAPOT);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let blocked = events.blocked.lock().unwrap();
+    assert_eq!(blocked.len(), 1);
+    assert_eq!(blocked[0].reason, BlockReason::Screening);
+    assert_eq!(blocked[0].matched_pattern.as_deref(), Some(r"\.php$"));
+    assert_eq!(blocked[0].path, "/wp-login.php");
+    assert_eq!(blocked[0].context.ip_address, "192.168.2.2");
+}
+
+#[tokio::test]
+async fn test_unblock_fires_on_unblocked() {
+    use crate::types::BlockReason;
+
+    let events = RecordingOnBlocked::default();
+    let config = RateLimitConfig::new(5, Duration::from_secs(60)).with_grace_period(0);
+    let limiter = RateLimiter::new(config, events.clone());
+    let ctx = SecurityContext::new("192.168.2.3".to_string(), "test-agent".to_string());
+
+    assert!(!limiter.unblock("192.168.2.3"), "nothing to unblock yet");
+
+    limiter.block_immediately("192.168.2.3");
+    let (allowed, _, _) = limiter.check_rate_limit("192.168.2.3", &ctx, "/").await;
+    assert!(!allowed);
+
+    assert!(limiter.unblock("192.168.2.3"));
+    let (allowed, _, tokens) = limiter.check_rate_limit("192.168.2.3", &ctx, "/").await;
+    assert!(allowed, "unblocked key should be allowed again");
+    assert_eq!(tokens, 4.0, "bucket should be refilled on unblock");
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    assert_eq!(
+        events.blocked.lock().unwrap()[0].reason,
+        BlockReason::Manual
+    );
+    let unblocked = events.unblocked.lock().unwrap();
+    assert_eq!(unblocked.len(), 1);
+    assert_eq!(unblocked[0].reason, Some(BlockReason::Manual));
+    assert!(events.expired.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_block_immediately_with_context_reports_request() {
+    use crate::types::BlockReason;
+
+    let events = RecordingOnBlocked::default();
+    let config = RateLimitConfig::new(5, Duration::from_secs(60)).with_grace_period(0);
+    let limiter = RateLimiter::new(config, events.clone());
+    let ctx = SecurityContext::new("192.168.2.8".to_string(), "curl/8.0".to_string());
+
+    limiter.block_immediately_with_context("192.168.2.8", &ctx, "/login");
+    limiter.block_immediately("192.168.2.9");
+    assert!(limiter.blocked_until("192.168.2.8").is_some());
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let mut blocked = events.blocked.lock().unwrap().clone();
+    blocked.sort_by(|a, b| a.key.cmp(&b.key));
+    assert_eq!(blocked[0].reason, BlockReason::Manual);
+    assert_eq!(blocked[0].path, "/login");
+    assert_eq!(blocked[0].context.user_agent, "curl/8.0");
+    assert_eq!(blocked[1].path, "");
+    assert_eq!(blocked[1].context.ip_address, "192.168.2.9");
+    assert_eq!(blocked[1].context.user_agent, "");
+}
+
+#[tokio::test]
+async fn test_expired_block_fires_on_expired_and_can_reblock() {
+    use crate::types::BlockReason;
+
+    let events = RecordingOnBlocked::default();
+    let config = RateLimitConfig::new(60, Duration::from_millis(100)).with_grace_period(0);
+    let limiter = RateLimiter::new(config, events.clone());
+    let ctx = SecurityContext::new("192.168.2.4".to_string(), "test-agent".to_string());
+
+    limiter.block_immediately("192.168.2.4");
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(limiter.blocked_until("192.168.2.4").is_none());
+
+    // The next check notices the expiry; the drained bucket then re-blocks the key
+    let (allowed, newly_blocked, _) = limiter.check_rate_limit("192.168.2.4", &ctx, "/").await;
+    assert!(!allowed && newly_blocked);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let expired = events.expired.lock().unwrap();
+    assert_eq!(expired.len(), 1);
+    assert_eq!(expired[0].reason, Some(BlockReason::Manual));
+
+    let blocked = events.blocked.lock().unwrap();
+    assert_eq!(blocked.len(), 2);
+    assert_eq!(blocked[1].reason, BlockReason::TokenExhaustion);
+    assert_eq!(blocked[1].strikes, 2);
+}
+
+#[tokio::test]
+async fn test_subscribe_receives_middleware_events() {
+    use crate::{
+        context::security_context_middleware, events::LimiterEvent,
+        middleware::rate_limit_middleware,
+    };
+    use axum::{
+        middleware::{from_fn, from_fn_with_state},
+        routing::get,
+        Router,
+    };
+    use axum_test::TestServer;
+    use std::net::SocketAddr;
+
+    let config = RateLimitConfig::new(3, Duration::from_secs(60))
+        .with_grace_period(0)
+        .with_error_penalty(1.0);
+    let limiter = RateLimiter::new(config, NoOpOnBlocked);
+    let mut dashboard = limiter.subscribe();
+    let mut detector = limiter.subscribe();
+
+    let app = Router::new()
+        .route("/", get(|| async { "OK" }))
+        .layer(from_fn_with_state(limiter, rate_limit_middleware))
+        .layer(from_fn(security_context_middleware))
+        .layer(axum::extract::connect_info::MockConnectInfo(
+            SocketAddr::from(([127, 0, 0, 1], 8080)),
+        ));
+    let server = TestServer::new(app);
+
+    for path in ["/", "/missing", "/"] {
+        server
+            .get(path)
+            .add_header("X-Forwarded-For", "192.168.3.1")
+            .await;
+    }
+
+    let expected = vec![
+        LimiterEvent::Allowed {
+            key: "192.168.3.1".to_string(),
+            path: "/".to_string(),
+            tokens: 2.0,
+        },
+        LimiterEvent::Allowed {
+            key: "192.168.3.1".to_string(),
+            path: "/missing".to_string(),
+            tokens: 1.0,
+        },
+        LimiterEvent::Penalized {
+            key: "192.168.3.1".to_string(),
+            path: "/missing".to_string(),
+            status: 404,
+            amount: 1.0,
+            tokens: 0.0,
+        },
+        LimiterEvent::Blocked {
+            key: "192.168.3.1".to_string(),
+            path: "/".to_string(),
+            newly_blocked: true,
+            dry_run: false,
+        },
+    ];
+    for receiver in [&mut dashboard, &mut detector] {
+        let received: Vec<_> = std::iter::from_fn(|| receiver.try_recv()).collect();
+        assert_eq!(received, expected);
+        assert_eq!(receiver.lagged(), 0);
+    }
+}
+
+#[tokio::test]
+async fn test_heavy_hitters_fed_from_middleware() {
+    use crate::{
+        context::security_context_middleware,
+        heavy_hitters::{HeavyHitterConfig, HeavyHitterKind},
+        middleware::rate_limit_middleware,
+        screener::{RequestScreener, ScreeningConfig},
+    };
+    use axum::{
+        middleware::{from_fn, from_fn_with_state},
+        routing::get,
+        Router,
+    };
+    use axum_test::TestServer;
+    use std::net::SocketAddr;
+
+    let config = RateLimitConfig::new(100, Duration::from_secs(60))
+        .with_grace_period(0)
+        .with_error_penalty(1.0);
+    let screener =
+        RequestScreener::new(&ScreeningConfig::new().with_path_pattern(r"\.env$")).unwrap();
+    let limiter = RateLimiter::new(config, NoOpOnBlocked)
+        .with_screener(screener)
+        .with_heavy_hitters(HeavyHitterConfig::new().with_capacity(16))
+        .unwrap();
+
+    let app = Router::new()
+        .route("/", get(|| async { "OK" }))
+        .layer(from_fn_with_state(limiter.clone(), rate_limit_middleware))
+        .layer(from_fn(security_context_middleware))
+        .layer(axum::extract::connect_info::MockConnectInfo(
+            SocketAddr::from(([127, 0, 0, 1], 8080)),
+        ));
+    let server = TestServer::new(app);
+
+    for (ip, path) in [
+        ("192.168.4.1", "/"),
+        ("192.168.4.1", "/missing"),
+        ("192.168.4.1", "/"),
+        ("192.168.4.2", "/.env"),
+    ] {
+        server.get(path).add_header("X-Forwarded-For", ip).await;
+    }
+
+    let requests = limiter.top_offenders(HeavyHitterKind::Requests, 10);
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].key, "192.168.4.1");
+    assert_eq!(requests[0].count, 3);
+
+    let penalties = limiter.top_offenders(HeavyHitterKind::Penalties, 10);
+    assert_eq!(penalties.len(), 1);
+    assert_eq!(penalties[0].key, "192.168.4.1");
+
+    let screened = limiter.top_offenders(HeavyHitterKind::ScreeningHits, 10);
+    assert_eq!(screened.len(), 1);
+    assert_eq!(screened[0].key, "192.168.4.2");
+}
+
+#[cfg(feature = "serde")]
+#[tokio::test]
+async fn test_top_offenders_handler() {
+    use crate::{heavy_hitters::HeavyHitterConfig, routes::top_offenders_handler};
+    use axum::{http::StatusCode, routing::get, Router};
+    use axum_test::TestServer;
+
+    let config = RateLimitConfig::new(10, Duration::from_secs(60));
+    let disabled = RateLimiter::new(config.clone(), NoOpOnBlocked);
+    let server = TestServer::new(
+        Router::new()
+            .route("/top", get(top_offenders_handler::<NoOpOnBlocked>))
+            .with_state(disabled),
+    );
+    server
+        .get("/top")
+        .expect_failure()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    let limiter = RateLimiter::new(config, NoOpOnBlocked)
+        .with_heavy_hitters(HeavyHitterConfig::new())
+        .unwrap();
+    limiter.heavy_hitters().unwrap().record(
+        crate::heavy_hitters::HeavyHitterKind::Requests,
+        "192.168.4.3",
+    );
+
+    let server = TestServer::new(
+        Router::new()
+            .route("/top", get(top_offenders_handler::<NoOpOnBlocked>))
+            .with_state(limiter),
+    );
+    let body: serde_json::Value = server.get("/top?n=5").await.json();
+    assert_eq!(body["window_secs"], 300);
+    assert_eq!(body["requests"][0]["key"], "192.168.4.3");
+    assert_eq!(body["requests"][0]["count"], 1);
+    assert!(body["penalties"].as_array().unwrap().is_empty());
+}
+
+#[cfg(feature = "serde")]
+#[tokio::test]
+async fn test_rule_stats_handler() {
+    use crate::{
+        routes::rule_stats_handler,
+        screener::{RequestScreener, ScreeningConfig, ScreeningRequest},
+    };
+    use axum::{http::StatusCode, routing::get, Router};
+    use axum_test::TestServer;
+
+    let config = RateLimitConfig::new(10, Duration::from_secs(60));
+    let server = TestServer::new(
+        Router::new()
+            .route("/rules", get(rule_stats_handler::<NoOpOnBlocked>))
+            .with_state(RateLimiter::new(config.clone(), NoOpOnBlocked)),
+    );
+    server
+        .get("/rules")
+        .expect_failure()
+        .await
+        .assert_status(StatusCode::NOT_FOUND);
+
+    let screener = RequestScreener::new(
+        &ScreeningConfig::new()
+            .with_path_pattern(r"\.php$")
+            .with_path_pattern(r"/\.git/"),
+    )
+    .unwrap();
+    screener.check_request(&ScreeningRequest::new("/index.php", "curl").with_key("192.168.4.3"));
+    let limiter = RateLimiter::new(config, NoOpOnBlocked).with_screener(screener);
+
+    let server = TestServer::new(
+        Router::new()
+            .route("/rules", get(rule_stats_handler::<NoOpOnBlocked>))
+            .with_state(limiter),
+    );
+    let body: serde_json::Value = server.get("/rules").await.json();
+    assert_eq!(body[0]["rule_id"], "path_patterns[0]");
+    assert_eq!(body[0]["hits"], 1);
+    assert_eq!(body[0]["unique_keys"], 1);
+    assert!(body[0]["last_hit"].is_string());
+    assert_eq!(body[1]["hits"], 0);
+    assert!(body[1]["last_hit"].is_null());
+}
+
+#[tokio::test]
+async fn test_screening_rule_actions_in_middleware() {
+    use crate::{
+        context::security_context_middleware,
+        middleware::rate_limit_middleware,
+        rules::{RuleAction, RuleTarget, ScreeningRule},
+        screener::{RequestScreener, ScreeningConfig},
+        types::RateLimitDecision,
+    };
+    use axum::{
+        extract::Request,
+        http::StatusCode,
+        middleware::{from_fn, from_fn_with_state, Next},
+        routing::get,
+        Router,
+    };
+    use axum_test::TestServer;
+    use std::net::SocketAddr;
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    let config = RateLimitConfig::new(50, Duration::from_secs(60)).with_grace_period(0);
+    let screener = RequestScreener::new(
+        &ScreeningConfig::new()
+            .with_rule(
+                ScreeningRule::new("log-curl", "curl")
+                    .with_target(RuleTarget::UserAgent)
+                    .with_action(RuleAction::LogOnly),
+            )
+            .with_rule(
+                ScreeningRule::new("reject-bak", r"\.bak$")
+                    .with_target(RuleTarget::Path)
+                    .with_action(RuleAction::RejectOnly),
+            )
+            .with_rule(
+                ScreeningRule::new("tarpit-env", r"\.env$")
+                    .with_target(RuleTarget::Path)
+                    .with_action(RuleAction::Tarpit {
+                        delay: Duration::from_millis(200),
+                    }),
+            ),
+    )
+    .unwrap();
+    let limiter = RateLimiter::new(config, NoOpOnBlocked).with_screener(screener);
+
+    let decisions: Arc<Mutex<Vec<RateLimitDecision>>> = Arc::default();
+    let recorded = decisions.clone();
+    let app = Router::new()
+        .fallback(get(|| async { "OK" }))
+        .layer(from_fn_with_state(limiter.clone(), rate_limit_middleware))
+        .layer(from_fn(security_context_middleware))
+        .layer(from_fn(move |request: Request, next: Next| {
+            let recorded = recorded.clone();
+            async move {
+                let response = next.run(request).await;
+                if let Some(decision) = response.extensions().get::<RateLimitDecision>() {
+                    recorded.lock().unwrap().push(decision.clone());
+                }
+                response
+            }
+        }))
+        .layer(axum::extract::connect_info::MockConnectInfo(
+            SocketAddr::from(([127, 0, 0, 1], 8080)),
+        ));
+    let server = TestServer::new(app);
+
+    // Log only: the request goes through
+    server
+        .get("/")
+        .add_header("X-Forwarded-For", "192.168.5.1")
+        .add_header("User-Agent", "curl/8.0")
+        .await
+        .assert_status_ok();
+
+    // Reject only: 418 without blocking the key
+    server
+        .get("/db.bak")
+        .add_header("X-Forwarded-For", "192.168.5.2")
+        .expect_failure()
+        .await
+        .assert_status(StatusCode::IM_A_TEAPOT);
+    assert!(limiter.blocked_until("192.168.5.2").is_none());
+    server
+        .get("/")
+        .add_header("X-Forwarded-For", "192.168.5.2")
+        .await
+        .assert_status_ok();
+
+    // Tarpit: the rejection is delayed, and the key is not blocked
+    let started = Instant::now();
+    server
+        .get("/.env")
+        .add_header("X-Forwarded-For", "192.168.5.3")
+        .expect_failure()
+        .await
+        .assert_status(StatusCode::IM_A_TEAPOT);
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    assert!(limiter.blocked_until("192.168.5.3").is_none());
+
+    let decisions = decisions.lock().unwrap();
+    assert_eq!(decisions[0].outcome, crate::RateLimitOutcome::Allowed);
+    assert_eq!(decisions[0].screening_rules, vec!["log-curl"]);
+    assert_eq!(decisions[1].outcome, crate::RateLimitOutcome::Screened);
+    assert_eq!(decisions[1].screening_rules, vec!["reject-bak"]);
+    assert_eq!(decisions[3].screening_rules, vec!["tarpit-env"]);
+}
+
+#[tokio::test]
+async fn test_protocol_checks_in_middleware() {
+    use crate::{
+        context::security_context_middleware,
+        middleware::rate_limit_middleware,
+        protocol::ProtocolChecks,
+        screener::{RequestScreener, ScreeningConfig},
+    };
+    use axum::{
+        http::StatusCode,
+        middleware::{from_fn, from_fn_with_state},
+        routing::get,
+        Router,
+    };
+    use axum_test::TestServer;
+    use std::net::SocketAddr;
+
+    let config = RateLimitConfig::new(50, Duration::from_secs(60)).with_grace_period(0);
+    let screener = RequestScreener::new(
+        &ScreeningConfig::new().with_protocol_checks(
+            ProtocolChecks::new()
+                .with_allowed_host("localhost")
+                .with_allowed_method("GET"),
+        ),
+    )
+    .unwrap();
+    let limiter = RateLimiter::new(config, NoOpOnBlocked).with_screener(screener);
+    let app = Router::new()
+        .fallback(get(|| async { "OK" }).put(|| async { "OK" }))
+        .layer(from_fn_with_state(limiter.clone(), rate_limit_middleware))
+        .layer(from_fn(security_context_middleware))
+        .layer(axum::extract::connect_info::MockConnectInfo(
+            SocketAddr::from(([127, 0, 0, 1], 8080)),
+        ));
+    let server = TestServer::new(app);
+
+    server
+        .get("/")
+        .add_header("Host", "localhost:3000")
+        .add_header("X-Forwarded-For", "192.168.6.1")
+        .await
+        .assert_status_ok();
+
+    server
+        .put("/")
+        .add_header("Host", "localhost")
+        .add_header("X-Forwarded-For", "192.168.6.2")
+        .expect_failure()
+        .await
+        .assert_status(StatusCode::IM_A_TEAPOT);
+    // Rejected by default, not blocked
+    assert!(limiter.blocked_until("192.168.6.2").is_none());
+
+    server
+        .get("/")
+        .add_header("Host", "203.0.113.50")
+        .add_header("X-Forwarded-For", "192.168.6.3")
+        .expect_failure()
+        .await
+        .assert_status(StatusCode::IM_A_TEAPOT);
+}
+
+#[tokio::test]
+async fn test_honeypot_bans_with_longer_block() {
+    use crate::{
+        context::security_context_middleware,
+        honeypot::{Honeypot, HoneypotConfig, HoneypotResponse},
+        middleware::rate_limit_middleware,
+        types::BlockReason,
+    };
+    use axum::{
+        http::StatusCode,
+        middleware::{from_fn, from_fn_with_state},
+        routing::get,
+        Router,
+    };
+    use axum_test::TestServer;
+    use std::net::SocketAddr;
+
+    let events = RecordingOnBlocked::default();
+    let config = RateLimitConfig::new(50, Duration::from_secs(60)).with_grace_period(0);
+    let honeypot = HoneypotConfig::new()
+        .with_path("/admin.php")
+        .with_block_duration(Duration::from_secs(7 * 86_400))
+        .with_response(HoneypotResponse::FakeOk {
+            body: "<html>Welcome</html>".to_string(),
+        });
+    let limiter =
+        RateLimiter::new(config, events.clone()).with_honeypot(Honeypot::new(&honeypot).unwrap());
+    let mut subscriber = limiter.subscribe();
+    let app = Router::new()
+        .fallback(get(|| async { "OK" }))
+        .layer(from_fn_with_state(limiter.clone(), rate_limit_middleware))
+        .layer(from_fn(security_context_middleware))
+        .layer(axum::extract::connect_info::MockConnectInfo(
+            SocketAddr::from(([127, 0, 0, 1], 8080)),
+        ));
+    let server = TestServer::new(app);
+
+    server
+        .get("/index.html")
+        .add_header("X-Forwarded-For", "192.168.7.1")
+        .await
+        .assert_status_ok();
+    assert!(limiter.blocked_until("192.168.7.1").is_none());
+
+    let resp = server
+        .get("/static/..%2fadmin.php")
+        .add_header("X-Forwarded-For", "192.168.7.1")
+        .await;
+    resp.assert_status_ok();
+    resp.assert_text("<html>Welcome</html>");
+
+    // Banned for the honeypot duration, not the limiter's
+    let blocked_until = limiter.blocked_until("192.168.7.1").unwrap();
+    assert!(blocked_until > chrono::Utc::now() + chrono::Duration::days(6));
+
+    // The decoy keeps answering the same way; other paths are rejected
+    server
+        .get("/admin.php")
+        .add_header("X-Forwarded-For", "192.168.7.1")
+        .await
+        .assert_text("<html>Welcome</html>");
+    server
+        .get("/index.html")
+        .add_header("X-Forwarded-For", "192.168.7.1")
+        .expect_failure()
+        .await
+        .assert_status(StatusCode::TOO_MANY_REQUESTS);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let blocked = events.blocked.lock().unwrap();
+    assert_eq!(blocked.len(), 1, "repeat decoy requests are not reported");
+    assert_eq!(blocked[0].reason, BlockReason::Honeypot);
+    assert_eq!(blocked[0].path, "/static/..%2fadmin.php");
+    assert_eq!(blocked[0].blocked_until, blocked_until);
+
+    let mut honeypot_events = Vec::new();
+    while let Some(event) = subscriber.try_recv() {
+        if let crate::LimiterEvent::Honeypot { newly_blocked, .. } = event {
+            honeypot_events.push(newly_blocked);
+        }
+    }
+    assert_eq!(honeypot_events, vec![true, false]);
+}
+
+#[tokio::test]
+async fn test_honeypot_rejects_and_upgrades_existing_block() {
+    use crate::{
+        context::security_context_middleware,
+        honeypot::{Honeypot, HoneypotConfig},
+        middleware::rate_limit_middleware,
+        types::BlockReason,
+    };
+    use axum::{
+        http::StatusCode,
+        middleware::{from_fn, from_fn_with_state},
+        routing::get,
+        Router,
+    };
+    use axum_test::TestServer;
+    use std::net::SocketAddr;
+
+    let config = RateLimitConfig::new(50, Duration::from_secs(60)).with_grace_period(0);
+    let limiter = RateLimiter::new(config, NoOpOnBlocked).with_honeypot(
+        Honeypot::new(
+            &HoneypotConfig::new()
+                .with_path("/trap")
+                .with_block_duration(Duration::from_secs(3600)),
+        )
+        .unwrap(),
+    );
+    let app = Router::new()
+        .fallback(get(|| async { "OK" }))
+        .layer(from_fn_with_state(limiter.clone(), rate_limit_middleware))
+        .layer(from_fn(security_context_middleware))
+        .layer(axum::extract::connect_info::MockConnectInfo(
+            SocketAddr::from(([127, 0, 0, 1], 8080)),
+        ));
+    let server = TestServer::new(app);
+
+    limiter.block_immediately("192.168.7.2");
+    let manual_until = limiter.blocked_until("192.168.7.2").unwrap();
+
+    server
+        .get("/trap")
+        .add_header("X-Forwarded-For", "192.168.7.2")
+        .expect_failure()
+        .await
+        .assert_status(StatusCode::IM_A_TEAPOT);
+    assert!(limiter.blocked_until("192.168.7.2").unwrap() > manual_until);
+
+    let ctx = SecurityContext::new("192.168.7.2".to_string(), String::new());
+    assert!(!limiter.trip_honeypot("192.168.7.2", &ctx, "/trap"));
+    assert!(limiter.trip_honeypot("192.168.7.3", &ctx, "/trap"));
+    assert!(
+        !RateLimiter::new(RateLimitConfig::default(), NoOpOnBlocked).trip_honeypot(
+            "192.168.7.3",
+            &ctx,
+            "/trap"
+        )
+    );
+    assert_eq!(BlockReason::Honeypot.to_string(), "honeypot");
+}
+
+#[tokio::test]
+async fn test_honeypot_keeps_longer_block_and_reports_once() {
+    use crate::honeypot::{Honeypot, HoneypotConfig};
+
+    let events = RecordingOnBlocked::default();
+    let config = RateLimitConfig::new(50, Duration::from_secs(7200)).with_grace_period(0);
+    let limiter = RateLimiter::new(config, events.clone()).with_honeypot(
+        Honeypot::new(
+            &HoneypotConfig::new()
+                .with_path("/trap")
+                .with_block_duration(Duration::from_secs(60)),
+        )
+        .unwrap(),
+    );
+    let ctx = SecurityContext::new("192.168.7.4".to_string(), String::new());
+
+    // A shorter honeypot ban doesn't cut an existing block short
+    limiter.block_immediately("192.168.7.4");
+    let manual_until = limiter.blocked_until("192.168.7.4").unwrap();
+    assert!(limiter.trip_honeypot("192.168.7.4", &ctx, "/trap"));
+    assert_eq!(limiter.blocked_until("192.168.7.4"), Some(manual_until));
+
+    // Concurrent decoy hits from one key report a single new block
+    let tripped: usize = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..16)
+            .map(|_| scope.spawn(|| limiter.trip_honeypot("192.168.7.5", &ctx, "/trap")))
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap() as usize)
+            .sum()
+    });
+    assert_eq!(tripped, 1);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let blocked = events.blocked.lock().unwrap();
+    assert_eq!(
+        blocked
+            .iter()
+            .filter(|event| event.key == "192.168.7.5")
+            .count(),
+        1
+    );
+}
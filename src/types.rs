@@ -24,6 +24,14 @@ pub struct RateLimitEntry {
     pub last_refill: DateTime<Utc>,
     pub created_at: DateTime<Utc>,
     pub blocked_until: Option<DateTime<Utc>>,
+    /// Why the current (or most recent) block was applied
+    pub block_reason: Option<BlockReason>,
+    /// Number of times this key has been blocked while it stayed in the cache
+    pub strikes: u32,
+    /// The current block was applied in dry-run mode. It is shadow state: it
+    /// counts no strike, is never reported, and is dropped rather than
+    /// enforced once dry run is turned off
+    pub dry_run_block: bool,
 }
 
 impl RateLimitEntry {
@@ -34,10 +42,65 @@ impl RateLimitEntry {
             last_refill: now,
             created_at: now,
             blocked_until: None,
+            block_reason: None,
+            strikes: 0,
+            dry_run_block: false,
         }
     }
 }
 
+/// Why a key was blocked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockReason {
+    /// The token bucket ran out.
+    TokenExhaustion,
+    /// The request matched a screening rule.
+    Screening,
+    /// `RateLimiter::block_immediately` was called directly.
+    Manual,
+    /// The key requested a honeypot decoy path.
+    Honeypot,
+}
+
+impl std::fmt::Display for BlockReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockReason::TokenExhaustion => write!(f, "token exhaustion"),
+            BlockReason::Screening => write!(f, "screening"),
+            BlockReason::Manual => write!(f, "manual"),
+            BlockReason::Honeypot => write!(f, "honeypot"),
+        }
+    }
+}
+
+/// Passed to `OnBlocked::on_block_event` when a key is blocked.
+#[derive(Debug, Clone)]
+pub struct BlockEvent {
+    pub key: String,
+    pub path: String,
+    pub context: SecurityContext,
+    pub reason: BlockReason,
+    /// The screening pattern that matched, for `BlockReason::Screening`, or
+    /// the decoy path, for `BlockReason::Honeypot`
+    pub matched_pattern: Option<String>,
+    pub blocked_until: DateTime<Utc>,
+    /// Token balance at the time of the block
+    pub tokens: f64,
+    /// Number of times this key has been blocked, including this one
+    pub strikes: u32,
+}
+
+/// Passed to `OnBlocked::on_unblocked` and `OnBlocked::on_expired` when a block ends.
+#[derive(Debug, Clone)]
+pub struct UnblockEvent {
+    pub key: String,
+    /// Reason the block that ended was applied, if known
+    pub reason: Option<BlockReason>,
+    /// When the block was scheduled to end
+    pub blocked_until: DateTime<Utc>,
+    pub strikes: u32,
+}
+
 #[derive(Debug, Clone)]
 pub struct SecurityContext {
     pub ip_address: String,
@@ -53,9 +116,69 @@ impl SecurityContext {
     }
 }
 
+/// What `rate_limit_middleware` did with a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum RateLimitOutcome {
+    /// Passed through with no refund or penalty
+    Allowed,
+    /// Passed through and tokens were refunded (auth or `304 Not Modified`)
+    Refunded,
+    /// Passed through and extra tokens were charged for an error response
+    Penalized,
+    /// Rejected by the screener
+    Screened,
+    /// Requested a honeypot decoy path; the key was banned
+    Honeypot,
+    /// Rejected, and this request started the block
+    Blocked,
+    /// Rejected because the key was already blocked
+    RateLimited,
+}
+
+/// Inserted into response extensions by `rate_limit_middleware` so outer
+/// layers, such as an access log, can report the decision.
+#[derive(Debug, Clone)]
+pub struct RateLimitDecision {
+    pub context: SecurityContext,
+    /// Balance when the request was admitted, after it was charged
+    pub tokens_before: f64,
+    /// Balance once refunds and penalties were applied
+    pub tokens_after: f64,
+    pub outcome: RateLimitOutcome,
+    /// Description of the matched screening pattern, if any
+    pub screening_reason: Option<String>,
+    /// IDs of every matched screening rule
+    pub screening_rules: Vec<String>,
+    /// The outcome was only logged, not enforced
+    pub dry_run: bool,
+}
+
+/// Callbacks for block lifecycle events. They run in a background task, so a
+/// slow implementation (e.g. a database write) does not delay the request.
 #[async_trait::async_trait]
 pub trait OnBlocked: Send + Sync {
-    async fn on_blocked(&self, ip: &str, path: &str, context: &SecurityContext);
+    /// Called by the default `on_block_event` for every new block.
+    async fn on_blocked(&self, _ip: &str, _path: &str, _context: &SecurityContext) {}
+
+    /// Called when a key is blocked for any reason: token exhaustion,
+    /// screening, or a manual `block_immediately`. Override this to receive the
+    /// full event; the default forwards to `on_blocked`.
+    async fn on_block_event(&self, event: &BlockEvent) {
+        self.on_blocked(&event.context.ip_address, &event.path, &event.context)
+            .await;
+    }
+
+    /// Called when a block is lifted early with `RateLimiter::unblock`.
+    async fn on_unblocked(&self, _event: &UnblockEvent) {}
+
+    /// Called when a block runs out, either on the key's next request or
+    /// during `RateLimiter::cleanup_cache`.
+    async fn on_expired(&self, _event: &UnblockEvent) {}
 }
 
 #[async_trait::async_trait]
@@ -66,6 +189,41 @@ pub trait ActionChecker: Send + Sync {
         action: &str,
         within: std::time::Duration,
     ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>>;
+
+    /// Record that `ip` performed `action`. `action_limit_middleware` calls this
+    /// after a successful response. The default does nothing, for checkers that
+    /// read actions the application already stores.
+    async fn record_action(
+        &self,
+        _ip: &str,
+        _action: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        Ok(())
+    }
+
+    /// Reserve `action` for `ip` unless it was performed within `within`,
+    /// returning `false` if it was. `action_limit_middleware` calls this before
+    /// the handler runs, so checkers that reserve atomically (as
+    /// `InMemoryActionChecker` does) let only one of several concurrent
+    /// requests through. The default only calls `check_recent_action`.
+    async fn try_acquire(
+        &self,
+        ip: &str,
+        action: &str,
+        within: std::time::Duration,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(!self.check_recent_action(ip, action, within).await?)
+    }
+
+    /// Undo a `try_acquire` reservation when the handler did not succeed.
+    /// The default does nothing.
+    async fn release(
+        &self,
+        _ip: &str,
+        _action: &str,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        Ok(())
+    }
 }
 
 /// Injected into request extensions by `rate_limit_middleware` when
@@ -80,9 +238,7 @@ pub struct AuthRefundCallback(pub Arc<dyn Fn() + Send + Sync>);
 pub struct NoOpOnBlocked;
 
 #[async_trait::async_trait]
-impl OnBlocked for NoOpOnBlocked {
-    async fn on_blocked(&self, _ip: &str, _path: &str, _context: &SecurityContext) {}
-}
+impl OnBlocked for NoOpOnBlocked {}
 
 pub struct NoOpActionChecker;
 
@@ -0,0 +1,182 @@
+/*  This file is part of basic-axum-rate-limit
+ *  Copyright (C) 2025  Grant DeFayette
+ *
+ *  basic-axum-rate-limit is free software: you can redistribute it and/or modify
+ *  it under the terms of the GNU Lesser General Public License as published by
+ *  the Free Software Foundation, either version 3 of the License, or
+ *  (at your option) any later version.
+ *
+ *  basic-axum-rate-limit is distributed in the hope that it will be useful,
+ *  but WITHOUT ANY WARRANTY; without even the implied warranty of
+ *  MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+ *  GNU Lesser General Public License for more details.
+ *
+ *  You should have received a copy of the GNU Lesser General Public License
+ *  along with basic-axum-rate-limit.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Message-level rate limiting for axum WebSockets.
+//!
+//! `rate_limit_middleware` only charges for the HTTP upgrade request. Wrapping
+//! the upgraded socket in [`RateLimitedWebSocket`] charges the same per-key
+//! bucket for every inbound message and closes the connection with code 1008
+//! (policy violation) once the key is blocked.
+
+use crate::{
+    limiter::RateLimiter,
+    types::{OnBlocked, SecurityContext},
+};
+use axum::extract::ws::{close_code, CloseFrame, Message, WebSocket};
+use std::sync::Arc;
+
+type MessageCostFn = Arc<dyn Fn(&Message) -> f64 + Send + Sync>;
+
+/// A `WebSocket` that charges the rate limiter for each inbound message.
+///
+/// Every message except `Close` is charged, including pings, so a client
+/// cannot flood control frames for free. The default cost is one token per
+/// message.
+///
+/// # Example
+/// ```rust,ignore
+/// async fn ws_handler(
+///     ws: WebSocketUpgrade,
+///     State(limiter): State<RateLimiter<MyCallbacks>>,
+///     Extension(ctx): Extension<SecurityContext>,
+/// ) -> Response {
+///     ws.on_upgrade(move |socket| async move {
+///         let mut socket = RateLimitedWebSocket::new(socket, limiter, ctx, "/ws")
+///             .with_message_cost(0.25);
+///         while let Some(Ok(message)) = socket.recv().await {
+///             // handle message
+///         }
+///     })
+/// }
+/// ```
+pub struct RateLimitedWebSocket<B: OnBlocked> {
+    socket: WebSocket,
+    limiter: RateLimiter<B>,
+    context: SecurityContext,
+    path: String,
+    message_cost: MessageCostFn,
+    closed: bool,
+}
+
+impl<B: OnBlocked + 'static> RateLimitedWebSocket<B> {
+    /// Wrap an upgraded socket. The rate limit key is `context.ip_address`, the
+    /// same key `rate_limit_middleware` uses for the upgrade request.
+    pub fn new(
+        socket: WebSocket,
+        limiter: RateLimiter<B>,
+        context: SecurityContext,
+        path: impl Into<String>,
+    ) -> Self {
+        Self {
+            socket,
+            limiter,
+            context,
+            path: path.into(),
+            message_cost: Arc::new(|_| 1.0),
+            closed: false,
+        }
+    }
+
+    /// Charge a fixed number of tokens per inbound message.
+    pub fn with_message_cost(mut self, cost: f64) -> Self {
+        let cost = cost.max(0.0);
+        self.message_cost = Arc::new(move |_| cost);
+        self
+    }
+
+    /// Compute the token cost of each inbound message, e.g. by payload size.
+    pub fn with_message_cost_fn<F>(mut self, cost_fn: F) -> Self
+    where
+        F: Fn(&Message) -> f64 + Send + Sync + 'static,
+    {
+        self.message_cost = Arc::new(cost_fn);
+        self
+    }
+
+    /// Receive the next message, charging its cost to the rate limit bucket.
+    ///
+    /// Returns `None` when the connection is closed, including when this call
+    /// closed it because the key is blocked.
+    pub async fn recv(&mut self) -> Option<Result<Message, axum::Error>> {
+        if self.closed {
+            return None;
+        }
+
+        let message = match self.socket.recv().await? {
+            Ok(message) => message,
+            Err(e) => return Some(Err(e)),
+        };
+
+        if matches!(message, Message::Close(_)) {
+            self.closed = true;
+            return Some(Ok(message));
+        }
+
+        let cost = (self.message_cost)(&message).max(0.0);
+        let key = self.context.ip_address.clone();
+        let (is_allowed, newly_blocked, _tokens) = self
+            .limiter
+            .check_rate_limit_with_cost(&key, &self.context, &self.path, cost)
+            .await;
+
+        if is_allowed {
+            return Some(Ok(message));
+        }
+
+        if self.limiter.is_dry_run() {
+            tracing::warn!(
+                "Dry run: WebSocket would have been closed for message rate: {} (path: {})",
+                self.context.ip_address,
+                self.path
+            );
+            self.limiter.telemetry().record_dry_run(if newly_blocked {
+                "block"
+            } else {
+                "rate_limited"
+            });
+            return Some(Ok(message));
+        }
+
+        if newly_blocked {
+            tracing::warn!(
+                "IP blocked for WebSocket message rate: {} (path: {})",
+                self.context.ip_address,
+                self.path
+            );
+            self.limiter.telemetry().record_block(&key);
+        }
+
+        self.close_policy_violation().await;
+        None
+    }
+
+    /// Send a message to the client. Outbound messages are not charged.
+    pub async fn send(&mut self, message: Message) -> Result<(), axum::Error> {
+        self.socket.send(message).await
+    }
+
+    /// The security context of the client that opened this socket.
+    pub fn security_context(&self) -> &SecurityContext {
+        &self.context
+    }
+
+    /// Unwrap the socket, ending message-level rate limiting.
+    pub fn into_inner(self) -> WebSocket {
+        self.socket
+    }
+
+    async fn close_policy_violation(&mut self) {
+        self.closed = true;
+        let frame = CloseFrame {
+            code: close_code::POLICY,
+            reason: "rate limit exceeded".into(),
+        };
+        if let Err(e) = self.socket.send(Message::Close(Some(frame))).await {
+            tracing::debug!("Failed to send WebSocket close frame: {}", e);
+        }
+    }
+}
@@ -0,0 +1,85 @@
+#![cfg(feature = "websocket")]
+
+use axum::{
+    extract::{ws::WebSocketUpgrade, State},
+    response::Response,
+    routing::get,
+    Extension, Router,
+};
+use basic_axum_rate_limit::{
+    rate_limit_middleware, security_context_middleware, NoOpOnBlocked, RateLimitConfig,
+    RateLimitedWebSocket, RateLimiter, SecurityContext,
+};
+use futures_util::{SinkExt, StreamExt};
+use std::{net::SocketAddr, time::Duration};
+use tokio_tungstenite::tungstenite::{
+    client::IntoClientRequest, protocol::frame::coding::CloseCode, Message,
+};
+
+async fn echo_handler(
+    ws: WebSocketUpgrade,
+    State(limiter): State<RateLimiter<NoOpOnBlocked>>,
+    Extension(ctx): Extension<SecurityContext>,
+) -> Response {
+    ws.on_upgrade(move |socket| async move {
+        let mut socket =
+            RateLimitedWebSocket::new(socket, limiter, ctx, "/ws").with_message_cost(2.0);
+        while let Some(Ok(message)) = socket.recv().await {
+            if socket.send(message).await.is_err() {
+                break;
+            }
+        }
+    })
+}
+
+async fn spawn_ws_server(limiter: RateLimiter<NoOpOnBlocked>) -> SocketAddr {
+    let app = Router::new()
+        .route("/ws", get(echo_handler))
+        .with_state(limiter.clone())
+        .layer(axum::middleware::from_fn_with_state(
+            limiter,
+            rate_limit_middleware,
+        ))
+        .layer(axum::middleware::from_fn(security_context_middleware));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .unwrap();
+    });
+    addr
+}
+
+#[tokio::test]
+async fn test_websocket_messages_are_charged_and_closed_with_1008() {
+    // 10 tokens: the upgrade costs 1, each message costs 2, so four messages
+    // are echoed and the fifth closes the socket.
+    let config = RateLimitConfig::new(10, Duration::from_secs(60)).with_grace_period(0);
+    let addr = spawn_ws_server(RateLimiter::new(config, NoOpOnBlocked)).await;
+
+    let mut request = format!("ws://{}/ws", addr).into_client_request().unwrap();
+    request
+        .headers_mut()
+        .insert("X-Forwarded-For", "10.2.0.1".parse().unwrap());
+    let (mut stream, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+
+    for i in 1..=4 {
+        stream
+            .send(Message::text(format!("message {}", i)))
+            .await
+            .unwrap();
+        let echoed = stream.next().await.unwrap().unwrap();
+        assert_eq!(echoed, Message::text(format!("message {}", i)));
+    }
+
+    stream.send(Message::text("one too many")).await.unwrap();
+    match stream.next().await.unwrap().unwrap() {
+        Message::Close(Some(frame)) => assert_eq!(frame.code, CloseCode::Policy),
+        other => panic!("expected policy violation close frame, got {:?}", other),
+    }
+}